@@ -0,0 +1,473 @@
+//! The object behind an [`OleData`](crate::OleData).
+//!
+//! [`DispatchBackend`] mirrors the parts of `IDispatch` that `OleData` uses, with values as
+//! [`Variant`]s and failures as crate [`Error`]s, so a backend written in Rust needs no
+//! `VARIANT` and runs on any platform. [`NativeDispatch`] forwards to a real COM object; other
+//! implementations, such as [`MemoryObject`](crate::MemoryObject), serve the same calls from
+//! Rust.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    mem::ManuallyDrop,
+    rc::{Rc, Weak},
+    thread::{self, ThreadId},
+};
+
+use windows::{
+    core::{implement, IUnknown, Interface, BSTR, GUID, PCWSTR},
+    Win32::{
+        Foundation::{
+            DISP_E_EXCEPTION, DISP_E_PARAMNOTFOUND, DISP_E_TYPEMISMATCH, E_FAIL, E_NOTIMPL,
+            RPC_E_WRONG_THREAD,
+        },
+        Globalization::GetUserDefaultLCID,
+        System::{
+            Com::{
+                IDispatch, IDispatch_Impl, IServiceProvider, ITypeInfo, DISPATCH_FLAGS, DISPPARAMS,
+                EXCEPINFO,
+            },
+            Ole::{fdexNameCaseInsensitive, IDispatchEx, DISPID_UNKNOWN},
+            Variant::{VARIANT, VT_BYREF},
+        },
+    },
+};
+
+use crate::{
+    error::{ComArgumentErrorType, ComException, Error, Result},
+    retry,
+    util::{
+        conv::ToWide,
+        variant::{dispparams_args, write_byref},
+    },
+    value::{IntoVariant, Variant},
+};
+
+/// The operations `OleData` needs from an automation object
+///
+/// Failures are reported the way a COM server would: [`Error::Exception`] for
+/// `DISP_E_EXCEPTION`, [`Error::IDispatchArgument`] for a rejected argument and
+/// [`Error::HResult`] for any other code.
+pub trait DispatchBackend {
+    /// Map a member name, optionally followed by parameter names, to DISPIDs
+    ///
+    /// Names that are not found are reported as `DISPID_UNKNOWN` in the returned ids.
+    fn ids_of_names(&self, names: &[String]) -> (Result<()>, Vec<i32>);
+
+    /// Invoke a member, as `IDispatch::Invoke`
+    ///
+    /// `args` are in `DISPPARAMS::rgvarg` order: the named arguments first, in the order of
+    /// `named`, then the positional ones from last to first. A backend may overwrite an
+    /// argument to pass a value back by reference.
+    fn invoke(
+        &self,
+        dispid: i32,
+        flags: DISPATCH_FLAGS,
+        args: &mut [Variant],
+        named: &[i32],
+    ) -> Result<Variant>;
+
+    /// Type information describing the object, if the backend has any
+    ///
+    fn type_info(&self) -> Result<ITypeInfo> {
+        Err(Error::HResult(E_NOTIMPL))
+    }
+
+    /// The COM object behind this backend, if there is one
+    ///
+    /// `OleData` passes `VARIANT` arguments straight to it rather than through
+    /// [`invoke`](DispatchBackend::invoke), so nothing is lost converting them.
+    fn dispatch(&self) -> Option<IDispatch> {
+        None
+    }
+
+    /// The `IDispatchEx` of the COM object behind this backend, if it implements one
+    ///
+    fn dispatch_ex(&self) -> Option<IDispatchEx> {
+        None
+    }
+
+    /// A value that is equal for two backends exactly when they are the same object
+    ///
+    fn identity(&self) -> usize {
+        self as *const Self as *const u8 as usize
+    }
+}
+
+/// A backend forwarding to a COM object's `IDispatch`
+///
+/// Objects that also implement `IDispatchEx`, such as JScript objects, are looked up with
+/// `GetDispID` and invoked with `InvokeEx`.
+pub struct NativeDispatch {
+    dispatch: IDispatch,
+    dispatch_ex: Option<IDispatchEx>,
+}
+
+impl NativeDispatch {
+    pub fn new(dispatch: IDispatch) -> NativeDispatch {
+        let dispatch_ex = dispatch.cast().ok();
+        NativeDispatch {
+            dispatch,
+            dispatch_ex,
+        }
+    }
+}
+
+/// Invoke a member of a COM object with `VARIANT` arguments
+///
+/// Failures are decoded from `EXCEPINFO` and `puArgErr`, without the names of the member and
+/// argument, which only `OleData` can look up.
+pub(crate) fn invoke_native(
+    dispatch: &IDispatch,
+    dispatch_ex: Option<&IDispatchEx>,
+    dispid: i32,
+    flags: DISPATCH_FLAGS,
+    params: &mut DISPPARAMS,
+) -> Result<VARIANT> {
+    let mut result = VARIANT::default();
+    let mut excepinfo = EXCEPINFO::default();
+    // Left untouched unless the server reports an argument.
+    let mut arg_err = u32::MAX;
+    // InvokeEx has no puArgErr, so a rejected argument is reported without its index.
+    let outcome = match dispatch_ex {
+        Some(dispatch_ex) => unsafe {
+            dispatch_ex.InvokeEx(
+                dispid,
+                0x0800, /*LOCALE_SYSTEM_DEFAULT*/
+                flags.0,
+                params,
+                Some(&mut result),
+                Some(&mut excepinfo),
+                None::<&IServiceProvider>,
+            )
+        },
+        None => unsafe {
+            dispatch.Invoke(
+                dispid,
+                &GUID::zeroed(),
+                0x0800, /*LOCALE_SYSTEM_DEFAULT*/
+                flags,
+                params,
+                Some(&mut result),
+                Some(&mut excepinfo),
+                Some(&mut arg_err),
+            )
+        },
+    };
+    let Err(error) = outcome else {
+        return Ok(result);
+    };
+    Err(match error.code() {
+        DISP_E_EXCEPTION => {
+            let mut exception = ComException::from_excepinfo(&excepinfo);
+            exception.arg_err = (arg_err != u32::MAX).then_some(arg_err);
+            exception.into()
+        }
+        code @ (DISP_E_TYPEMISMATCH | DISP_E_PARAMNOTFOUND) => Error::IDispatchArgument {
+            error_type: if code == DISP_E_TYPEMISMATCH {
+                ComArgumentErrorType::TypeMismatch
+            } else {
+                ComArgumentErrorType::ParameterNotFound
+            },
+            arg_err: (arg_err != u32::MAX).then_some(arg_err),
+            member: None,
+            argument: None,
+        },
+        _ => retry::take_timeout(&error).unwrap_or_else(|| error.into()),
+    })
+}
+
+/// Invoke a backend with the arguments of `params`, as `IDispatch::Invoke` would
+///
+/// Arguments the backend changes are written back through the `VT_BYREF` ones.
+pub(crate) fn invoke_with_params(
+    backend: &dyn DispatchBackend,
+    dispid: i32,
+    flags: DISPATCH_FLAGS,
+    params: &DISPPARAMS,
+) -> Result<Variant> {
+    let (rgvarg, named) = dispparams_args(params);
+    let mut args = Vec::with_capacity(rgvarg.len());
+    for (index, arg) in rgvarg.iter().enumerate() {
+        match Variant::try_from(arg) {
+            Ok(value) => args.push(value),
+            Err(_) => {
+                return Err(Error::IDispatchArgument {
+                    error_type: ComArgumentErrorType::TypeMismatch,
+                    arg_err: Some(index as u32),
+                    member: None,
+                    argument: None,
+                })
+            }
+        }
+    }
+    let passed = args.clone();
+    let result = backend.invoke(dispid, flags, &mut args, named)?;
+    for ((target, before), after) in rgvarg.iter().zip(&passed).zip(args) {
+        if target.vt().0 & VT_BYREF.0 != 0 && after != *before {
+            write_byref(target, &after.into_variant())?;
+        }
+    }
+    Ok(result)
+}
+
+/// Report `error` the way `IDispatch::Invoke` does
+///
+/// Exceptions fill `excepinfo` and a rejected argument its index into `arg_err`. Other errors
+/// with an `HRESULT` are returned as is; the rest become exceptions raised by `source`, with
+/// `E_FAIL` as their code.
+pub(crate) fn report_error(
+    error: Error,
+    source: &str,
+    excepinfo: *mut EXCEPINFO,
+    arg_err: *mut u32,
+) -> windows::core::Error {
+    let exception = match error {
+        Error::Windows(error) => return error,
+        Error::HResult(hresult) => return hresult.into(),
+        Error::IDispatchArgument {
+            error_type,
+            arg_err: index,
+            ..
+        } => {
+            if let (Some(index), false) = (index, arg_err.is_null()) {
+                unsafe { arg_err.write(index) };
+            }
+            return match error_type {
+                ComArgumentErrorType::TypeMismatch => DISP_E_TYPEMISMATCH.into(),
+                ComArgumentErrorType::ParameterNotFound => DISP_E_PARAMNOTFOUND.into(),
+            };
+        }
+        Error::Exception(exception) => *exception,
+        error => ComException::new(
+            source,
+            &format!("{error:#}"),
+            error.hresult().unwrap_or(E_FAIL),
+        ),
+    };
+    if !excepinfo.is_null() {
+        unsafe { excepinfo.write(exception.to_excepinfo()) };
+    }
+    DISP_E_EXCEPTION.into()
+}
+
+impl DispatchBackend for NativeDispatch {
+    fn ids_of_names(&self, names: &[String]) -> (Result<()>, Vec<i32>) {
+        // GetDispID resolves a single member; parameter names still need GetIDsOfNames.
+        if let (Some(dispatch_ex), [name]) = (&self.dispatch_ex, names) {
+            return match unsafe {
+                dispatch_ex.GetDispID(&BSTR::from(name), fdexNameCaseInsensitive)
+            } {
+                Ok(dispid) => (Ok(()), vec![dispid]),
+                Err(error) => (Err(error.into()), vec![DISPID_UNKNOWN]),
+            };
+        }
+        let wide_names: Vec<Vec<u16>> = names.iter().map(|name| name.to_wide_null()).collect();
+        let wnames: Vec<PCWSTR> = wide_names
+            .iter()
+            .map(|name| PCWSTR(name.as_ptr()))
+            .collect();
+        let mut dispids = vec![DISPID_UNKNOWN; wnames.len()];
+
+        let result = unsafe {
+            self.dispatch.GetIDsOfNames(
+                &GUID::zeroed(),
+                wnames.as_ptr(),
+                wnames.len() as u32,
+                GetUserDefaultLCID(),
+                dispids.as_mut_ptr(),
+            )
+        };
+        (result.map_err(Error::from), dispids)
+    }
+
+    fn invoke(
+        &self,
+        dispid: i32,
+        flags: DISPATCH_FLAGS,
+        args: &mut [Variant],
+        named: &[i32],
+    ) -> Result<Variant> {
+        let mut rgvarg: Vec<VARIANT> = args.iter().cloned().map(Variant::into_variant).collect();
+        let mut named = named.to_vec();
+        let mut params = DISPPARAMS {
+            rgvarg: rgvarg.as_mut_ptr(),
+            rgdispidNamedArgs: named.as_mut_ptr(),
+            cArgs: rgvarg.len() as u32,
+            cNamedArgs: named.len() as u32,
+        };
+        let result = invoke_native(
+            &self.dispatch,
+            self.dispatch_ex.as_ref(),
+            dispid,
+            flags,
+            &mut params,
+        )?;
+        Variant::try_from(&result)
+    }
+
+    fn type_info(&self) -> Result<ITypeInfo> {
+        Ok(unsafe { self.dispatch.GetTypeInfo(0, GetUserDefaultLCID())? })
+    }
+
+    fn dispatch(&self) -> Option<IDispatch> {
+        Some(self.dispatch.clone())
+    }
+
+    fn dispatch_ex(&self) -> Option<IDispatchEx> {
+        self.dispatch_ex.clone()
+    }
+
+    /// The canonical `IUnknown` pointer, which COM guarantees to be stable for an object
+    fn identity(&self) -> usize {
+        match self.dispatch.cast::<IUnknown>() {
+            Ok(unknown) => unknown.as_raw() as usize,
+            Err(_) => self.dispatch.as_raw() as usize,
+        }
+    }
+}
+
+/// Exposes any backend as a COM `IDispatch`, so it can be passed to COM servers as an argument
+///
+/// A backend has at most one live wrapper per thread, so it keeps one COM identity however
+/// often it is passed, and a wrapper handed back by a server unwraps to the same backend.
+/// Like the backend it wraps, a wrapper belongs to the thread that created it, and calls from
+/// any other thread fail with `RPC_E_WRONG_THREAD`.
+#[implement(IDispatch)]
+pub(crate) struct BackendDispatch {
+    backend: Rc<dyn DispatchBackend>,
+    /// The thread that created the wrapper, the only one allowed to call it
+    thread: ThreadId,
+}
+
+/// The live wrappers of this thread. Entries are removed when the wrapper is dropped, so the
+/// raw pointers always refer to live objects.
+#[derive(Default)]
+struct Wrappers {
+    /// Raw `IDispatch` of the wrapper by backend address
+    by_backend: HashMap<usize, usize>,
+    /// Backend by raw `IDispatch` of its wrapper
+    by_dispatch: HashMap<usize, Weak<dyn DispatchBackend>>,
+}
+
+thread_local!(static WRAPPERS: RefCell<Wrappers> = RefCell::new(Wrappers::default()));
+
+fn backend_key(backend: &Rc<dyn DispatchBackend>) -> usize {
+    Rc::as_ptr(backend) as *const u8 as usize
+}
+
+impl BackendDispatch {
+    pub(crate) fn wrap(backend: Rc<dyn DispatchBackend>) -> IDispatch {
+        if let Some(dispatch) = backend.dispatch() {
+            return dispatch;
+        }
+        let key = backend_key(&backend);
+        if let Some(raw) = WRAPPERS.with(|wrappers| wrappers.borrow().by_backend.get(&key).copied())
+        {
+            let wrapper = ManuallyDrop::new(unsafe { IDispatch::from_raw(raw as *mut _) });
+            return (*wrapper).clone();
+        }
+        let weak = Rc::downgrade(&backend);
+        let dispatch: IDispatch = BackendDispatch {
+            backend,
+            thread: thread::current().id(),
+        }
+        .into();
+        let raw = dispatch.as_raw() as usize;
+        WRAPPERS.with(|wrappers| {
+            let mut wrappers = wrappers.borrow_mut();
+            wrappers.by_backend.insert(key, raw);
+            wrappers.by_dispatch.insert(raw, weak);
+        });
+        dispatch
+    }
+
+    /// Fail with `RPC_E_WRONG_THREAD` unless called on the thread that created the wrapper
+    ///
+    fn check_thread(&self) -> windows::core::Result<()> {
+        if thread::current().id() != self.thread {
+            return Err(RPC_E_WRONG_THREAD.into());
+        }
+        Ok(())
+    }
+
+    /// The backend of `dispatch` if it is a wrapper created on this thread
+    ///
+    pub(crate) fn unwrap(dispatch: &IDispatch) -> Option<Rc<dyn DispatchBackend>> {
+        let raw = dispatch.as_raw() as usize;
+        WRAPPERS.with(|wrappers| wrappers.borrow().by_dispatch.get(&raw)?.upgrade())
+    }
+}
+
+impl Drop for BackendDispatch {
+    fn drop(&mut self) {
+        let key = backend_key(&self.backend);
+        // The thread-local may already be gone when wrappers are released at thread exit.
+        let _ = WRAPPERS.try_with(|wrappers| {
+            let mut wrappers = wrappers.borrow_mut();
+            if let Some(raw) = wrappers.by_backend.remove(&key) {
+                wrappers.by_dispatch.remove(&raw);
+            }
+        });
+    }
+}
+
+impl IDispatch_Impl for BackendDispatch {
+    fn GetTypeInfoCount(&self) -> windows::core::Result<u32> {
+        self.check_thread()?;
+        Ok(self.backend.type_info().is_ok() as u32)
+    }
+
+    fn GetTypeInfo(&self, _itinfo: u32, _lcid: u32) -> windows::core::Result<ITypeInfo> {
+        self.check_thread()?;
+        Ok(self.backend.type_info()?)
+    }
+
+    fn GetIDsOfNames(
+        &self,
+        _riid: *const GUID,
+        rgsznames: *const PCWSTR,
+        cnames: u32,
+        _lcid: u32,
+        rgdispid: *mut i32,
+    ) -> windows::core::Result<()> {
+        self.check_thread()?;
+        let names: Vec<String> = unsafe { std::slice::from_raw_parts(rgsznames, cnames as usize) }
+            .iter()
+            .map(|name| unsafe { name.to_string() }.unwrap_or_default())
+            .collect();
+        let (result, ids) = self.backend.ids_of_names(&names);
+        let dispids = unsafe { std::slice::from_raw_parts_mut(rgdispid, cnames as usize) };
+        for (dispid, id) in dispids.iter_mut().zip(ids) {
+            *dispid = id;
+        }
+        Ok(result?)
+    }
+
+    fn Invoke(
+        &self,
+        dispidmember: i32,
+        _riid: *const GUID,
+        _lcid: u32,
+        wflags: DISPATCH_FLAGS,
+        pdispparams: *const DISPPARAMS,
+        pvarresult: *mut VARIANT,
+        pexcepinfo: *mut EXCEPINFO,
+        puargerr: *mut u32,
+    ) -> windows::core::Result<()> {
+        self.check_thread()?;
+        let params = match unsafe { pdispparams.as_ref() } {
+            Some(params) => params,
+            None => &DISPPARAMS::default(),
+        };
+        match invoke_with_params(self.backend.as_ref(), dispidmember, wflags, params) {
+            Ok(value) => {
+                if !pvarresult.is_null() {
+                    unsafe { pvarresult.write(value.into_variant()) };
+                }
+                Ok(())
+            }
+            Err(error) => Err(report_error(error, "", pexcepinfo, puargerr)),
+        }
+    }
+}
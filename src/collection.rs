@@ -0,0 +1,269 @@
+//! Rust sequences served as COM collections.
+//!
+//! VBA's `For Each` and JScript's `Enumerator` expect a collection object: `Count`, a default
+//! `Item` member and `_NewEnum` returning an `IEnumVARIANT`. [`Collection`] provides all three
+//! for a vector, an iterator or anything implementing [`CollectionItems`]:
+//!
+//! ```ignore
+//! let names = Collection::new(["Alice", "Bob"]);
+//! sheet.call("ShowNames", vec![names.into_variant()])?;
+//! ```
+
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+    thread::{self, ThreadId},
+};
+
+use windows::{
+    core::{implement, IUnknown, HRESULT},
+    Win32::{
+        Foundation::{DISP_E_BADINDEX, E_POINTER, RPC_E_WRONG_THREAD, S_FALSE, S_OK},
+        System::{
+            Ole::{IEnumVARIANT, IEnumVARIANT_Impl, DISPID_NEWENUM, DISPID_VALUE},
+            Variant::{VT_I4, VT_UNKNOWN, VT_VARIANT},
+        },
+    },
+};
+
+use crate::{
+    error::Result,
+    server::{Access, Arguments, DispatchObject, MemberInfo, MemberKind, ParamInfo, VARIANT},
+    value::IntoVariant,
+};
+
+/// The items of a [`Collection`], addressed by a zero-based index
+pub trait CollectionItems {
+    fn count(&self) -> usize;
+    /// The item at `index`, which is less than [`count`](CollectionItems::count)
+    ///
+    fn item(&self, index: usize) -> VARIANT;
+}
+
+impl<T: Clone + IntoVariant> CollectionItems for Vec<T> {
+    fn count(&self) -> usize {
+        self.len()
+    }
+    fn item(&self, index: usize) -> VARIANT {
+        self[index].clone().into_variant()
+    }
+}
+
+struct Indexed<F> {
+    count: usize,
+    item: F,
+}
+
+impl<F, T> CollectionItems for Indexed<F>
+where
+    F: Fn(usize) -> T,
+    T: IntoVariant,
+{
+    fn count(&self) -> usize {
+        self.count
+    }
+    fn item(&self, index: usize) -> VARIANT {
+        (self.item)(index).into_variant()
+    }
+}
+
+/// A read-only COM collection with `Count`, `Item` and `_NewEnum`
+///
+/// `Item` takes a one-based index by default, like VBA collections.
+#[derive(Clone)]
+pub struct Collection {
+    items: Rc<dyn CollectionItems>,
+    base: i32,
+}
+
+impl Collection {
+    /// A collection of the items of `items`, converted up front
+    ///
+    pub fn new<I>(items: I) -> Collection
+    where
+        I: IntoIterator,
+        I::Item: IntoVariant,
+    {
+        let items: Vec<VARIANT> = items.into_iter().map(IntoVariant::into_variant).collect();
+        Collection::from_items(items)
+    }
+    /// A collection of `count` items, each produced by `item` when it is requested
+    ///
+    pub fn indexed<F, T>(count: usize, item: F) -> Collection
+    where
+        F: Fn(usize) -> T + 'static,
+        T: IntoVariant + 'static,
+    {
+        Collection::from_items(Indexed { count, item })
+    }
+    pub fn from_items<C: CollectionItems + 'static>(items: C) -> Collection {
+        Collection {
+            items: Rc::new(items),
+            base: 1,
+        }
+    }
+    /// Index of the first item as seen by `Item`, 1 unless changed
+    ///
+    pub fn base(mut self, base: i32) -> Self {
+        self.base = base;
+        self
+    }
+    pub fn count(&self) -> usize {
+        self.items.count()
+    }
+    /// A new enumerator over the items, as returned by `_NewEnum`
+    ///
+    pub fn enumerate(&self) -> IEnumVARIANT {
+        VariantEnum {
+            items: self.items.clone(),
+            position: Cell::new(0),
+            thread: thread::current().id(),
+        }
+        .into()
+    }
+    fn item(&self, args: &Arguments) -> Result<VARIANT> {
+        let index: i32 = args.arg(0)?;
+        let index = index
+            .checked_sub(self.base)
+            .and_then(|index| usize::try_from(index).ok())
+            .filter(|index| *index < self.items.count());
+        match index {
+            Some(index) => Ok(self.items.item(index)),
+            None => Err(DISP_E_BADINDEX.into()),
+        }
+    }
+}
+
+const DISPID_COUNT: i32 = 1;
+
+static COLLECTION_MEMBERS: [MemberInfo; 3] = [
+    MemberInfo {
+        name: "Item",
+        dispid: DISPID_VALUE,
+        kind: MemberKind::Method,
+        get: false,
+        put: false,
+        params: &[ParamInfo {
+            name: "Index",
+            vt: VT_VARIANT.0,
+        }],
+        vt: VT_VARIANT.0,
+    },
+    MemberInfo {
+        name: "Count",
+        dispid: DISPID_COUNT,
+        kind: MemberKind::Property,
+        get: true,
+        put: false,
+        params: &[],
+        vt: VT_I4.0,
+    },
+    MemberInfo {
+        name: "_NewEnum",
+        dispid: DISPID_NEWENUM,
+        kind: MemberKind::Property,
+        get: true,
+        put: false,
+        params: &[],
+        vt: VT_UNKNOWN.0,
+    },
+];
+
+impl DispatchObject for Collection {
+    const TYPE_NAME: &'static str = "Collection";
+
+    fn members() -> Vec<&'static MemberInfo> {
+        COLLECTION_MEMBERS.iter().collect()
+    }
+
+    fn invoke_member(
+        this: &RefCell<Self>,
+        dispid: i32,
+        access: Access,
+        args: &Arguments,
+    ) -> Option<Result<VARIANT>> {
+        if access == Access::Put {
+            return None;
+        }
+        let this = this.borrow();
+        match dispid {
+            DISPID_VALUE => Some(this.item(args)),
+            DISPID_COUNT => Some(Ok((this.count() as i32).into_variant())),
+            DISPID_NEWENUM => Some(Ok(IUnknown::from(this.enumerate()).into_variant())),
+            _ => None,
+        }
+    }
+}
+
+impl IntoVariant for Collection {
+    fn into_variant(self) -> VARIANT {
+        self.into_dispatch().into_variant()
+    }
+}
+
+#[implement(IEnumVARIANT)]
+struct VariantEnum {
+    items: Rc<dyn CollectionItems>,
+    position: Cell<usize>,
+    thread: ThreadId,
+}
+
+impl IEnumVARIANT_Impl for VariantEnum {
+    fn Next(&self, celt: u32, rgvar: *mut VARIANT, pceltfetched: *mut u32) -> HRESULT {
+        if thread::current().id() != self.thread {
+            return RPC_E_WRONG_THREAD;
+        }
+        if rgvar.is_null() && celt > 0 {
+            return E_POINTER;
+        }
+        let mut fetched = 0;
+        while fetched < celt && self.position.get() < self.items.count() {
+            let item = self.items.item(self.position.get());
+            unsafe { rgvar.add(fetched as usize).write(item) };
+            self.position.set(self.position.get() + 1);
+            fetched += 1;
+        }
+        if !pceltfetched.is_null() {
+            unsafe { pceltfetched.write(fetched) };
+        }
+        if fetched == celt {
+            S_OK
+        } else {
+            S_FALSE
+        }
+    }
+
+    fn Skip(&self, celt: u32) -> HRESULT {
+        if thread::current().id() != self.thread {
+            return RPC_E_WRONG_THREAD;
+        }
+        let count = self.items.count();
+        let target = self.position.get().saturating_add(celt as usize);
+        self.position.set(target.min(count));
+        if target <= count {
+            S_OK
+        } else {
+            S_FALSE
+        }
+    }
+
+    fn Reset(&self) -> windows::core::Result<()> {
+        if thread::current().id() != self.thread {
+            return Err(RPC_E_WRONG_THREAD.into());
+        }
+        self.position.set(0);
+        Ok(())
+    }
+
+    fn Clone(&self) -> windows::core::Result<IEnumVARIANT> {
+        if thread::current().id() != self.thread {
+            return Err(RPC_E_WRONG_THREAD.into());
+        }
+        Ok(VariantEnum {
+            items: self.items.clone(),
+            position: Cell::new(self.position.get()),
+            thread: self.thread,
+        }
+        .into())
+    }
+}
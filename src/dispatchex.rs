@@ -0,0 +1,99 @@
+use windows::{
+    core::BSTR,
+    Win32::System::{
+        Com::{DISPATCH_FLAGS, DISPPARAMS},
+        Ole::{
+            fdexEnumAll, fdexNameCaseInsensitive, fdexNameEnsure, IDispatchEx, DISPID_UNKNOWN,
+            DISPID_VALUE,
+        },
+        Variant::VARIANT,
+    },
+};
+
+use crate::{
+    error::{Error, Result},
+    OleData,
+};
+
+const DISPATCH_CONSTRUCT: DISPATCH_FLAGS = DISPATCH_FLAGS(0x4000);
+const GRFDEX_PROP_ALL: u32 = 0x3FFF;
+
+/// A member reported by `IDispatchEx::GetNextDispID`
+#[derive(Clone, Debug)]
+pub struct DynamicMember {
+    pub dispid: i32,
+    pub name: String,
+    /// The `fdexProp*` flags from `IDispatchEx::GetMemberProperties`
+    pub properties: u32,
+}
+
+impl OleData {
+    /// Whether the object implements `IDispatchEx`, in which case member lookup and
+    /// invocation go through `GetDispID` and `InvokeEx`
+    ///
+    pub fn is_dispatch_ex(&self) -> bool {
+        self.backend.dispatch_ex().is_some()
+    }
+    fn require_dispatch_ex(&self) -> Result<IDispatchEx> {
+        self.backend
+            .dispatch_ex()
+            .ok_or(Error::Generic("object does not implement IDispatchEx"))
+    }
+    /// Look up a member with `IDispatchEx::GetDispID`, passing the given `fdex*` flags
+    ///
+    pub fn get_dispid_ex(&self, name: &str, flags: u32) -> Result<i32> {
+        let dispatch_ex = self.require_dispatch_ex()?;
+        let dispid = unsafe { dispatch_ex.GetDispID(&BSTR::from(name), flags)? };
+        Ok(dispid)
+    }
+    /// Look up a member, creating it if it does not exist yet (`fdexNameEnsure`)
+    ///
+    pub fn ensure_member(&self, name: &str) -> Result<i32> {
+        let dispid = self.get_dispid_ex(name, fdexNameEnsure | fdexNameCaseInsensitive)?;
+        self.dispids.insert(&[name], &[dispid]);
+        Ok(dispid)
+    }
+    /// Remove a member with `IDispatchEx::DeleteMemberByName`
+    ///
+    pub fn delete_member(&self, name: &str) -> Result<()> {
+        let dispatch_ex = self.require_dispatch_ex()?;
+        unsafe { dispatch_ex.DeleteMemberByName(&BSTR::from(name), fdexNameCaseInsensitive)? };
+        self.forget_dispid(name);
+        Ok(())
+    }
+    /// Use the object as a constructor, as in JScript's `new Foo(args)`
+    ///
+    pub fn construct(&self, args: Vec<VARIANT>) -> Result<VARIANT> {
+        self.require_dispatch_ex()?;
+        let mut rgvarg: Vec<VARIANT> = args.into_iter().rev().collect();
+        let mut dp = DISPPARAMS {
+            rgvarg: rgvarg.as_mut_ptr(),
+            cArgs: rgvarg.len() as u32,
+            ..Default::default()
+        };
+        self.invoke_dispid(DISPID_VALUE, &mut dp, DISPATCH_CONSTRUCT)
+    }
+    /// Enumerate the object's current members
+    ///
+    pub fn dynamic_members(&self) -> Result<Vec<DynamicMember>> {
+        let dispatch_ex = self.require_dispatch_ex()?;
+        let mut members = vec![];
+        let mut dispid = DISPID_UNKNOWN;
+        loop {
+            dispid = match unsafe { dispatch_ex.GetNextDispID(fdexEnumAll, dispid) } {
+                Ok(next) if next != DISPID_UNKNOWN => next,
+                _ => break,
+            };
+            let name = unsafe { dispatch_ex.GetMemberName(dispid)? };
+            let properties = unsafe { dispatch_ex.GetMemberProperties(dispid, GRFDEX_PROP_ALL) }
+                .map(|properties| properties.0)
+                .unwrap_or(0);
+            members.push(DynamicMember {
+                dispid,
+                name: name.to_string(),
+                properties,
+            });
+        }
+        Ok(members)
+    }
+}
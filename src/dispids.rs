@@ -0,0 +1,71 @@
+use std::{cell::RefCell, collections::HashMap, ffi::OsStr, rc::Rc};
+
+use windows::Win32::System::Com::DISPATCH_FLAGS;
+
+thread_local!(static TYPE_DISPID_CACHES: RefCell<HashMap<u128, DispIdCache>> = RefCell::new(HashMap::new()));
+
+/// Memoized results of `GetIDsOfNames`, keyed case-insensitively on the full list of names
+/// that was resolved (member name first, then any parameter names).
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DispIdCache {
+    entries: Rc<RefCell<HashMap<String, Vec<i32>>>>,
+    /// Member names as they were spelled when resolved, for error messages
+    names: Rc<RefCell<HashMap<i32, String>>>,
+    /// How object values are assigned to each property, from the type info
+    put_flags: Rc<RefCell<HashMap<i32, DISPATCH_FLAGS>>>,
+}
+
+impl DispIdCache {
+    /// The cache shared by every object of the type identified by `type_guid` on this thread.
+    pub(crate) fn for_type(type_guid: u128) -> DispIdCache {
+        TYPE_DISPID_CACHES.with(|caches| caches.borrow_mut().entry(type_guid).or_default().clone())
+    }
+    pub(crate) fn get<S: AsRef<OsStr>>(&self, names: &[S]) -> Option<Vec<i32>> {
+        self.entries.borrow().get(&cache_key(names)).cloned()
+    }
+    pub(crate) fn insert<S: AsRef<OsStr>>(&self, names: &[S], ids: &[i32]) {
+        if let (Some(name), Some(id)) = (names.first(), ids.first()) {
+            self.names
+                .borrow_mut()
+                .insert(*id, name.as_ref().to_string_lossy().into_owned());
+        }
+        self.entries
+            .borrow_mut()
+            .insert(cache_key(names), ids.to_vec());
+    }
+    /// Forgets every entry whose member name is `name`, including those resolved together
+    /// with parameter names.
+    pub(crate) fn remove<S: AsRef<OsStr>>(&self, name: S) {
+        let member = cache_key(&[name]);
+        let prefix = format!("{member}\u{0}");
+        self.entries
+            .borrow_mut()
+            .retain(|key, _| *key != member && !key.starts_with(&prefix));
+        self.names
+            .borrow_mut()
+            .retain(|_, name| cache_key(&[name.as_str()]) != member);
+    }
+    pub(crate) fn clear(&self) {
+        self.entries.borrow_mut().clear();
+        self.names.borrow_mut().clear();
+        self.put_flags.borrow_mut().clear();
+    }
+    pub(crate) fn put_flags(&self, dispid: i32) -> Option<DISPATCH_FLAGS> {
+        self.put_flags.borrow().get(&dispid).copied()
+    }
+    pub(crate) fn insert_put_flags(&self, dispid: i32, flags: DISPATCH_FLAGS) {
+        self.put_flags.borrow_mut().insert(dispid, flags);
+    }
+    /// The name a member was last resolved under
+    pub(crate) fn name_of(&self, dispid: i32) -> Option<String> {
+        self.names.borrow().get(&dispid).cloned()
+    }
+}
+
+fn cache_key<S: AsRef<OsStr>>(names: &[S]) -> String {
+    names
+        .iter()
+        .map(|name| name.as_ref().to_string_lossy().to_lowercase())
+        .collect::<Vec<_>>()
+        .join("\u{0}")
+}
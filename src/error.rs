@@ -4,19 +4,33 @@ use std::{
     num::{ParseFloatError, TryFromIntError},
     str::Utf8Error,
     string::FromUtf16Error,
+    time::Duration,
 };
 
 use windows::{
-    core::HRESULT,
-    Win32::{Foundation::WIN32_ERROR, System::Com::EXCEPINFO},
+    core::{BSTR, HRESULT},
+    Win32::{
+        Foundation::{
+            DISP_E_PARAMNOTFOUND, DISP_E_TYPEMISMATCH, ERROR_FILE_NOT_FOUND, E_FAIL, WIN32_ERROR,
+        },
+        System::Com::EXCEPINFO,
+    },
 };
 
+use crate::hresult;
+
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Errors from this crate
+///
+/// `Context` and `Expression` errors display only their own message and expose the wrapped
+/// error through `source()`. The alternate form `{:#}` includes the whole chain.
 #[derive(Debug)]
 pub enum Error {
     Io(io::Error),
     Windows(windows::core::Error),
+    /// A failure reported as a bare `HRESULT`, without the text a `Windows` error carries
+    HResult(HRESULT),
     Utf8(Utf8Error),
     Utf16(FromUtf16Error),
     ParseFloat(ParseFloatError),
@@ -24,11 +38,50 @@ pub enum Error {
     IntoString(IntoStringError),
     Generic(&'static str),
     Custom(String),
+    /// A registry key or value that does not exist, named by the text
+    NotFound(String),
     Ole(OleError),
-    Exception(EXCEPINFO),
+    Exception(Box<ComException>),
     IDispatchArgument {
         error_type: ComArgumentErrorType,
-        arg_err: u32,
+        /// The index into `DISPPARAMS::rgvarg` reported through `puArgErr`, if any
+        arg_err: Option<u32>,
+        /// The member being invoked, when its name could be resolved
+        member: Option<String>,
+        /// The argument at `arg_err`, by name when type information describes it and as `#n`
+        /// otherwise; `None` when the server did not report which argument it rejected
+        argument: Option<String>,
+    },
+    UnknownArgumentNames {
+        member: String,
+        names: Vec<String>,
+    },
+    ArgumentCount {
+        member: String,
+        min: usize,
+        max: Option<usize>,
+        given: usize,
+    },
+    InvalidArgument {
+        member: String,
+        param: String,
+        reason: String,
+    },
+    Expression {
+        expression: String,
+        position: usize,
+        message: String,
+        source: Option<Box<Error>>,
+    },
+    Context {
+        context: String,
+        source: Box<Error>,
+    },
+    /// The server kept rejecting a call until the retry policy gave up
+    Timeout {
+        hresult: HRESULT,
+        attempts: u32,
+        elapsed: Duration,
     },
 }
 
@@ -78,11 +131,10 @@ impl fmt::Display for OleError {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             fmt,
-            "{}: {}. HRESULT error code: {} ({})",
+            "{}: {}. {}",
             self.error_type,
             self.context_message,
-            self.hresult,
-            self.hresult.message()
+            hresult::describe(self.hresult)
         )
     }
 }
@@ -113,9 +165,234 @@ impl fmt::Display for ComArgumentErrorType {
     }
 }
 
+/// An exception raised by an automation server, decoded from `EXCEPINFO`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ComException {
+    /// The object or application that raised the exception
+    pub source: String,
+    pub description: String,
+    pub help_file: String,
+    pub help_context: u32,
+    /// The server's own error number, zero when it reported an `HRESULT` instead
+    pub code: u16,
+    /// `scode`, or `code` mapped to an `HRESULT` in `FACILITY_ITF`
+    pub hresult: HRESULT,
+    /// The member being invoked, when its name could be resolved
+    pub member: Option<String>,
+    pub dispid: Option<i32>,
+    /// The index into `DISPPARAMS::rgvarg` reported through `puArgErr`, if any
+    pub arg_err: Option<u32>,
+    /// The argument the server reported through `puArgErr`, by name when type information
+    /// describes it and as `#n` otherwise
+    pub argument: Option<String>,
+}
+
+/// The `HRESULT` of an exception with error number `code` and status `scode`, of which only
+/// one is set
+pub(crate) fn exception_hresult(code: u16, scode: i32) -> HRESULT {
+    if scode == 0 && code != 0 {
+        // MAKE_HRESULT(SEVERITY_ERROR, FACILITY_ITF, wCode)
+        HRESULT((0x8004_0000u32 | code as u32) as i32)
+    } else {
+        HRESULT(scode)
+    }
+}
+
+impl ComException {
+    /// An exception raised by `source` with an `HRESULT` rather than an error number
+    ///
+    pub fn new(source: &str, description: &str, hresult: HRESULT) -> ComException {
+        ComException {
+            source: source.to_string(),
+            description: description.to_string(),
+            help_file: String::new(),
+            help_context: 0,
+            code: 0,
+            hresult,
+            member: None,
+            dispid: None,
+            arg_err: None,
+            argument: None,
+        }
+    }
+    pub fn from_excepinfo(excepinfo: &EXCEPINFO) -> ComException {
+        let mut excepinfo = excepinfo.clone();
+        if let Some(func) = excepinfo.pfnDeferredFillIn {
+            let _ = unsafe { func(&mut excepinfo) };
+        }
+        let hresult = exception_hresult(excepinfo.wCode, excepinfo.scode);
+        ComException {
+            source: excepinfo.bstrSource.to_string(),
+            description: excepinfo.bstrDescription.to_string(),
+            help_file: excepinfo.bstrHelpFile.to_string(),
+            help_context: excepinfo.dwHelpContext,
+            code: excepinfo.wCode,
+            hresult,
+            member: None,
+            dispid: None,
+            arg_err: None,
+            argument: None,
+        }
+    }
+    pub fn with_member(mut self, member: Option<String>, dispid: i32) -> ComException {
+        self.member = member;
+        self.dispid = Some(dispid);
+        self
+    }
+    pub fn with_argument(mut self, argument: Option<String>) -> ComException {
+        self.argument = argument;
+        self
+    }
+    /// The exception as an `EXCEPINFO`, for reporting it from an `IDispatch` implementation
+    ///
+    pub fn to_excepinfo(&self) -> EXCEPINFO {
+        EXCEPINFO {
+            wCode: self.code,
+            // Exactly one of wCode and scode is set.
+            scode: if self.code != 0 { 0 } else { self.hresult.0 },
+            bstrSource: BSTR::from(self.source.as_str()),
+            bstrDescription: BSTR::from(self.description.as_str()),
+            bstrHelpFile: BSTR::from(self.help_file.as_str()),
+            dwHelpContext: self.help_context,
+            ..Default::default()
+        }
+    }
+}
+
+impl fmt::Display for ComException {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = if self.source.is_empty() {
+            "<Unknown>"
+        } else {
+            &self.source
+        };
+        if self.code != 0 {
+            write!(fmt, "{source} error {} ({})", self.code, self.hresult)?;
+        } else {
+            write!(fmt, "{source} error {}", self.hresult)?;
+            if let Some(name) = hresult::name(self.hresult) {
+                write!(fmt, " {name}")?;
+            }
+        }
+        if let Some(ref member) = self.member {
+            write!(fmt, " in `{member}`")?;
+        }
+        if let Some(ref argument) = self.argument {
+            write!(fmt, " for argument `{argument}`")?;
+        }
+        if self.description.is_empty() {
+            write!(fmt, ": <No Description>")
+        } else {
+            write!(fmt, ": {}", self.description.trim_end())
+        }
+    }
+}
+
+impl std::error::Error for ComException {}
+
+impl From<ComException> for Error {
+    fn from(exception: ComException) -> Error {
+        Error::Exception(Box::new(exception))
+    }
+}
+
+impl Error {
+    /// Wrap the error with a description of what was being attempted
+    ///
+    pub fn context<C: Into<String>>(self, context: C) -> Error {
+        Error::Context {
+            context: context.into(),
+            source: Box::new(self),
+        }
+    }
+    /// The innermost error, following `Context` and `Expression` sources
+    ///
+    pub fn root_cause(&self) -> &Error {
+        match self {
+            Error::Context { source, .. }
+            | Error::Expression {
+                source: Some(source),
+                ..
+            } => source.root_cause(),
+            error => error,
+        }
+    }
+    /// The text attached to a `Windows` error
+    ///
+    /// This is what the server set through `IErrorInfo`, which is often the only useful
+    /// detail, or else the system message for the code in the language of the installed OS.
+    /// `Display` leaves it out so that messages read the same on every machine.
+    pub fn message(&self) -> Option<String> {
+        match self.root_cause() {
+            Error::Windows(error) => {
+                let message = error.message().to_string();
+                let message = message.trim_end();
+                (!message.is_empty()).then(|| message.to_string())
+            }
+            _ => None,
+        }
+    }
+    /// The `HRESULT` this error was reported with, if it has one
+    ///
+    /// For an exception this is the code the server raised rather than `DISP_E_EXCEPTION`.
+    pub fn hresult(&self) -> Option<HRESULT> {
+        match self.root_cause() {
+            Error::Windows(error) => Some(error.code()),
+            Error::HResult(hresult) | Error::Timeout { hresult, .. } => Some(*hresult),
+            Error::NotFound(_) => Some(HRESULT::from_win32(ERROR_FILE_NOT_FOUND.0)),
+            Error::Ole(error) => Some(error.hresult),
+            Error::Exception(exception) => Some(exception.hresult),
+            Error::IDispatchArgument { error_type, .. } => Some(match error_type {
+                ComArgumentErrorType::TypeMismatch => DISP_E_TYPEMISMATCH,
+                ComArgumentErrorType::ParameterNotFound => DISP_E_PARAMNOTFOUND,
+            }),
+            _ => None,
+        }
+    }
+    /// The exception raised by the server, if this error comes from one
+    ///
+    pub fn exception(&self) -> Option<&ComException> {
+        match self.root_cause() {
+            Error::Exception(exception) => Some(exception.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// Adds context to the error of a `Result`
+pub trait ResultExt<T> {
+    fn context<C: Into<String>>(self, context: C) -> Result<T>;
+    fn with_context<C: Into<String>, F: FnOnce() -> C>(self, context: F) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> ResultExt<T> for std::result::Result<T, E> {
+    fn context<C: Into<String>>(self, context: C) -> Result<T> {
+        self.map_err(|error| error.into().context(context))
+    }
+    fn with_context<C: Into<String>, F: FnOnce() -> C>(self, context: F) -> Result<T> {
+        self.map_err(|error| error.into().context(context()))
+    }
+}
+
 impl std::error::Error for Error {
+    /// Wrapped library errors are displayed in place, so their own source comes next
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        Some(self)
+        use Error::*;
+        match self {
+            Io(ref err) => err.source(),
+            Windows(ref err) => err.source(),
+            Utf8(ref err) => err.source(),
+            Utf16(ref err) => err.source(),
+            ParseFloat(ref err) => err.source(),
+            FromInt(ref err) => err.source(),
+            IntoString(ref err) => err.source(),
+            Expression {
+                source: Some(ref source),
+                ..
+            } => Some(source.as_ref()),
+            Context { ref source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
     }
 }
 
@@ -131,6 +408,22 @@ impl From<windows::core::Error> for Error {
     }
 }
 
+impl From<HRESULT> for Error {
+    fn from(hresult: HRESULT) -> Error {
+        Error::HResult(hresult)
+    }
+}
+
+/// Errors without an `HRESULT` of their own become `E_FAIL`
+impl From<Error> for windows::core::Error {
+    fn from(error: Error) -> windows::core::Error {
+        match error {
+            Error::Windows(error) => error,
+            error => error.hresult().unwrap_or(E_FAIL).into(),
+        }
+    }
+}
+
 impl From<Utf8Error> for Error {
     fn from(err: Utf8Error) -> Error {
         Error::Utf8(err)
@@ -163,7 +456,7 @@ impl From<IntoStringError> for Error {
 
 impl From<WIN32_ERROR> for Error {
     fn from(err: WIN32_ERROR) -> Self {
-        Error::Windows(HRESULT::from_win32(err.0).into())
+        Error::HResult(HRESULT::from_win32(err.0))
     }
 }
 
@@ -172,7 +465,8 @@ impl fmt::Display for Error {
         use Error::*;
         match self {
             Io(ref err) => err.fmt(fmt),
-            Windows(ref err) => err.fmt(fmt),
+            Windows(ref err) => write!(fmt, "{}", hresult::describe(err.code())),
+            HResult(hresult) => write!(fmt, "{}", hresult::describe(*hresult)),
             Utf8(ref err) => err.fmt(fmt),
             Utf16(ref err) => err.fmt(fmt),
             ParseFloat(ref err) => err.fmt(fmt),
@@ -180,57 +474,93 @@ impl fmt::Display for Error {
             IntoString(ref err) => err.fmt(fmt),
             Generic(ref err) => err.fmt(fmt),
             Custom(ref err) => err.fmt(fmt),
+            NotFound(ref what) => write!(fmt, "{what} not found"),
             Ole(ref err) => err.fmt(fmt),
-            Exception(excepinfo) => writeln!(fmt, "{}", ole_excepinfo2msg(excepinfo)),
+            Exception(ref exception) => exception.fmt(fmt),
             IDispatchArgument {
                 error_type,
                 arg_err,
-            } => writeln!(
+                member,
+                argument,
+            } => {
+                match (argument, arg_err) {
+                    (Some(argument), _) => write!(
+                        fmt,
+                        "COM argument error {error_type} for argument `{argument}`"
+                    )?,
+                    (None, Some(arg_err)) => write!(
+                        fmt,
+                        "COM argument error {error_type} for argument {arg_err}"
+                    )?,
+                    (None, None) => write!(fmt, "COM argument error {error_type}")?,
+                }
+                match member {
+                    Some(member) => writeln!(fmt, " in `{member}`"),
+                    None => writeln!(fmt),
+                }
+            }
+            UnknownArgumentNames { member, names } => write!(
                 fmt,
-                "COM argument error {error_type} for argument {arg_err}"
+                "`{member}` has no parameters named {}",
+                names
+                    .iter()
+                    .map(|name| format!("`{name}`"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            ArgumentCount {
+                member,
+                min,
+                max,
+                given,
+            } => match max {
+                Some(max) if max == min => write!(
+                    fmt,
+                    "`{member}` takes {min} argument(s) but {given} were supplied"
+                ),
+                Some(max) => write!(
+                    fmt,
+                    "`{member}` takes {min} to {max} arguments but {given} were supplied"
+                ),
+                None => write!(
+                    fmt,
+                    "`{member}` takes at least {min} argument(s) but {given} were supplied"
+                ),
+            },
+            InvalidArgument {
+                member,
+                param,
+                reason,
+            } => write!(fmt, "invalid argument `{param}` for `{member}`: {reason}"),
+            Expression {
+                expression,
+                position,
+                message,
+                source,
+            } => {
+                write!(
+                    fmt,
+                    "{message} at column {} of `{expression}`",
+                    position + 1
+                )?;
+                match source {
+                    Some(source) if fmt.alternate() => write!(fmt, ": {source:#}"),
+                    _ => Ok(()),
+                }
+            }
+            Context { context, source } if fmt.alternate() => {
+                write!(fmt, "{context}: {source:#}")
+            }
+            Context { context, .. } => write!(fmt, "{context}"),
+            Timeout {
+                hresult,
+                attempts,
+                elapsed,
+            } => write!(
+                fmt,
+                "the server was still busy after {attempts} attempt(s) over {elapsed:?} ({})",
+                hresult::describe(*hresult)
             ),
         }
     }
 }
-
-fn ole_excepinfo2msg(excepinfo: &EXCEPINFO) -> String {
-    let mut excepinfo = excepinfo.clone();
-    if let Some(func) = excepinfo.pfnDeferredFillIn {
-        let _ = unsafe { func(&mut excepinfo) };
-    }
-
-    let s = &excepinfo.bstrSource;
-    let source = if !s.is_empty() {
-        s.to_string()
-    } else {
-        String::new()
-    };
-    let d = &excepinfo.bstrDescription;
-    let description = if !d.is_empty() {
-        d.to_string()
-    } else {
-        String::new()
-    };
-    let mut msg = if excepinfo.wCode == 0 {
-        format!("\n    OLE error code: {} in ", excepinfo.scode)
-    } else {
-        format!("\n    OLE error code: {} in ", excepinfo.wCode)
-    };
-
-    if !source.is_empty() {
-        msg.push_str(&source);
-    } else {
-        msg.push_str("<Unknown>");
-    }
-    msg.push_str("\n      ");
-    if !description.is_empty() {
-        msg.push_str(&description);
-    } else {
-        msg.push_str("<No Description>");
-    }
-
-    let _ = excepinfo.bstrSource;
-    let _ = excepinfo.bstrDescription;
-
-    msg
-}
@@ -0,0 +1,505 @@
+//! Event signatures read from the type information of a source interface.
+//!
+//! An [`EventInterface`] maps the DISPIDs an event source fires to event names, parameter
+//! names and parameter types, so that incoming `DISPPARAMS` can be decoded into an
+//! [`Event`]. [`EventInterface::handler_trait`] turns the same information into Rust source
+//! for a trait with one method per event:
+//!
+//! ```ignore
+//! let workbook_type = OleTypeData::new("Microsoft Excel 16.0 Object Library", "Workbook")?;
+//! let source = workbook_type.default_event_sources()?.remove(0);
+//! let code = EventInterface::new(&source)?.handler_trait("WorkbookEventsHandler");
+//! std::fs::write("src/workbook_events.rs", code)?;
+//! ```
+
+use std::{collections::BTreeMap, fmt::Write};
+
+use windows::{
+    core::GUID,
+    Win32::System::{
+        Com::{FUNCFLAG_FRESTRICTED, INVOKE_FUNC, TYPEDESC},
+        Variant::{
+            VariantChangeType, VARENUM, VARIANT, VAR_CHANGE_FLAGS, VT_BOOL, VT_BSTR, VT_BYREF,
+            VT_I1, VT_I2, VT_I4, VT_I8, VT_INT, VT_PTR, VT_R4, VT_R8, VT_UI1, VT_UI2, VT_UI4,
+            VT_UINT, VT_USERDEFINED,
+        },
+    },
+};
+
+use crate::{
+    error::Result, olemethoddata::ole_methods_from_typeinfo, util::ole::TypeRef, value::Variant,
+    OleData, OleTypeData,
+};
+
+/// A parameter of an event
+#[derive(Clone, Debug)]
+pub struct EventParam {
+    pub name: String,
+    /// The type as the type library spells it, such as `BOOL` or `Workbook`
+    pub ole_type: String,
+    /// The variant type of the value, without `VT_BYREF`
+    pub vt: VARENUM,
+    /// Whether the source passes the argument by reference, so that a handler can assign it
+    pub byref: bool,
+}
+
+/// An event of a source interface
+#[derive(Clone, Debug)]
+pub struct EventSignature {
+    pub name: String,
+    pub dispid: i32,
+    pub params: Vec<EventParam>,
+}
+
+impl EventSignature {
+    pub(crate) fn unknown(dispid: i32) -> EventSignature {
+        EventSignature {
+            name: format!("#{dispid}"),
+            dispid,
+            params: vec![],
+        }
+    }
+    /// Position of the parameter `name`, ignoring case
+    ///
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.params
+            .iter()
+            .position(|param| param.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// An event decoded with the signature of its source interface
+///
+/// Like [`Variant`], the type of the objects among the arguments is a parameter, so that
+/// events received through an [`OleProxy`](crate::OleProxy) carry proxies.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event<O = OleData> {
+    pub name: String,
+    pub dispid: i32,
+    /// Parameter names and values, in the order the event declares them
+    pub args: Vec<(String, Variant<O>)>,
+}
+
+impl<O> Event<O> {
+    /// The argument for the parameter `name`, ignoring case
+    ///
+    pub fn get(&self, name: &str) -> Option<&Variant<O>> {
+        self.args
+            .iter()
+            .find(|(param, _)| param.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+    /// Convert the objects among the arguments, see [`Variant::try_map_objects`]
+    ///
+    pub fn try_map_objects<P, E>(
+        self,
+        f: &mut impl FnMut(O) -> std::result::Result<P, E>,
+    ) -> std::result::Result<Event<P>, E> {
+        let args = self
+            .args
+            .into_iter()
+            .map(|(name, value)| Ok((name, value.try_map_objects(f)?)))
+            .collect::<std::result::Result<_, E>>()?;
+        Ok(Event {
+            name: self.name,
+            dispid: self.dispid,
+            args,
+        })
+    }
+}
+
+/// The events of a source interface, keyed by DISPID
+#[derive(Clone, Debug)]
+pub struct EventInterface {
+    name: String,
+    iid: GUID,
+    events: BTreeMap<i32, EventSignature>,
+}
+
+impl EventInterface {
+    /// Read the events of `source`, as returned by [`OleTypeData::default_event_sources`] or
+    /// [`OleTypeData::source_ole_types`]
+    ///
+    pub fn new(source: &OleTypeData) -> Result<EventInterface> {
+        let mut events = BTreeMap::new();
+        for method in ole_methods_from_typeinfo(source.typeinfo().clone(), INVOKE_FUNC.0)? {
+            // Skips the IUnknown and IDispatch methods a dispinterface inherits.
+            if method.desc().wFuncFlags.0 & FUNCFLAG_FRESTRICTED.0 != 0 {
+                continue;
+            }
+            let mut params = vec![];
+            for param in method.params() {
+                let param = param?;
+                let (vt, byref) = param_type(&param.elem_desc().tdesc);
+                params.push(EventParam {
+                    name: param.name().to_string(),
+                    ole_type: param.ole_type()?,
+                    vt,
+                    byref,
+                });
+            }
+            events.insert(
+                method.dispid(),
+                EventSignature {
+                    name: method.name().to_string(),
+                    dispid: method.dispid(),
+                    params,
+                },
+            );
+        }
+        Ok(EventInterface {
+            name: source.name().to_string(),
+            iid: source.guid(),
+            events,
+        })
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn iid(&self) -> GUID {
+        self.iid
+    }
+    /// The events, ordered by DISPID
+    ///
+    pub fn events(&self) -> impl Iterator<Item = &EventSignature> {
+        self.events.values()
+    }
+    pub fn get(&self, dispid: i32) -> Option<&EventSignature> {
+        self.events.get(&dispid)
+    }
+    /// The event named `name`, ignoring case
+    ///
+    pub fn find(&self, name: &str) -> Option<&EventSignature> {
+        self.events
+            .values()
+            .find(|event| event.name.eq_ignore_ascii_case(name))
+    }
+    /// Rust source for a trait named `trait_name` with one method per event
+    ///
+    /// Every method does nothing unless overridden. The provided `dispatch_event` method
+    /// routes [`EventArgs`](crate::EventArgs) to them and writes `ByRef` parameters back, so
+    /// a handler is connected with
+    /// `events.on_any_event(move |args| handler.dispatch_event(args))`.
+    pub fn handler_trait(&self, trait_name: &str) -> String {
+        let mut code = String::new();
+        let _ = writeln!(code, "/// Handlers for the events of `{}`", self.name);
+        let _ = writeln!(code, "///");
+        let _ = writeln!(code, "/// Generated from the type library by win32ole.");
+        let _ = writeln!(code, "pub trait {trait_name} {{");
+        let methods = unique_idents(
+            self.events.values().map(|event| event.name.as_str()),
+            &["dispatch_event"],
+        );
+        for (event, method) in self.events.values().zip(&methods) {
+            let params = rust_params(event);
+            let signature: Vec<_> = event
+                .params
+                .iter()
+                .map(|param| format!("{}: {}", param.name, param.ole_type))
+                .collect();
+            let _ = writeln!(code, "    /// `{}({})`", event.name, signature.join(", "));
+            let _ = write!(code, "    fn {method}(&mut self");
+            for (name, ty) in &params {
+                let _ = write!(code, ", {name}: {ty}");
+            }
+            let _ = writeln!(code, ") -> ::win32ole::error::Result<()> {{");
+            if !params.is_empty() {
+                let names: Vec<_> = params.iter().map(|(name, _)| name.as_str()).collect();
+                let names = match names.as_slice() {
+                    [name] => name.to_string(),
+                    _ => format!("({})", names.join(", ")),
+                };
+                let _ = writeln!(code, "        let _ = {names};");
+            }
+            let _ = writeln!(code, "        Ok(())");
+            let _ = writeln!(code, "    }}");
+        }
+        let _ = writeln!(code, "    /// Call the method for the event in `args`");
+        let _ = writeln!(code, "    fn dispatch_event(");
+        let _ = writeln!(code, "        &mut self,");
+        let _ = writeln!(code, "        args: &mut ::win32ole::EventArgs,");
+        let _ = writeln!(code, "    ) -> ::win32ole::error::Result<()> {{");
+        let _ = writeln!(code, "        match args.dispid() {{");
+        for (event, method) in self.events.values().zip(&methods) {
+            let _ = writeln!(code, "            {} => {{", event.dispid);
+            // Arguments are bound by position, so that no parameter name can shadow `args`.
+            for (index, param) in event.params.iter().enumerate() {
+                let mutability = if param.byref { "mut " } else { "" };
+                let _ = writeln!(
+                    code,
+                    "                let {mutability}arg{index} = args.arg::<{}>({index})?;",
+                    rust_type(param.vt)
+                );
+            }
+            let call_args: Vec<_> = event
+                .params
+                .iter()
+                .enumerate()
+                .map(|(index, param)| {
+                    if param.byref {
+                        format!("&mut arg{index}")
+                    } else {
+                        format!("arg{index}")
+                    }
+                })
+                .collect();
+            let call = format!("self.{method}({})", call_args.join(", "));
+            if !event.params.iter().any(|param| param.byref) {
+                let _ = writeln!(code, "                {call}");
+                let _ = writeln!(code, "            }}");
+                continue;
+            }
+            let _ = writeln!(code, "                let result = {call};");
+            for (index, param) in event.params.iter().enumerate() {
+                if param.byref {
+                    let _ = writeln!(code, "                if args.raw({index}).is_some() {{");
+                    let _ = writeln!(code, "                    args.set({index}, arg{index})?;");
+                    let _ = writeln!(code, "                }}");
+                }
+            }
+            let _ = writeln!(code, "                result");
+            let _ = writeln!(code, "            }}");
+        }
+        let _ = writeln!(code, "            _ => Ok(()),");
+        let _ = writeln!(code, "        }}");
+        let _ = writeln!(code, "    }}");
+        let _ = writeln!(code, "}}");
+        code
+    }
+}
+
+/// The variant type of a parameter and whether it is passed by reference
+///
+/// Interfaces and user defined types are declared through a pointer without being `ByRef`.
+fn param_type(tdesc: &TYPEDESC) -> (VARENUM, bool) {
+    if tdesc.vt != VT_PTR {
+        return (tdesc.vt, false);
+    }
+    let inner = unsafe { &*tdesc.Anonymous.lptdesc };
+    if inner.vt == VT_USERDEFINED {
+        return (inner.vt, false);
+    }
+    (inner.vt, true)
+}
+
+/// Convert `value` to the declared type of `param`, so that handlers see `Bool(true)` rather
+/// than whatever integer the source passed
+///
+pub(crate) fn decode_argument(value: &VARIANT, param: Option<&EventParam>) -> Variant {
+    let declared = param.map(|param| param.vt).filter(|vt| is_coercible(*vt));
+    if let Some(vt) = declared {
+        if VARENUM(value.vt().0 & !VT_BYREF.0) != vt {
+            let mut converted = VARIANT::default();
+            if unsafe { VariantChangeType(&mut converted, value, VAR_CHANGE_FLAGS(0), vt) }.is_ok()
+            {
+                if let Ok(converted) = Variant::try_from(&converted) {
+                    return converted;
+                }
+            }
+        }
+    }
+    Variant::try_from(value).unwrap_or(Variant::Empty)
+}
+
+fn is_coercible(vt: VARENUM) -> bool {
+    matches!(
+        vt,
+        VT_BOOL
+            | VT_I1
+            | VT_I2
+            | VT_I4
+            | VT_I8
+            | VT_INT
+            | VT_UI1
+            | VT_UI2
+            | VT_UI4
+            | VT_UINT
+            | VT_R4
+            | VT_R8
+            | VT_BSTR
+    )
+}
+
+/// The Rust type a generated handler receives for `vt`
+///
+/// Only types that convert both ways are used, so that `ByRef` parameters can be written
+/// back; narrower integers and floats are widened.
+fn rust_type(vt: VARENUM) -> &'static str {
+    match vt {
+        VT_BOOL => "bool",
+        VT_I1 | VT_I2 | VT_I4 | VT_INT | VT_UI1 | VT_UI2 => "i32",
+        VT_I8 | VT_UI4 | VT_UINT => "i64",
+        VT_R4 | VT_R8 => "f64",
+        VT_BSTR => "String",
+        _ => "::win32ole::Variant",
+    }
+}
+
+/// Names and types of the parameters of a generated handler method
+fn rust_params(event: &EventSignature) -> Vec<(String, String)> {
+    let names = unique_idents(event.params.iter().map(|param| param.name.as_str()), &[]);
+    event
+        .params
+        .iter()
+        .zip(names)
+        .map(|(param, name)| {
+            let ty = rust_type(param.vt);
+            let ty = if param.byref {
+                format!("&mut {ty}")
+            } else {
+                ty.to_string()
+            };
+            (name, ty)
+        })
+        .collect()
+}
+
+/// [`rust_ident`] of each name, with a numeric suffix on those that would repeat an earlier
+/// one or a name in `reserved`
+fn unique_idents<'a>(names: impl Iterator<Item = &'a str>, reserved: &[&str]) -> Vec<String> {
+    let mut used: Vec<String> = reserved.iter().map(|name| name.to_string()).collect();
+    let mut idents = vec![];
+    for name in names {
+        let base = rust_ident(name);
+        let mut ident = base.clone();
+        let mut suffix = 2;
+        while used.contains(&ident) {
+            ident = format!("{}_{suffix}", base.trim_start_matches("r#"));
+            suffix += 1;
+        }
+        used.push(ident.clone());
+        idents.push(ident);
+    }
+    idents
+}
+
+/// `WorkbookBeforeClose` becomes `workbook_before_close`, `Type` becomes `r#type`
+///
+fn rust_ident(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut ident = String::new();
+    for (index, ch) in chars.iter().enumerate() {
+        if !ch.is_ascii_alphanumeric() {
+            if !ident.is_empty() && !ident.ends_with('_') {
+                ident.push('_');
+            }
+            continue;
+        }
+        if ch.is_ascii_uppercase() && index > 0 {
+            let prev = chars[index - 1];
+            let next_lower = chars.get(index + 1).is_some_and(|c| c.is_ascii_lowercase());
+            let boundary = prev.is_ascii_lowercase()
+                || ((prev.is_ascii_uppercase() || prev.is_ascii_digit()) && next_lower);
+            if boundary && !ident.ends_with('_') {
+                ident.push('_');
+            }
+        }
+        ident.push(ch.to_ascii_lowercase());
+    }
+    let ident = ident.trim_end_matches('_').to_string();
+    if ident.is_empty() {
+        return "arg".to_string();
+    }
+    if ident.starts_with(|c: char| c.is_ascii_digit()) {
+        return format!("_{ident}");
+    }
+    match ident.as_str() {
+        "self" | "super" | "crate" => format!("{ident}_"),
+        "as" | "async" | "await" | "break" | "const" | "continue" | "dyn" | "else" | "enum"
+        | "extern" | "false" | "fn" | "for" | "if" | "impl" | "in" | "let" | "loop" | "match"
+        | "mod" | "move" | "mut" | "pub" | "ref" | "return" | "static" | "struct" | "trait"
+        | "true" | "type" | "unsafe" | "use" | "where" | "while" | "yield" | "box" | "try" => {
+            format!("r#{ident}")
+        }
+        _ => ident,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param(name: &str, vt: VARENUM, byref: bool) -> EventParam {
+        EventParam {
+            name: name.to_string(),
+            ole_type: String::new(),
+            vt,
+            byref,
+        }
+    }
+
+    fn event(dispid: i32, name: &str, params: Vec<EventParam>) -> (i32, EventSignature) {
+        let event = EventSignature {
+            name: name.to_string(),
+            dispid,
+            params,
+        };
+        (dispid, event)
+    }
+
+    #[test]
+    fn idents() {
+        assert_eq!(rust_ident("WorkbookBeforeClose"), "workbook_before_close");
+        assert_eq!(rust_ident("HTMLDocument"), "html_document");
+        assert_eq!(rust_ident("onreadystatechange"), "onreadystatechange");
+        assert_eq!(rust_ident("Type"), "r#type");
+        assert_eq!(rust_ident("Self"), "self_");
+        assert_eq!(rust_ident("2ndValue"), "_2nd_value");
+        assert_eq!(rust_ident("Sheet-Name"), "sheet_name");
+        assert_eq!(rust_ident("?"), "arg");
+
+        let names = ["Value", "value", "VALUE", "Type", "type", "Check"];
+        assert_eq!(
+            unique_idents(names.into_iter(), &["check"]),
+            ["value", "value_2", "value_3", "r#type", "type_2", "check_2"]
+        );
+    }
+
+    #[test]
+    fn handler_trait() {
+        let interface = EventInterface {
+            name: "DocEvents".to_string(),
+            iid: GUID::zeroed(),
+            events: BTreeMap::from([
+                event(
+                    1,
+                    "BeforeClose",
+                    vec![
+                        param("Cancel", VT_BOOL, true),
+                        param("args", VT_I4, false),
+                        param("Result", VT_BSTR, false),
+                        param("result", VT_I2, false),
+                    ],
+                ),
+                event(2, "Click", vec![]),
+                event(3, "click", vec![]),
+                event(4, "DispatchEvent", vec![]),
+            ]),
+        };
+        let code = interface.handler_trait("DocEventsHandler");
+        assert!(code.contains("pub trait DocEventsHandler {"));
+        assert!(code.contains(
+            "    fn before_close(&mut self, cancel: &mut bool, args: i32, result: String, \
+             result_2: i32) -> ::win32ole::error::Result<()> {\n        \
+             let _ = (cancel, args, result, result_2);"
+        ));
+        assert!(code.contains(
+            "            1 => {\n                \
+             let mut arg0 = args.arg::<bool>(0)?;\n                \
+             let arg1 = args.arg::<i32>(1)?;\n                \
+             let arg2 = args.arg::<String>(2)?;\n                \
+             let arg3 = args.arg::<i32>(3)?;\n                \
+             let result = self.before_close(&mut arg0, arg1, arg2, arg3);\n                \
+             if args.raw(0).is_some() {\n                    \
+             args.set(0, arg0)?;\n                \
+             }\n                \
+             result\n            \
+             }\n"
+        ));
+        assert!(code.contains("    fn click(&mut self)"));
+        assert!(code.contains("    fn click_2(&mut self)"));
+        assert!(code.contains("    fn dispatch_event_2(&mut self)"));
+        assert!(code.contains("            3 => {\n                self.click_2()\n"));
+        assert!(code.contains("            4 => {\n                self.dispatch_event_2()\n"));
+    }
+}
@@ -0,0 +1,526 @@
+//! Names and descriptions of `HRESULT`s without `FormatMessage`.
+//!
+//! The built-in catalog covers the codes automation clients usually see: `E_*`, `DISP_E_*`,
+//! `TYPE_E_*`, `RPC_E_*`, `CO_E_*`, `REGDB_E_*`, `CLASS_E_*`, `CONNECT_E_*` and common Win32
+//! errors wrapped with `HRESULT_FROM_WIN32`. Applications add their own codes with
+//! [`register`]:
+//!
+//! ```
+//! # use win32ole::hresult::{self, HresultEntry};
+//! # use windows::core::HRESULT;
+//! static EXCEL: &[HresultEntry] = &[HresultEntry::new(
+//!     0x800A03EC,
+//!     "XL_E_1004",
+//!     "Application-defined or object-defined error.",
+//! )];
+//! hresult::register(EXCEL);
+//! assert_eq!(hresult::describe(HRESULT(0x800A03ECu32 as i32)),
+//!     "0x800A03EC XL_E_1004: Application-defined or object-defined error.");
+//! ```
+
+use std::{fmt::Write, sync::RwLock};
+
+use windows::core::HRESULT;
+
+/// A named `HRESULT`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HresultEntry {
+    pub hresult: u32,
+    pub name: &'static str,
+    pub description: &'static str,
+}
+
+impl HresultEntry {
+    pub const fn new(hresult: u32, name: &'static str, description: &'static str) -> Self {
+        HresultEntry {
+            hresult,
+            name,
+            description,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Success,
+    Error,
+}
+
+pub const FACILITY_ITF: u16 = 4;
+pub const FACILITY_WIN32: u16 = 7;
+pub const FACILITY_CONTROL: u16 = 10;
+
+static REGISTERED: RwLock<Vec<&'static [HresultEntry]>> = RwLock::new(Vec::new());
+
+/// Add application-specific codes to the catalog
+///
+/// Tables registered later take precedence, and all registered tables take precedence over
+/// the built-in catalog.
+pub fn register(table: &'static [HresultEntry]) {
+    REGISTERED
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .push(table);
+}
+
+pub fn severity(hresult: HRESULT) -> Severity {
+    if hresult.0 < 0 {
+        Severity::Error
+    } else {
+        Severity::Success
+    }
+}
+
+pub fn facility(hresult: HRESULT) -> u16 {
+    ((hresult.0 as u32 >> 16) & 0x1FFF) as u16
+}
+
+/// The low 16 bits, which hold the Win32 error of `FACILITY_WIN32` codes
+///
+pub fn code(hresult: HRESULT) -> u16 {
+    (hresult.0 as u32 & 0xFFFF) as u16
+}
+
+pub fn facility_name(facility: u16) -> Option<&'static str> {
+    FACILITIES
+        .iter()
+        .find(|(value, _)| *value == facility)
+        .map(|(_, name)| *name)
+}
+
+/// Look `hresult` up in the registered tables, then in the built-in catalog
+///
+/// `FACILITY_WIN32` codes that have no entry of their own are named after the wrapped
+/// Win32 error, such as `ERROR_FILE_NOT_FOUND`.
+pub fn lookup(hresult: HRESULT) -> Option<HresultEntry> {
+    find(hresult).map(|(entry, _)| entry)
+}
+
+/// The entry for `hresult`, and whether it describes a wrapped Win32 error
+fn find(hresult: HRESULT) -> Option<(HresultEntry, bool)> {
+    let value = hresult.0 as u32;
+    let registered = REGISTERED
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .iter()
+        .rev()
+        .find_map(|table| table.iter().find(|entry| entry.hresult == value).copied());
+    if let Some(entry) =
+        registered.or_else(|| CATALOG.iter().find(|entry| entry.hresult == value).copied())
+    {
+        return Some((entry, false));
+    }
+    if facility(hresult) != FACILITY_WIN32 || severity(hresult) != Severity::Error {
+        return None;
+    }
+    WIN32_ERRORS
+        .iter()
+        .find(|(error, ..)| *error == code(hresult) as u32)
+        .map(|(_, name, description)| (HresultEntry::new(value, name, description), true))
+}
+
+/// The symbolic name of `hresult`, if the catalog knows it
+///
+pub fn name(hresult: HRESULT) -> Option<&'static str> {
+    lookup(hresult).map(|entry| entry.name)
+}
+
+/// A message that is the same on every platform
+///
+/// Known codes read `0x80020009 DISP_E_EXCEPTION: Exception occurred.`; others are decoded,
+/// as in `0x800A03EC (error, FACILITY_CONTROL, code 1004)`.
+pub fn describe(hresult: HRESULT) -> String {
+    let mut message = format!("{:#010X}", hresult.0 as u32);
+    match find(hresult) {
+        Some((entry, true)) => {
+            let _ = write!(
+                message,
+                " HRESULT_FROM_WIN32({}): {}",
+                entry.name, entry.description
+            );
+        }
+        Some((entry, false)) => {
+            let _ = write!(message, " {}: {}", entry.name, entry.description);
+        }
+        None => {
+            let severity = match severity(hresult) {
+                Severity::Success => "success",
+                Severity::Error => "error",
+            };
+            let facility = facility(hresult);
+            let _ = match facility_name(facility) {
+                Some(name) => write!(message, " ({severity}, {name}"),
+                None => write!(message, " ({severity}, facility {facility}"),
+            };
+            let _ = write!(message, ", code {})", code(hresult));
+        }
+    }
+    message
+}
+
+const FACILITIES: &[(u16, &str)] = &[
+    (0, "FACILITY_NULL"),
+    (1, "FACILITY_RPC"),
+    (2, "FACILITY_DISPATCH"),
+    (3, "FACILITY_STORAGE"),
+    (FACILITY_ITF, "FACILITY_ITF"),
+    (FACILITY_WIN32, "FACILITY_WIN32"),
+    (8, "FACILITY_WINDOWS"),
+    (9, "FACILITY_SECURITY"),
+    (FACILITY_CONTROL, "FACILITY_CONTROL"),
+    (11, "FACILITY_CERT"),
+    (12, "FACILITY_INTERNET"),
+    (13, "FACILITY_MEDIASERVER"),
+    (14, "FACILITY_MSMQ"),
+    (15, "FACILITY_SETUPAPI"),
+    (16, "FACILITY_SCARD"),
+    (17, "FACILITY_COMPLUS"),
+    (18, "FACILITY_AAF"),
+    (19, "FACILITY_URT"),
+    (20, "FACILITY_ACS"),
+    (21, "FACILITY_DPLAY"),
+    (22, "FACILITY_UMI"),
+    (23, "FACILITY_SXS"),
+    (24, "FACILITY_WINDOWS_CE"),
+    (25, "FACILITY_HTTP"),
+    (32, "FACILITY_BACKGROUNDCOPY"),
+    (33, "FACILITY_CONFIGURATION"),
+    (36, "FACILITY_WINDOWSUPDATE"),
+    (37, "FACILITY_DIRECTORYSERVICE"),
+    (38, "FACILITY_GRAPHICS"),
+    (39, "FACILITY_SHELL"),
+];
+
+macro_rules! catalog {
+    ($($hresult:literal $name:ident $description:literal)*) => {
+        &[$(HresultEntry::new($hresult, stringify!($name), $description)),*]
+    };
+}
+
+const CATALOG: &[HresultEntry] = catalog! {
+    0x00000000 S_OK "The operation completed successfully."
+    0x00000001 S_FALSE "The operation completed successfully but returned false."
+
+    0x8000FFFF E_UNEXPECTED "Catastrophic failure."
+    0x80004001 E_NOTIMPL "Not implemented."
+    0x80004002 E_NOINTERFACE "No such interface supported."
+    0x80004003 E_POINTER "Invalid pointer."
+    0x80004004 E_ABORT "Operation aborted."
+    0x80004005 E_FAIL "Unspecified error."
+    0x8000000A E_PENDING "The data necessary to complete this operation is not yet available."
+    0x8000000B E_BOUNDS "The operation attempted to access data outside the valid range."
+    0x8000000C E_CHANGED_STATE "A concurrent or interleaved operation changed the state of the object."
+    0x8000000D E_ILLEGAL_STATE_CHANGE "An illegal state change was requested."
+    0x8000000E E_ILLEGAL_METHOD_CALL "A method was called at an unexpected time."
+    0x80070005 E_ACCESSDENIED "Access is denied."
+    0x80070006 E_HANDLE "The handle is invalid."
+    0x8007000E E_OUTOFMEMORY "Not enough memory resources are available to complete this operation."
+    0x80070057 E_INVALIDARG "The parameter is incorrect."
+
+    0x80020001 DISP_E_UNKNOWNINTERFACE "Unknown interface."
+    0x80020003 DISP_E_MEMBERNOTFOUND "Member not found."
+    0x80020004 DISP_E_PARAMNOTFOUND "Parameter not found."
+    0x80020005 DISP_E_TYPEMISMATCH "Type mismatch."
+    0x80020006 DISP_E_UNKNOWNNAME "Unknown name."
+    0x80020007 DISP_E_NONAMEDARGS "No named arguments."
+    0x80020008 DISP_E_BADVARTYPE "Bad variable type."
+    0x80020009 DISP_E_EXCEPTION "Exception occurred."
+    0x8002000A DISP_E_OVERFLOW "Out of present range."
+    0x8002000B DISP_E_BADINDEX "Invalid index."
+    0x8002000C DISP_E_UNKNOWNLCID "Unknown language."
+    0x8002000D DISP_E_ARRAYISLOCKED "Memory is locked."
+    0x8002000E DISP_E_BADPARAMCOUNT "Invalid number of parameters."
+    0x8002000F DISP_E_PARAMNOTOPTIONAL "Parameter not optional."
+    0x80020010 DISP_E_BADCALLEE "Invalid callee."
+    0x80020011 DISP_E_NOTACOLLECTION "Does not support a collection."
+    0x80020012 DISP_E_DIVBYZERO "Division by zero."
+    0x80020013 DISP_E_BUFFERTOOSMALL "Buffer too small."
+
+    0x80028016 TYPE_E_BUFFERTOOSMALL "Buffer too small."
+    0x80028017 TYPE_E_FIELDNOTFOUND "Field name not defined in the record."
+    0x80028018 TYPE_E_INVDATAREAD "Old format or invalid type library."
+    0x80028019 TYPE_E_UNSUPFORMAT "Old format or invalid type library."
+    0x8002801C TYPE_E_REGISTRYACCESS "Error accessing the OLE registry."
+    0x8002801D TYPE_E_LIBNOTREGISTERED "Library not registered."
+    0x80028027 TYPE_E_UNDEFINEDTYPE "Bound to unknown type."
+    0x80028028 TYPE_E_QUALIFIEDNAMEDISALLOWED "Qualified name disallowed."
+    0x80028029 TYPE_E_INVALIDSTATE "Invalid forward reference, or reference to uncompiled type."
+    0x8002802A TYPE_E_WRONGTYPEKIND "Type mismatch."
+    0x8002802B TYPE_E_ELEMENTNOTFOUND "Element not found."
+    0x8002802C TYPE_E_AMBIGUOUSNAME "Ambiguous name."
+    0x8002802D TYPE_E_NAMECONFLICT "Name already exists in the library."
+    0x8002802E TYPE_E_UNKNOWNLCID "Unknown LCID."
+    0x8002802F TYPE_E_DLLFUNCTIONNOTFOUND "Function not defined in specified DLL."
+    0x800288BD TYPE_E_BADMODULEKIND "Wrong module kind for the operation."
+    0x800288C5 TYPE_E_SIZETOOBIG "Size may not exceed 64K."
+    0x800288C6 TYPE_E_DUPLICATEID "Duplicate ID in inheritance hierarchy."
+    0x800288CF TYPE_E_INVALIDID "Incorrect inheritance depth in standard OLE hmember."
+    0x80028CA0 TYPE_E_TYPEMISMATCH "Type mismatch."
+    0x80028CA1 TYPE_E_OUTOFBOUNDS "Invalid number of arguments."
+    0x80028CA2 TYPE_E_IOERROR "I/O error."
+    0x80028CA3 TYPE_E_CANTCREATETMPFILE "Error creating unique tmp file."
+    0x80029C4A TYPE_E_CANTLOADLIBRARY "Error loading type library/DLL."
+    0x80029C83 TYPE_E_INCONSISTENTPROPFUNCS "Inconsistent property functions."
+    0x80029C84 TYPE_E_CIRCULARTYPE "Circular dependency between types/modules."
+
+    0x80010001 RPC_E_CALL_REJECTED "Call was rejected by callee."
+    0x80010002 RPC_E_CALL_CANCELED "Call was canceled by the message filter."
+    0x80010003 RPC_E_CANTPOST_INSENDCALL "The caller is dispatching an intertask SendMessage call and cannot call out via PostMessage."
+    0x80010004 RPC_E_CANTCALLOUT_INASYNCCALL "The caller is dispatching an asynchronous call and cannot make an outgoing call on behalf of this call."
+    0x80010005 RPC_E_CANTCALLOUT_INEXTERNALCALL "It is illegal to call out while inside message filter."
+    0x80010006 RPC_E_CONNECTION_TERMINATED "The connection terminated or is in a bogus state and can no longer be used."
+    0x80010007 RPC_E_SERVER_DIED "The callee is not available and disappeared; all connections are invalid. The call may have executed."
+    0x80010008 RPC_E_CLIENT_DIED "The caller disappeared and the callee is no longer available."
+    0x80010009 RPC_E_INVALID_DATAPACKET "The data packet with the marshalled parameter data is incorrect."
+    0x8001000A RPC_E_CANTTRANSMIT_CALL "The call was not transmitted properly; the message queue was full and was not emptied after yielding."
+    0x8001000B RPC_E_CLIENT_CANTMARSHAL_DATA "The client could not marshal the parameter data."
+    0x8001000C RPC_E_CLIENT_CANTUNMARSHAL_DATA "The client could not unmarshal the return data."
+    0x8001000D RPC_E_SERVER_CANTMARSHAL_DATA "The server could not marshal the return data."
+    0x8001000E RPC_E_SERVER_CANTUNMARSHAL_DATA "The server could not unmarshal the parameter data."
+    0x8001000F RPC_E_INVALID_DATA "Received data is invalid."
+    0x80010010 RPC_E_INVALID_PARAMETER "A particular parameter is invalid and cannot be unmarshalled."
+    0x80010011 RPC_E_CANTCALLOUT_AGAIN "There is no second outgoing call on same channel in DDE conversation."
+    0x80010012 RPC_E_SERVER_DIED_DNE "The callee is not available and disappeared; all connections are invalid. The call did not execute."
+    0x80010100 RPC_E_SYS_CALL_FAILED "System call failed."
+    0x80010101 RPC_E_OUT_OF_RESOURCES "Could not allocate some required resource."
+    0x80010102 RPC_E_ATTEMPTED_MULTITHREAD "Attempted to make calls on more than one thread in single threaded mode."
+    0x80010103 RPC_E_NOT_REGISTERED "The requested interface is not registered on the server object."
+    0x80010104 RPC_E_FAULT "RPC could not call the server or could not return the results of calling the server."
+    0x80010105 RPC_E_SERVERFAULT "The server threw an exception."
+    0x80010106 RPC_E_CHANGED_MODE "Cannot change thread mode after it is set."
+    0x80010107 RPC_E_INVALIDMETHOD "The method called does not exist on the server."
+    0x80010108 RPC_E_DISCONNECTED "The object invoked has disconnected from its clients."
+    0x80010109 RPC_E_RETRY "The object invoked chose not to process the call now. Try again later."
+    0x8001010A RPC_E_SERVERCALL_RETRYLATER "The message filter indicated that the application is busy."
+    0x8001010B RPC_E_SERVERCALL_REJECTED "The message filter rejected the call."
+    0x8001010C RPC_E_INVALID_CALLDATA "A call control interface was called with invalid data."
+    0x8001010D RPC_E_CANTCALLOUT_ININPUTSYNCCALL "An outgoing call cannot be made since the application is dispatching an input-synchronous call."
+    0x8001010E RPC_E_WRONG_THREAD "The application called an interface that was marshalled for a different thread."
+    0x8001010F RPC_E_THREAD_NOT_INIT "CoInitialize has not been called on the current thread."
+    0x8001011F RPC_E_TIMEOUT "This operation returned because the timeout period expired."
+    0x8001FFFF RPC_E_UNEXPECTED "An internal error occurred."
+
+    0x800401E3 MK_E_UNAVAILABLE "Operation unavailable."
+    0x800401F0 CO_E_NOTINITIALIZED "CoInitialize has not been called."
+    0x800401F1 CO_E_ALREADYINITIALIZED "CoInitialize has already been called."
+    0x800401F2 CO_E_CANTDETERMINECLASS "Class of object cannot be determined."
+    0x800401F3 CO_E_CLASSSTRING "Invalid class string."
+    0x800401F4 CO_E_IIDSTRING "Invalid interface string."
+    0x800401F5 CO_E_APPNOTFOUND "Application not found."
+    0x800401F6 CO_E_APPSINGLEUSE "Application cannot be run more than once."
+    0x800401F7 CO_E_ERRORINAPP "Some error in application program."
+    0x800401F8 CO_E_DLLNOTFOUND "DLL for class not found."
+    0x800401F9 CO_E_ERRORINDLL "Error in the DLL."
+    0x800401FA CO_E_WRONGOSFORAPP "Wrong operating system or operating system version for the application."
+    0x800401FB CO_E_OBJNOTREG "Object is not registered."
+    0x800401FC CO_E_OBJISREG "Object is already registered."
+    0x800401FD CO_E_OBJNOTCONNECTED "Object is not connected to server."
+    0x800401FE CO_E_APPDIDNTREG "Application was launched but it didn't register a class factory."
+    0x800401FF CO_E_RELEASED "Object has been released."
+    0x80004021 CO_E_NOT_SUPPORTED "The operation is not supported."
+    0x80080001 CO_E_CLASS_CREATE_FAILED "Attempt to create a class object failed."
+    0x80080005 CO_E_SERVER_EXEC_FAILURE "Server execution failed."
+    0x80080008 CO_E_SERVER_STOPPING "Object server is stopping when OLE service contacts it."
+
+    0x80040150 REGDB_E_READREGDB "Could not read key from registry."
+    0x80040151 REGDB_E_WRITEREGDB "Could not write key to registry."
+    0x80040152 REGDB_E_KEYMISSING "Could not find the key in the registry."
+    0x80040153 REGDB_E_INVALIDVALUE "Invalid value for registry."
+    0x80040154 REGDB_E_CLASSNOTREG "Class not registered."
+    0x80040155 REGDB_E_IIDNOTREG "Interface not registered."
+    0x80040156 REGDB_E_BADTHREADINGMODEL "Threading model entry is not valid."
+
+    0x80040110 CLASS_E_NOAGGREGATION "Class does not support aggregation (or class object is remote)."
+    0x80040111 CLASS_E_CLASSNOTAVAILABLE "ClassFactory cannot supply requested class."
+    0x80040112 CLASS_E_NOTLICENSED "Class is not licensed for use."
+
+    0x80040200 CONNECT_E_NOCONNECTION "There is no connection for this connection ID."
+    0x80040201 CONNECT_E_ADVISELIMIT "This implementation's limit for advisory connections has been reached."
+    0x80040202 CONNECT_E_CANNOTCONNECT "Connection attempt failed."
+    0x80040203 CONNECT_E_OVERRIDDEN "Must use a derived interface to connect."
+};
+
+/// Win32 errors, looked up for `HRESULT_FROM_WIN32` codes
+const WIN32_ERRORS: &[(u32, &str, &str)] = &[
+    (1, "ERROR_INVALID_FUNCTION", "Incorrect function."),
+    (
+        2,
+        "ERROR_FILE_NOT_FOUND",
+        "The system cannot find the file specified.",
+    ),
+    (
+        3,
+        "ERROR_PATH_NOT_FOUND",
+        "The system cannot find the path specified.",
+    ),
+    (5, "ERROR_ACCESS_DENIED", "Access is denied."),
+    (6, "ERROR_INVALID_HANDLE", "The handle is invalid."),
+    (
+        8,
+        "ERROR_NOT_ENOUGH_MEMORY",
+        "Not enough memory resources are available to process this command.",
+    ),
+    (
+        14,
+        "ERROR_OUTOFMEMORY",
+        "Not enough memory resources are available to complete this operation.",
+    ),
+    (
+        32,
+        "ERROR_SHARING_VIOLATION",
+        "The process cannot access the file because it is being used by another process.",
+    ),
+    (87, "ERROR_INVALID_PARAMETER", "The parameter is incorrect."),
+    (
+        122,
+        "ERROR_INSUFFICIENT_BUFFER",
+        "The data area passed to a system call is too small.",
+    ),
+    (
+        126,
+        "ERROR_MOD_NOT_FOUND",
+        "The specified module could not be found.",
+    ),
+    (
+        127,
+        "ERROR_PROC_NOT_FOUND",
+        "The specified procedure could not be found.",
+    ),
+    (
+        183,
+        "ERROR_ALREADY_EXISTS",
+        "Cannot create a file when that file already exists.",
+    ),
+    (
+        193,
+        "ERROR_BAD_EXE_FORMAT",
+        "The file is not a valid Win32 application.",
+    ),
+    (234, "ERROR_MORE_DATA", "More data is available."),
+    (259, "ERROR_NO_MORE_ITEMS", "No more data is available."),
+    (
+        1114,
+        "ERROR_DLL_INIT_FAILED",
+        "A dynamic link library (DLL) initialization routine failed.",
+    ),
+    (
+        1115,
+        "ERROR_SHUTDOWN_IN_PROGRESS",
+        "A system shutdown is in progress.",
+    ),
+    (
+        1155,
+        "ERROR_NO_ASSOCIATION",
+        "No application is associated with the specified file for this operation.",
+    ),
+    (
+        1223,
+        "ERROR_CANCELLED",
+        "The operation was canceled by the user.",
+    ),
+    (
+        1460,
+        "ERROR_TIMEOUT",
+        "This operation returned because the timeout period expired.",
+    ),
+    (
+        1722,
+        "RPC_S_SERVER_UNAVAILABLE",
+        "The RPC server is unavailable.",
+    ),
+    (
+        1726,
+        "RPC_S_CALL_FAILED",
+        "The remote procedure call failed.",
+    ),
+    (
+        1727,
+        "RPC_S_CALL_FAILED_DNE",
+        "The remote procedure call failed and did not execute.",
+    ),
+    (
+        1814,
+        "ERROR_RESOURCE_NAME_NOT_FOUND",
+        "The specified resource name cannot be found in the image file.",
+    ),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hr(value: u32) -> HRESULT {
+        HRESULT(value as i32)
+    }
+
+    #[test]
+    fn known_codes() {
+        assert_eq!(
+            describe(hr(0x80020009)),
+            "0x80020009 DISP_E_EXCEPTION: Exception occurred."
+        );
+        assert_eq!(name(hr(0x80070005)), Some("E_ACCESSDENIED"));
+        assert_eq!(name(hr(0x00000000)), Some("S_OK"));
+    }
+
+    #[test]
+    fn unknown_codes_are_decoded() {
+        assert_eq!(
+            describe(hr(0x800A1234)),
+            "0x800A1234 (error, FACILITY_CONTROL, code 4660)"
+        );
+        assert_eq!(
+            describe(hr(0x07FF0001)),
+            "0x07FF0001 (success, facility 2047, code 1)"
+        );
+        assert_eq!(lookup(hr(0x800A1234)), None);
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(severity(hr(0x80070002)), Severity::Error);
+        assert_eq!(severity(hr(0x00000001)), Severity::Success);
+        assert_eq!(facility(hr(0x80070002)), FACILITY_WIN32);
+        assert_eq!(facility(hr(0x80040201)), FACILITY_ITF);
+        assert_eq!(facility(hr(0x800A03EC)), FACILITY_CONTROL);
+        assert_eq!(code(hr(0x800A03EC)), 1004);
+        assert_eq!(facility_name(FACILITY_ITF), Some("FACILITY_ITF"));
+        assert_eq!(facility_name(2047), None);
+    }
+
+    #[test]
+    fn win32_errors_are_unwrapped() {
+        assert_eq!(
+            describe(hr(0x80070002)),
+            "0x80070002 HRESULT_FROM_WIN32(ERROR_FILE_NOT_FOUND): \
+             The system cannot find the file specified."
+        );
+        assert_eq!(name(hr(0x80070002)), Some("ERROR_FILE_NOT_FOUND"));
+        // A catalog entry of its own wins over the wrapped Win32 name.
+        assert_eq!(name(hr(0x80070005)), Some("E_ACCESSDENIED"));
+        // Only failures in FACILITY_WIN32 wrap a Win32 error.
+        assert_eq!(name(hr(0x00070002)), None);
+        assert_eq!(name(hr(0x800A0002)), None);
+    }
+
+    #[test]
+    fn registered_tables_take_precedence() {
+        // Registration is process-wide, so these codes are not used by any other test.
+        static FIRST: &[HresultEntry] = &[
+            HresultEntry::new(0x800A7001, "APP_E_FIRST", "First table."),
+            HresultEntry::new(0x800A7002, "APP_E_SHADOWED", "Shadowed."),
+        ];
+        static SECOND: &[HresultEntry] = &[
+            HresultEntry::new(0x800A7002, "APP_E_SECOND", "Second table."),
+            HresultEntry::new(0x8007000E, "APP_E_MEMORY", "Overrides the catalog."),
+        ];
+        register(FIRST);
+        register(SECOND);
+        assert_eq!(name(hr(0x800A7001)), Some("APP_E_FIRST"));
+        assert_eq!(
+            describe(hr(0x800A7002)),
+            "0x800A7002 APP_E_SECOND: Second table."
+        );
+        assert_eq!(name(hr(0x8007000E)), Some("APP_E_MEMORY"));
+    }
+}
@@ -1,46 +1,98 @@
-use crate::{error::Result, util::RegKey};
+use crate::error::Result;
 use std::sync::LazyLock;
-use windows::Win32::System::Registry::{HKEY_CLASSES_ROOT, HKEY_LOCAL_MACHINE};
 
+// The dispatch macros refer to `::win32ole`, which this makes work inside the crate too.
+extern crate self as win32ole;
+
+pub mod backend;
+mod collection;
+mod dispatchex;
+mod dispids;
 pub mod error;
+mod eventtype;
+pub mod hresult;
+#[doc(hidden)]
+pub mod macros;
+mod memory;
+pub mod msgpump;
 mod oledata;
-//mod oleeventdata;
+mod oleenum;
+mod oleeventdata;
 mod olemethoddata;
 mod oleparamdata;
+mod olepropertynotify;
 mod oletypedata;
 mod oletypelibdata;
 mod olevariabledata;
+pub mod path;
+mod proxy;
+pub mod record;
+pub mod registry;
+pub mod retry;
+pub mod server;
 pub mod types;
 mod util;
+mod value;
 //mod variant;
 
 pub use {
-    oledata::OleData,
+    backend::{DispatchBackend, NativeDispatch},
+    collection::{Collection, CollectionItems},
+    dispatchex::DynamicMember,
+    eventtype::{Event, EventInterface, EventParam, EventSignature},
+    memory::MemoryObject,
+    msgpump::{CancelToken, MessagePump},
+    oledata::{Argument, OleData},
+    oleenum::OleEnum,
+    oleeventdata::{EventArgs, OleEventData},
     olemethoddata::OleMethodData,
     oleparamdata::OleParamData,
+    olepropertynotify::{OlePropertyNotify, PropertyChange},
     oletypedata::OleTypeData,
-    oletypelibdata::{oletypelib_from_guid, OleTypeLibData},
+    oletypelibdata::{
+        oletypelib_from_guid, oletypelib_from_guid_in, oletypelib_path, oletypelib_path_in,
+        typelib_file_in, OleTypeLibData,
+    },
     olevariabledata::OleVariableData,
+    proxy::{Backpressure, EventStream, OleProxy, ProxyEvent, ProxyFuture, ProxyValue, StaThread},
+    record::{Recorder, Replay},
+    registry::{Hive, MemoryRegistry, NativeRegistry, Registry, RegistryKey},
+    retry::RetryPolicy,
+    server::{Callback, DispatchObject},
     util::{
         conv::ToWide,
         ole::{init_runtime, ole_initialized, TypeRef},
     },
+    value::{IntoVariant, Missing, Variant},
+    win32ole_derive::{dispatch_impl, Dispatch},
+    windows::Win32::System::Ole::{
+        DISPID_COLLECT, DISPID_CONSTRUCTOR, DISPID_DESTRUCTOR, DISPID_EVALUATE, DISPID_NEWENUM,
+        DISPID_PROPERTYPUT, DISPID_UNKNOWN, DISPID_VALUE,
+    },
 };
 
-static G_RUNNING_NANO: LazyLock<bool> = LazyLock::new(|| {
-    let hsubkey = RegKey::predef(HKEY_LOCAL_MACHINE)
-        .open_subkey("SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion\\Server\\ServerLevels");
-    if let Ok(hsubkey) = hsubkey {
-        let result = hsubkey.get_value("NanoServer");
-        if result.is_ok() {
-            return true;
-        }
-    }
-    false
-});
+static G_RUNNING_NANO: LazyLock<bool> = LazyLock::new(|| running_nano(&NativeRegistry));
+
+/// Whether the registry describes a Nano Server installation
+///
+fn running_nano(registry: &dyn Registry) -> bool {
+    registry
+        .value(
+            Hive::LocalMachine,
+            "SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion\\Server\\ServerLevels",
+            "NanoServer",
+        )
+        .is_ok()
+}
 
 pub fn progids() -> Result<Vec<String>> {
-    let hclsids = RegKey::predef(HKEY_CLASSES_ROOT).open_subkey("CLSID")?;
+    progids_in(&NativeRegistry)
+}
+
+/// ProgIDs registered in `registry`
+///
+pub fn progids_in(registry: &dyn Registry) -> Result<Vec<String>> {
+    let hclsids = RegistryKey::open(registry, Hive::ClassesRoot, "CLSID")?;
     let mut progids = vec![];
 
     for clsid_or_error in hclsids.enum_keys() {
@@ -83,7 +135,13 @@ pub fn progids() -> Result<Vec<String>> {
 }
 
 pub fn typelibs() -> Result<Vec<Result<OleTypeLibData>>> {
-    let htypelib = RegKey::predef(HKEY_CLASSES_ROOT).open_subkey("TypeLib")?;
+    typelibs_in(&NativeRegistry)
+}
+
+/// Type libraries registered in `registry`
+///
+pub fn typelibs_in(registry: &dyn Registry) -> Result<Vec<Result<OleTypeLibData>>> {
+    let htypelib = RegistryKey::open(registry, Hive::ClassesRoot, "TypeLib")?;
     let mut typelibs = vec![];
 
     for guid_or_error in htypelib.enum_keys() {
@@ -100,7 +158,7 @@ pub fn typelibs() -> Result<Vec<Result<OleTypeLibData>>> {
                         hversion.get_value(&version)
                     };
                     if let Ok(name) = name {
-                        let typelib = oletypelib_from_guid(&guid, &version);
+                        let typelib = oletypelib_from_guid_in(registry, &guid, &version);
                         if let Ok(typelib) = typelib {
                             typelibs.push(OleTypeLibData::make(typelib, name));
                         }
@@ -114,3 +172,84 @@ pub fn typelibs() -> Result<Vec<Result<OleTypeLibData>>> {
 
     Ok(typelibs)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The value of `result`, without `Debug`ging the error, which needs oleaut32
+    fn value<T>(result: Result<T>) -> T {
+        match result {
+            Ok(value) => value,
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    #[test]
+    fn progids_from_subkeys_and_values() {
+        let mut registry = MemoryRegistry::new();
+        let excel = r"CLSID\{00024500-0000-0000-C000-000000000046}";
+        registry.set_value(
+            Hive::ClassesRoot,
+            &format!(r"{excel}\ProgID"),
+            "",
+            "Excel.Application.16",
+        );
+        registry.set_value(
+            Hive::ClassesRoot,
+            &format!(r"{excel}\VersionIndependentProgID"),
+            "",
+            "Excel.Application",
+        );
+        let word = r"CLSID\{000209FF-0000-0000-C000-000000000046}";
+        registry.set_value(Hive::ClassesRoot, word, "ProgID", "Word.Application.16");
+        registry.set_value(
+            Hive::ClassesRoot,
+            word,
+            "VersionIndependentProgID",
+            "Word.Application",
+        );
+        registry.create_key(
+            Hive::ClassesRoot,
+            r"CLSID\{00000000-0000-0000-0000-000000000000}",
+        );
+
+        assert_eq!(
+            value(progids_in(&registry)),
+            [
+                "Word.Application.16",
+                "Word.Application",
+                "Excel.Application.16",
+                "Excel.Application",
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_keys_are_not_found() {
+        let registry = MemoryRegistry::new();
+        match progids_in(&registry) {
+            Err(error @ error::Error::NotFound(_)) => {
+                assert_eq!(
+                    error.to_string(),
+                    r"registry key `ClassesRoot\CLSID` not found"
+                )
+            }
+            Err(error) => panic!("expected a missing key, got {error}"),
+            Ok(progids) => panic!("found {progids:?}"),
+        }
+    }
+
+    #[test]
+    fn running_nano_server() {
+        let mut registry = MemoryRegistry::new();
+        assert!(!running_nano(&registry));
+        registry.set_value(
+            Hive::LocalMachine,
+            r"SOFTWARE\Microsoft\Windows NT\CurrentVersion\Server\ServerLevels",
+            "NanoServer",
+            "1",
+        );
+        assert!(running_nano(&registry));
+    }
+}
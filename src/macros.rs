@@ -0,0 +1,117 @@
+//! Runtime support for the [`ole!`](crate::ole) macro.
+
+use windows::Win32::System::Variant::VARIANT;
+
+use crate::{
+    error::{Error, Result},
+    OleData,
+};
+
+/// One segment of an `ole!` expression
+pub enum Step {
+    /// `.Name` or `.Name(args)`
+    Member {
+        name: &'static str,
+        args: Vec<VARIANT>,
+    },
+    /// `[args]`, applied to the default member of the current object
+    Index { args: Vec<VARIANT> },
+}
+
+impl Step {
+    fn text(&self) -> String {
+        match self {
+            Step::Member { name, args } if args.is_empty() => format!(".{name}"),
+            Step::Member { name, args } => format!(".{name}(..{} args)", args.len()),
+            Step::Index { args } => format!("[..{} args]", args.len()),
+        }
+    }
+    fn eval(self, target: &OleData) -> Result<VARIANT> {
+        match self {
+            Step::Member { name, args } => target.get_with(name, args),
+            Step::Index { args } => target.get_default(args),
+        }
+    }
+    fn assign(self, target: &OleData, value: VARIANT) -> Result<()> {
+        match self {
+            Step::Member { name, args } => target.put_with(name, args, value),
+            Step::Index { args } => target.put_default(args, value),
+        }
+    }
+}
+
+/// Evaluate `steps` starting at `root`, assigning `value` through the last step if given.
+///
+/// Intermediate objects are released as soon as the next step has been evaluated.
+pub fn run(root: &OleData, steps: Vec<Step>, value: Option<VARIANT>) -> Result<VARIANT> {
+    let mut current = root.clone();
+    let mut result = VARIANT::default();
+    let mut value = value;
+    let count = steps.len();
+    for (i, step) in steps.into_iter().enumerate() {
+        let text = step.text();
+        let annotate = |error: Error| Error::Context {
+            context: format!("`{text}` (segment {}) failed", i + 1),
+            source: Box::new(error),
+        };
+        if i > 0 {
+            current = OleData::try_from(&result).map_err(annotate)?;
+        }
+        if i + 1 == count {
+            if let Some(value) = value.take() {
+                step.assign(&current, value).map_err(annotate)?;
+                return Ok(VARIANT::default());
+            }
+        }
+        result = step.eval(&current).map_err(annotate)?;
+    }
+    Ok(result)
+}
+
+/// Late-bound member access with VBA-like syntax
+///
+/// ```ignore
+/// let name = ole!(excel.Workbooks.Add().Sheets[1].Name)?;
+/// ole!(excel.ActiveWorkbook.Sheets[1].Name = "Report")?;
+/// ```
+///
+/// `.Name` and `.Name(args)` get a property or call a method, `[args]` indexes the default
+/// member and a trailing `= value` assigns. Arguments are converted with
+/// [`IntoVariant`](crate::IntoVariant). The expansion evaluates to `Result<VARIANT>`, and an
+/// error names the segment that failed.
+#[macro_export]
+macro_rules! ole {
+    (@steps $root:tt [$($steps:expr,)*] . $name:ident ( $($arg:expr),* $(,)? ) $($rest:tt)*) => {
+        $crate::ole!(@steps $root [$($steps,)* $crate::macros::Step::Member {
+            name: stringify!($name),
+            args: vec![$($crate::IntoVariant::into_variant($arg)),*],
+        },] $($rest)*)
+    };
+    (@steps $root:tt [$($steps:expr,)*] . $name:ident $($rest:tt)*) => {
+        $crate::ole!(@steps $root [$($steps,)* $crate::macros::Step::Member {
+            name: stringify!($name),
+            args: vec![],
+        },] $($rest)*)
+    };
+    (@steps $root:tt [$($steps:expr,)*] [ $($arg:expr),* $(,)? ] $($rest:tt)*) => {
+        $crate::ole!(@steps $root [$($steps,)* $crate::macros::Step::Index {
+            args: vec![$($crate::IntoVariant::into_variant($arg)),*],
+        },] $($rest)*)
+    };
+    (@steps $root:tt [$($steps:expr,)*] = $value:expr) => {
+        $crate::macros::run(
+            $root,
+            vec![$($steps),*],
+            Some($crate::IntoVariant::into_variant($value)),
+        )
+    };
+    (@steps $root:tt [$($steps:expr,)*]) => {
+        $crate::macros::run($root, vec![$($steps),*], None)
+    };
+    (($root:expr) $($rest:tt)*) => {
+        $crate::ole!(@steps (&$root) [] $($rest)*)
+    };
+    ($root:ident $($rest:tt)*) => {
+        $crate::ole!(@steps (&$root) [] $($rest)*)
+    };
+}
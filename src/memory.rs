@@ -0,0 +1,435 @@
+//! An automation object model implemented in Rust.
+//!
+//! [`MemoryObject`] is a [`DispatchBackend`] whose members are plain values, closures or
+//! nested objects, so code written against [`OleData`] can be exercised without a COM server:
+//!
+//! ```ignore
+//! let sheet = MemoryObject::new("Worksheet").property("Name", "Data").into_ole();
+//! let app = MemoryObject::new("Application")
+//!     .property("Version", "16.0")
+//!     .collection("Worksheets", vec![sheet])
+//!     .method("Calculate", |_| Ok(Variant::Empty))
+//!     .into_ole();
+//! ```
+
+use std::{cell::RefCell, rc::Rc};
+
+use windows::Win32::{
+    Foundation::{
+        DISP_E_BADPARAMCOUNT, DISP_E_MEMBERNOTFOUND, DISP_E_NONAMEDARGS, DISP_E_TYPEMISMATCH,
+        DISP_E_UNKNOWNNAME, E_FAIL,
+    },
+    System::{
+        Com::{
+            DISPATCH_FLAGS, DISPATCH_METHOD, DISPATCH_PROPERTYGET, DISPATCH_PROPERTYPUT,
+            DISPATCH_PROPERTYPUTREF,
+        },
+        Ole::{DISPID_PROPERTYPUT, DISPID_UNKNOWN, DISPID_VALUE},
+    },
+};
+
+use crate::{
+    backend::DispatchBackend,
+    error::{ComException, Error, Result},
+    value::Variant,
+    OleData,
+};
+
+type Getter = Box<dyn Fn(&[Variant]) -> Result<Variant>>;
+type Setter = Box<dyn Fn(&[Variant], Variant) -> Result<()>>;
+
+enum MemberKind {
+    /// A value that can be read and assigned
+    Value(RefCell<Variant>),
+    /// A read-only property or method computed by a closure
+    Getter(Getter),
+    /// A property with a closure for each direction
+    Accessor(Getter, Setter),
+}
+
+struct Member {
+    name: String,
+    kind: MemberKind,
+}
+
+/// An in-process automation object with members defined in Rust
+///
+/// Members get DISPIDs from 1 in the order they are added. Errors returned by closures reach
+/// the caller as `DISP_E_EXCEPTION`, like exceptions raised by a COM server.
+pub struct MemoryObject {
+    type_name: String,
+    members: Vec<Member>,
+    default_member: Option<usize>,
+}
+
+impl MemoryObject {
+    pub fn new(type_name: &str) -> MemoryObject {
+        MemoryObject {
+            type_name: type_name.to_string(),
+            members: vec![],
+            default_member: None,
+        }
+    }
+    fn member(mut self, name: &str, kind: MemberKind) -> MemoryObject {
+        self.members.push(Member {
+            name: name.to_string(),
+            kind,
+        });
+        self
+    }
+    /// A read/write property holding a value
+    ///
+    pub fn property(self, name: &str, value: impl Into<Variant>) -> MemoryObject {
+        self.member(name, MemberKind::Value(RefCell::new(value.into())))
+    }
+    /// A method, or a read-only property taking arguments
+    ///
+    pub fn method(
+        self,
+        name: &str,
+        method: impl Fn(&[Variant]) -> Result<Variant> + 'static,
+    ) -> MemoryObject {
+        self.member(name, MemberKind::Getter(Box::new(method)))
+    }
+    /// A property with computed get and put, which receive any index arguments
+    ///
+    pub fn accessor(
+        self,
+        name: &str,
+        get: impl Fn(&[Variant]) -> Result<Variant> + 'static,
+        put: impl Fn(&[Variant], Variant) -> Result<()> + 'static,
+    ) -> MemoryObject {
+        self.member(name, MemberKind::Accessor(Box::new(get), Box::new(put)))
+    }
+    /// A read-only property returning a nested object
+    ///
+    pub fn object(self, name: &str, object: OleData) -> MemoryObject {
+        self.method(name, move |_| Ok(Variant::Object(object.clone())))
+    }
+    /// A read-only property returning a collection with `Count`, `Item` and a default member
+    ///
+    pub fn collection(self, name: &str, items: Vec<OleData>) -> MemoryObject {
+        let collection = MemoryObject::from_items(
+            name,
+            items.into_iter().map(Variant::Object).collect::<Vec<_>>(),
+        )
+        .into_ole();
+        self.object(name, collection)
+    }
+    /// A collection object over `items`, indexed from 1 like Office collections
+    ///
+    /// `Item` also accepts a name, matched against each item's `Name` property.
+    pub fn from_items(type_name: &str, items: Vec<Variant>) -> MemoryObject {
+        let items = Rc::new(items);
+        let count = items.clone();
+        MemoryObject::new(type_name)
+            .method("Count", move |_| Ok(Variant::I4(count.len() as i32)))
+            .method("Item", move |args| item_of(&items, args))
+            .default_member("Item")
+    }
+    /// Make `name` the member invoked through `DISPID_VALUE`
+    ///
+    pub fn default_member(mut self, name: &str) -> MemoryObject {
+        self.default_member = self.position(name);
+        self
+    }
+    pub fn into_ole(self) -> OleData {
+        OleData::from_backend(self)
+    }
+    fn position(&self, name: &str) -> Option<usize> {
+        self.members
+            .iter()
+            .position(|member| member.name.eq_ignore_ascii_case(name))
+    }
+    fn resolve(&self, dispid: i32) -> Option<&Member> {
+        match dispid {
+            DISPID_VALUE => self.default_member.map(|index| &self.members[index]),
+            dispid if dispid > 0 => self.members.get(dispid as usize - 1),
+            _ => None,
+        }
+    }
+    /// Run `member` with arguments in `DISPPARAMS::rgvarg` order
+    ///
+    fn run(
+        &self,
+        member: &Member,
+        flags: DISPATCH_FLAGS,
+        args: &[Variant],
+        named: &[i32],
+    ) -> Result<Variant> {
+        let put = flags.0 & (DISPATCH_PROPERTYPUT.0 | DISPATCH_PROPERTYPUTREF.0) != 0;
+        if put {
+            // The assigned value is passed first, as the named argument DISPID_PROPERTYPUT.
+            let ([DISPID_PROPERTYPUT], [value, indices @ ..]) = (named, args) else {
+                return Err(Error::HResult(DISP_E_BADPARAMCOUNT));
+            };
+            let indices: Vec<Variant> = indices.iter().rev().cloned().collect();
+            return match member.kind {
+                MemberKind::Value(ref slot) if indices.is_empty() => {
+                    *slot.borrow_mut() = value.clone();
+                    Ok(Variant::Empty)
+                }
+                MemberKind::Value(_) => Err(Error::HResult(DISP_E_BADPARAMCOUNT)),
+                MemberKind::Accessor(_, ref set) => {
+                    set(&indices, value.clone()).map(|_| Variant::Empty)
+                }
+                MemberKind::Getter(_) => Err(Error::HResult(DISP_E_MEMBERNOTFOUND)),
+            }
+            .map_err(|error| self.exception(member, error));
+        }
+        if !named.is_empty() {
+            return Err(Error::HResult(DISP_E_NONAMEDARGS));
+        }
+        if flags.0 & (DISPATCH_METHOD.0 | DISPATCH_PROPERTYGET.0) == 0 {
+            return Err(Error::HResult(DISP_E_MEMBERNOTFOUND));
+        }
+        let args: Vec<Variant> = args.iter().rev().cloned().collect();
+        let value = match member.kind {
+            MemberKind::Value(ref slot) => {
+                let value = slot.borrow().clone();
+                if args.is_empty() {
+                    Ok(value)
+                } else {
+                    // `obj.Items(2)` on a plain value indexes the value's default member.
+                    index_value(value, &args)
+                }
+            }
+            MemberKind::Getter(ref get) | MemberKind::Accessor(ref get, _) => get(&args),
+        };
+        value.map_err(|error| self.exception(member, error))
+    }
+    /// Report a closure's error as an exception raised by the member, passing bare codes on
+    fn exception(&self, member: &Member, error: Error) -> Error {
+        match error {
+            error @ (Error::Windows(_) | Error::HResult(_)) => error,
+            error => ComException::new(
+                &format!("{}.{}", self.type_name, member.name),
+                &format!("{error:#}"),
+                error.hresult().unwrap_or(E_FAIL),
+            )
+            .into(),
+        }
+    }
+}
+
+fn item_of(items: &[Variant], args: &[Variant]) -> Result<Variant> {
+    let [key] = args else {
+        return Err(Error::HResult(DISP_E_BADPARAMCOUNT));
+    };
+    let index = match key {
+        Variant::String(name) => items.iter().position(|item| match item {
+            Variant::Object(object) => object
+                .get_value("Name", vec![])
+                .is_ok_and(|value| {
+                    matches!(value, Variant::String(ref item_name) if item_name.eq_ignore_ascii_case(name))
+                }),
+            _ => false,
+        }),
+        key => {
+            let index = whole_number(key).ok_or(Error::HResult(DISP_E_TYPEMISMATCH))?;
+            usize::try_from(index - 1)
+                .ok()
+                .filter(|index| *index < items.len())
+        }
+    };
+    match index {
+        Some(index) => Ok(items[index].clone()),
+        None => Err(Error::Custom(format!("no item matches the key {key:?}"))),
+    }
+}
+
+/// The value of a numeric key, if it is a whole number
+fn whole_number(key: &Variant) -> Option<i64> {
+    match *key {
+        Variant::UI1(value) => Some(value.into()),
+        Variant::I2(value) => Some(value.into()),
+        Variant::UI2(value) => Some(value.into()),
+        Variant::I4(value) => Some(value.into()),
+        Variant::UI4(value) => Some(value.into()),
+        Variant::I8(value) => Some(value),
+        Variant::UI8(value) => i64::try_from(value).ok(),
+        Variant::R4(value) if value.fract() == 0.0 => Some(value as i64),
+        Variant::R8(value) if value.fract() == 0.0 => Some(value as i64),
+        _ => None,
+    }
+}
+
+fn index_value(value: Variant, args: &[Variant]) -> Result<Variant> {
+    match value {
+        Variant::Object(object) => {
+            let mut args: Vec<Variant> = args.iter().rev().cloned().collect();
+            object.invoke_value(
+                DISPID_VALUE,
+                DISPATCH_FLAGS(DISPATCH_PROPERTYGET.0 | DISPATCH_METHOD.0),
+                &mut args,
+                &[],
+            )
+        }
+        Variant::Array(items) => item_of(&items, args),
+        _ => Err(Error::HResult(DISP_E_BADPARAMCOUNT)),
+    }
+}
+
+impl DispatchBackend for MemoryObject {
+    fn ids_of_names(&self, names: &[String]) -> (Result<()>, Vec<i32>) {
+        let mut ids = vec![DISPID_UNKNOWN; names.len()];
+        let Some(member) = names.first().and_then(|name| self.position(name)) else {
+            return (Err(Error::HResult(DISP_E_UNKNOWNNAME)), ids);
+        };
+        ids[0] = member as i32 + 1;
+        if names.len() > 1 {
+            // Members of a memory object have no parameter names.
+            return (Err(Error::HResult(DISP_E_UNKNOWNNAME)), ids);
+        }
+        (Ok(()), ids)
+    }
+
+    fn invoke(
+        &self,
+        dispid: i32,
+        flags: DISPATCH_FLAGS,
+        args: &mut [Variant],
+        named: &[i32],
+    ) -> Result<Variant> {
+        let Some(member) = self.resolve(dispid) else {
+            return Err(Error::HResult(DISP_E_MEMBERNOTFOUND));
+        };
+        self.run(member, flags, args, named)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The value of `result`, without `Debug`ging the error, which needs oleaut32
+    fn value<T>(result: Result<T>) -> T {
+        match result {
+            Ok(value) => value,
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    fn object(value: Variant) -> OleData {
+        match value {
+            Variant::Object(object) => object,
+            value => panic!("expected an object, got {value:?}"),
+        }
+    }
+
+    fn workbook() -> OleData {
+        let sheets = ["Data", "Summary"]
+            .into_iter()
+            .map(|name| {
+                MemoryObject::new("Worksheet")
+                    .property("Name", name)
+                    .into_ole()
+            })
+            .collect();
+        MemoryObject::new("Workbook")
+            .property("Saved", true)
+            .method("Sum", |args| {
+                let mut total = 0;
+                for arg in args {
+                    match arg {
+                        Variant::I4(value) => total += value,
+                        _ => return Err(Error::HResult(DISP_E_TYPEMISMATCH)),
+                    }
+                }
+                Ok(Variant::I4(total))
+            })
+            .method("Fail", |_| Err(Error::Custom("disk full".into())))
+            .collection("Worksheets", sheets)
+            .into_ole()
+    }
+
+    #[test]
+    fn properties() {
+        let book = workbook();
+        assert_eq!(value(book.get_value("Saved", vec![])), Variant::Bool(true));
+        value(book.put_value("saved", vec![], false.into()));
+        assert_eq!(value(book.get_value("SAVED", vec![])), Variant::Bool(false));
+    }
+
+    #[test]
+    fn methods() {
+        let book = workbook();
+        let args = vec![2.into(), 3.into()];
+        assert_eq!(value(book.call_value("Sum", args)), Variant::I4(5));
+    }
+
+    #[test]
+    fn collections() {
+        let sheets = object(value(workbook().get_value("Worksheets", vec![])));
+        assert_eq!(value(sheets.get_value("Count", vec![])), Variant::I4(2));
+        let mut index = [2.into()];
+        let second = sheets.invoke_value(DISPID_VALUE, DISPATCH_PROPERTYGET, &mut index, &[]);
+        let second = object(value(second));
+        assert_eq!(
+            value(second.get_value("Name", vec![])),
+            Variant::from("Summary")
+        );
+        let by_name = object(value(sheets.call_value("Item", vec!["data".into()])));
+        assert_eq!(
+            value(by_name.get_value("Name", vec![])),
+            Variant::from("Data")
+        );
+        let third = sheets.call_value("Item", vec![3.into()]).unwrap_err();
+        assert!(
+            matches!(third, Error::Exception(ref exception) if exception.source == "Worksheets.Item")
+        );
+    }
+
+    // Enumerating goes through VARIANTs, which need oleaut32.
+    #[cfg(windows)]
+    #[test]
+    fn iteration() {
+        let sheets = object(value(workbook().get_value("Worksheets", vec![])));
+        let names: Vec<Variant> = value(sheets.iter())
+            .map(|item| {
+                let item = OleData::try_from(value(item)).unwrap();
+                value(item.get_value("Name", vec![]))
+            })
+            .collect();
+        assert_eq!(names, [Variant::from("Data"), Variant::from("Summary")]);
+    }
+
+    #[test]
+    fn errors() {
+        let book = workbook();
+        match book.call_value("Fail", vec![]) {
+            Err(Error::Exception(exception)) => {
+                assert_eq!(exception.source, "Workbook.Fail");
+                assert_eq!(exception.member.as_deref(), Some("Fail"));
+                assert!(exception.description.contains("disk full"));
+                assert_eq!(exception.hresult, E_FAIL);
+            }
+            Err(error) => panic!("expected an exception, got {error}"),
+            Ok(_) => panic!("expected an exception"),
+        }
+        let error = book.get_value("Missing", vec![]).unwrap_err();
+        assert_eq!(error.hresult(), Some(DISP_E_UNKNOWNNAME));
+        let error = book.put_value("Sum", vec![], 1.into()).unwrap_err();
+        assert_eq!(error.hresult(), Some(DISP_E_MEMBERNOTFOUND));
+    }
+
+    // Wrapping a backend in a COM object needs the COM runtime.
+    #[cfg(windows)]
+    #[test]
+    fn identity_through_com() {
+        use std::rc::Rc;
+
+        use windows::core::Interface;
+
+        let book = workbook();
+        let dispatch = book.as_dispatch();
+        assert_eq!(book.as_dispatch().as_raw(), dispatch.as_raw());
+        let unwrapped = OleData::from_dispatch(dispatch);
+        assert!(Rc::ptr_eq(unwrapped.backend(), book.backend()));
+        assert_eq!(unwrapped, book);
+        assert_eq!(
+            value(unwrapped.get_value("Saved", vec![])),
+            Variant::Bool(true)
+        );
+    }
+}
@@ -0,0 +1,458 @@
+//! Running the message loop of a single-threaded apartment.
+//!
+//! An STA receives incoming calls and events as window messages, so a thread that owns STA
+//! objects has to pump messages while it waits. [`MessagePump`] runs the loop until a
+//! predicate holds, a deadline passes or a [`CancelToken`] fires, optionally waking up for
+//! kernel handles as well:
+//!
+//! ```ignore
+//! let closed = Rc::new(Cell::new(false));
+//! let flag = closed.clone();
+//! events.on_event("BeforeClose", move |_| {
+//!     flag.set(true);
+//!     Ok(())
+//! })?;
+//! MessagePump::new().run_until(|| closed.get());
+//! ```
+//!
+//! The loop only talks to the thread's queue through [`MessageSource`], so its scheduling can
+//! be driven by a scripted source and clock.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use windows::{
+    core::HRESULT,
+    Win32::{
+        Foundation::{HANDLE, HWND, WAIT_FAILED, WAIT_OBJECT_0},
+        UI::WindowsAndMessaging::{
+            DispatchMessageW, MsgWaitForMultipleObjectsEx, PeekMessageW, TranslateMessage, MSG,
+            MWMO_INPUTAVAILABLE, PM_REMOVE, QS_ALLINPUT, WM_QUIT,
+        },
+    },
+};
+
+/// Why a wait on a [`MessageSource`] returned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Wake {
+    /// A message is queued
+    Message,
+    /// The handle at this index was signalled
+    Handle(usize),
+    Timeout,
+    /// The wait itself failed, with this error
+    Failed(HRESULT),
+}
+
+/// The message queue of the current thread, as seen by a [`MessagePump`]
+pub trait MessageSource {
+    /// Remove the next queued message, if any
+    ///
+    fn peek(&mut self) -> Option<MSG>;
+    /// Deliver a message to its window procedure
+    ///
+    fn dispatch(&mut self, msg: &MSG);
+    /// Block until a message arrives, one of `handles` is signalled or `timeout` has passed
+    ///
+    fn wait(&mut self, handles: &[HANDLE], timeout: Duration) -> Wake;
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// The real queue of the calling thread
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ThreadMessages;
+
+impl MessageSource for ThreadMessages {
+    fn peek(&mut self) -> Option<MSG> {
+        let mut msg = MSG::default();
+        unsafe { PeekMessageW(&mut msg, HWND(0), 0, 0, PM_REMOVE) }
+            .as_bool()
+            .then_some(msg)
+    }
+    fn dispatch(&mut self, msg: &MSG) {
+        unsafe {
+            let _ = TranslateMessage(msg);
+            DispatchMessageW(msg);
+        }
+    }
+    fn wait(&mut self, handles: &[HANDLE], timeout: Duration) -> Wake {
+        let millis = timeout.as_millis().min(u32::MAX as u128 - 1) as u32;
+        let handles = (!handles.is_empty()).then_some(handles);
+        let count = handles.map_or(0, |handles| handles.len() as u32);
+        let result = unsafe {
+            MsgWaitForMultipleObjectsEx(handles, millis, QS_ALLINPUT, MWMO_INPUTAVAILABLE)
+        };
+        if result == WAIT_FAILED {
+            return Wake::Failed(windows::core::Error::from_win32().code());
+        }
+        match result.0.wrapping_sub(WAIT_OBJECT_0.0) {
+            index if index < count => Wake::Handle(index as usize),
+            index if index == count => Wake::Message,
+            // Also abandoned mutexes, after which the caller re-checks its conditions.
+            _ => Wake::Timeout,
+        }
+    }
+}
+
+/// Cancels a running [`MessagePump`] from any thread
+///
+/// The pump notices the cancellation the next time it wakes up, at the latest after its poll
+/// interval.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// Why a [`MessagePump`] stopped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PumpExit {
+    /// The predicate holds
+    Done,
+    TimedOut,
+    Cancelled,
+    /// The handle at this index, as added with [`MessagePump::wait_on`], was signalled
+    Signaled(usize),
+    /// `WM_QUIT` was received, with the exit code passed to `PostQuitMessage`
+    Quit(i32),
+    /// Waiting for messages failed, with this error
+    Failed(HRESULT),
+}
+
+type MessageFilter = Box<dyn FnMut(&MSG) -> bool>;
+
+/// A message loop for the current thread
+///
+/// Waits for messages with `MsgWaitForMultipleObjectsEx` rather than spinning, but wakes up
+/// at least every poll interval (50ms by default) to re-check its exit conditions.
+pub struct MessagePump<S: MessageSource = ThreadMessages> {
+    source: S,
+    handles: Vec<HANDLE>,
+    filters: Vec<MessageFilter>,
+    poll_interval: Duration,
+}
+
+impl Default for MessagePump {
+    fn default() -> Self {
+        MessagePump::with_source(ThreadMessages)
+    }
+}
+
+impl MessagePump {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<S: MessageSource> MessagePump<S> {
+    /// A pump reading from `source` instead of the thread's queue
+    ///
+    pub fn with_source(source: S) -> Self {
+        MessagePump {
+            source,
+            handles: vec![],
+            filters: vec![],
+            poll_interval: Duration::from_millis(50),
+        }
+    }
+    /// Stop with [`PumpExit::Signaled`] when `handle` is signalled
+    ///
+    pub fn wait_on(mut self, handle: HANDLE) -> Self {
+        self.handles.push(handle);
+        self
+    }
+    /// Inspect every message before it is dispatched. Returning `false` drops the message.
+    ///
+    pub fn filter(mut self, filter: impl FnMut(&MSG) -> bool + 'static) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+    /// Longest time to wait before re-checking the exit conditions
+    ///
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+    pub fn source_mut(&mut self) -> &mut S {
+        &mut self.source
+    }
+    /// Dispatch every message currently queued, without waiting
+    ///
+    /// Returns [`PumpExit::Quit`] if `WM_QUIT` was among them.
+    pub fn pump(&mut self) -> Option<PumpExit> {
+        while let Some(msg) = self.source.peek() {
+            if msg.message == WM_QUIT {
+                return Some(PumpExit::Quit(msg.wParam.0 as i32));
+            }
+            if self.filters.iter_mut().all(|filter| filter(&msg)) {
+                self.source.dispatch(&msg);
+            }
+        }
+        None
+    }
+    /// Pump messages until `done` returns `true`
+    ///
+    pub fn run_until(&mut self, done: impl FnMut() -> bool) -> PumpExit {
+        self.run(done, None, None)
+    }
+    /// Pump messages for `timeout`
+    ///
+    pub fn run_for(&mut self, timeout: Duration) -> PumpExit {
+        let deadline = self.source.now() + timeout;
+        self.run(|| false, Some(deadline), None)
+    }
+    pub fn run_until_deadline(&mut self, deadline: Instant) -> PumpExit {
+        self.run(|| false, Some(deadline), None)
+    }
+    pub fn run_until_cancelled(&mut self, token: &CancelToken) -> PumpExit {
+        self.run(|| false, None, Some(token))
+    }
+    /// Pump messages until `done` returns `true`, `deadline` passes or `token` is cancelled,
+    /// whichever comes first
+    ///
+    /// The conditions are checked after every batch of messages, so `done` may depend on
+    /// state that event handlers change.
+    pub fn run(
+        &mut self,
+        mut done: impl FnMut() -> bool,
+        deadline: Option<Instant>,
+        token: Option<&CancelToken>,
+    ) -> PumpExit {
+        loop {
+            if let Some(exit) = self.pump() {
+                return exit;
+            }
+            if done() {
+                return PumpExit::Done;
+            }
+            if token.is_some_and(CancelToken::is_cancelled) {
+                return PumpExit::Cancelled;
+            }
+            let mut timeout = self.poll_interval;
+            if let Some(deadline) = deadline {
+                let now = self.source.now();
+                if now >= deadline {
+                    return PumpExit::TimedOut;
+                }
+                timeout = timeout.min(deadline - now);
+            }
+            match self.source.wait(&self.handles, timeout) {
+                Wake::Handle(index) => return PumpExit::Signaled(index),
+                // Waiting again would fail again at once.
+                Wake::Failed(error) => return PumpExit::Failed(error),
+                Wake::Message | Wake::Timeout => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+    use windows::Win32::{
+        Foundation::{ERROR_INVALID_HANDLE, WPARAM},
+        UI::WindowsAndMessaging::WM_USER,
+    };
+
+    use super::*;
+
+    /// What the next wait of a [`Scripted`] source does
+    enum Step {
+        /// A message is posted after this long
+        Message(Duration, u32),
+        /// The handle at this index is signalled after this long
+        Signal(Duration, usize),
+        /// The wait fails after this long
+        Fail(Duration, HRESULT),
+    }
+
+    /// What the pump did with a [`Scripted`] source
+    #[derive(Default)]
+    struct Log {
+        dispatched: Vec<u32>,
+        timeouts: Vec<Duration>,
+        handles: Vec<usize>,
+    }
+
+    /// A queue and clock driven by a script
+    struct Scripted {
+        start: Instant,
+        elapsed: Duration,
+        queue: VecDeque<MSG>,
+        steps: VecDeque<Step>,
+        log: Rc<RefCell<Log>>,
+    }
+
+    impl Scripted {
+        fn new(queued: &[u32], steps: Vec<Step>) -> (Scripted, Rc<RefCell<Log>>) {
+            let log = Rc::new(RefCell::new(Log::default()));
+            let source = Scripted {
+                start: Instant::now(),
+                elapsed: Duration::ZERO,
+                queue: queued.iter().map(|message| msg(*message)).collect(),
+                steps: steps.into(),
+                log: log.clone(),
+            };
+            (source, log)
+        }
+    }
+
+    fn msg(message: u32) -> MSG {
+        MSG {
+            message,
+            ..Default::default()
+        }
+    }
+
+    impl MessageSource for Scripted {
+        fn peek(&mut self) -> Option<MSG> {
+            self.queue.pop_front()
+        }
+        fn dispatch(&mut self, msg: &MSG) {
+            self.log.borrow_mut().dispatched.push(msg.message);
+        }
+        fn wait(&mut self, handles: &[HANDLE], timeout: Duration) -> Wake {
+            let mut log = self.log.borrow_mut();
+            log.timeouts.push(timeout);
+            log.handles.push(handles.len());
+            let Some(step) = self.steps.front_mut() else {
+                self.elapsed += timeout;
+                return Wake::Timeout;
+            };
+            let (Step::Message(after, _) | Step::Signal(after, _) | Step::Fail(after, _)) = step;
+            if *after > timeout {
+                *after -= timeout;
+                self.elapsed += timeout;
+                return Wake::Timeout;
+            }
+            self.elapsed += *after;
+            match self.steps.pop_front().unwrap() {
+                Step::Message(_, message) => {
+                    self.queue.push_back(msg(message));
+                    Wake::Message
+                }
+                Step::Signal(_, index) => Wake::Handle(index),
+                Step::Fail(_, error) => Wake::Failed(error),
+            }
+        }
+        fn now(&self) -> Instant {
+            self.start + self.elapsed
+        }
+    }
+
+    const A: u32 = WM_USER + 1;
+    const B: u32 = WM_USER + 2;
+    const C: u32 = WM_USER + 3;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn pump_dispatches_through_filters() {
+        let (source, log) = Scripted::new(&[A, B, C], vec![]);
+        let mut pump = MessagePump::with_source(source).filter(|msg| msg.message != B);
+        assert_eq!(pump.pump(), None);
+        assert_eq!(log.borrow().dispatched, [A, C]);
+        assert!(log.borrow().timeouts.is_empty());
+    }
+
+    #[test]
+    fn quit_stops_before_later_messages() {
+        let (mut source, log) = Scripted::new(&[A], vec![]);
+        source.queue.push_back(MSG {
+            message: WM_QUIT,
+            wParam: WPARAM(7),
+            ..Default::default()
+        });
+        source.queue.push_back(msg(B));
+        let mut pump = MessagePump::with_source(source);
+        assert_eq!(pump.run_until(|| false), PumpExit::Quit(7));
+        assert_eq!(log.borrow().dispatched, [A]);
+        assert_eq!(pump.source().queue.len(), 1);
+    }
+
+    #[test]
+    fn run_for_waits_in_poll_intervals() {
+        let (source, log) = Scripted::new(&[], vec![]);
+        let mut pump = MessagePump::with_source(source);
+        assert_eq!(pump.run_for(ms(120)), PumpExit::TimedOut);
+        assert_eq!(log.borrow().timeouts, [ms(50), ms(50), ms(20)]);
+        assert_eq!(pump.source().elapsed, ms(120));
+    }
+
+    #[test]
+    fn run_until_checks_after_each_batch() {
+        let (source, log) = Scripted::new(&[], vec![Step::Message(ms(70), A)]);
+        let mut pump = MessagePump::with_source(source).poll_interval(ms(30));
+        let seen = log.clone();
+        let exit = pump.run_until(|| seen.borrow().dispatched.contains(&A));
+        assert_eq!(exit, PumpExit::Done);
+        assert_eq!(log.borrow().timeouts, [ms(30), ms(30), ms(30)]);
+        assert_eq!(pump.source().elapsed, ms(70));
+    }
+
+    #[test]
+    fn message_arriving_before_deadline_is_dispatched() {
+        let (source, log) = Scripted::new(&[], vec![Step::Message(ms(10), A)]);
+        let mut pump = MessagePump::with_source(source);
+        assert_eq!(pump.run_for(ms(40)), PumpExit::TimedOut);
+        assert_eq!(log.borrow().dispatched, [A]);
+        assert_eq!(log.borrow().timeouts, [ms(40), ms(30)]);
+    }
+
+    #[test]
+    fn signalled_handle_stops_the_pump() {
+        let steps = vec![Step::Message(ms(5), A), Step::Signal(ms(5), 1)];
+        let (source, log) = Scripted::new(&[], steps);
+        let mut pump = MessagePump::with_source(source)
+            .wait_on(HANDLE::default())
+            .wait_on(HANDLE::default());
+        assert_eq!(pump.run_until(|| false), PumpExit::Signaled(1));
+        assert_eq!(log.borrow().dispatched, [A]);
+        assert_eq!(log.borrow().handles, [2, 2]);
+    }
+
+    #[test]
+    fn failed_wait_stops_the_pump() {
+        let error = HRESULT::from_win32(ERROR_INVALID_HANDLE.0);
+        let steps = vec![Step::Message(ms(5), A), Step::Fail(ms(0), error)];
+        let (source, log) = Scripted::new(&[], steps);
+        let mut pump = MessagePump::with_source(source).wait_on(HANDLE::default());
+        assert_eq!(pump.run_until(|| false), PumpExit::Failed(error));
+        assert_eq!(log.borrow().dispatched, [A]);
+        assert_eq!(log.borrow().timeouts.len(), 2);
+    }
+
+    #[test]
+    fn cancellation() {
+        let (source, log) = Scripted::new(&[A], vec![]);
+        let mut pump = MessagePump::with_source(source);
+        let token = CancelToken::new();
+        token.cancel();
+        assert_eq!(pump.run_until_cancelled(&token), PumpExit::Cancelled);
+        // Messages already queued are dispatched first.
+        assert_eq!(log.borrow().dispatched, [A]);
+        assert!(log.borrow().timeouts.is_empty());
+    }
+}
@@ -1,30 +1,51 @@
-use std::{ffi::OsStr, ptr};
+use std::{ffi::OsStr, ptr, rc::Rc};
 
 use windows::{
-    core::{Interface, BSTR, GUID, PCWSTR},
+    core::{Interface, BSTR},
     Win32::{
-        Foundation::{DISP_E_EXCEPTION, DISP_E_PARAMNOTFOUND, DISP_E_TYPEMISMATCH},
-        Globalization::GetUserDefaultLCID,
+        Foundation::{DISP_E_MEMBERNOTFOUND, DISP_E_UNKNOWNNAME, E_FAIL, E_NOINTERFACE},
         System::{
             Com::{
                 IDispatch, ITypeInfo, ITypeLib, DISPATCH_FLAGS, DISPATCH_METHOD,
-                DISPATCH_PROPERTYGET, DISPATCH_PROPERTYPUT, DISPPARAMS, EXCEPINFO, INVOKE_FUNC,
-                INVOKE_PROPERTYGET, INVOKE_PROPERTYPUT, INVOKE_PROPERTYPUTREF,
+                DISPATCH_PROPERTYGET, DISPATCH_PROPERTYPUT, DISPATCH_PROPERTYPUTREF, DISPPARAMS,
+                INVOKEKIND, INVOKE_FUNC, INVOKE_PROPERTYGET, INVOKE_PROPERTYPUT,
+                INVOKE_PROPERTYPUTREF, TKIND_ENUM, TYPEDESC,
+            },
+            Ole::{
+                DISPID_EVALUATE, DISPID_NEWENUM, DISPID_PROPERTYPUT, DISPID_UNKNOWN, DISPID_VALUE,
+            },
+            Variant::{
+                VariantChangeType, VARENUM, VARIANT, VAR_CHANGE_FLAGS, VT_BOOL, VT_BSTR, VT_CY,
+                VT_DATE, VT_DECIMAL, VT_DISPATCH, VT_EMPTY, VT_I1, VT_I2, VT_I4, VT_I8, VT_INT,
+                VT_PTR, VT_R4, VT_R8, VT_UI1, VT_UI2, VT_UI4, VT_UI8, VT_UINT, VT_UNKNOWN,
+                VT_USERDEFINED,
             },
-            Ole::DISPID_PROPERTYPUT,
-            Variant::VARIANT,
         },
     },
 };
 
 use crate::{
-    error::{ComArgumentErrorType, Error, OleError, Result},
+    backend::{
+        invoke_native, invoke_with_params, BackendDispatch, DispatchBackend, NativeDispatch,
+    },
+    dispids::DispIdCache,
+    error::{Error, OleError, Result},
+    hresult,
+    oleenum::{enumerator_from_variant, OleEnum},
     olemethoddata::{ole_methods_from_typeinfo, OleMethodData},
+    oleparamdata::OleParamData,
+    path::{self, PathArg, PathExpr, SegmentKind},
+    record,
     types::OleClassNames,
     util::{
-        conv::ToWide,
+        ole::TypeRef,
         ole::{create_com_object, get_class_id},
+        variant::{
+            byref_variant, dispparams_args, is_missing_argument, missing_argument,
+            typed_byref_variant, variant_dispatch, vartype_name,
+        },
     },
+    value::{IntoVariant, Variant},
     OleTypeData, OleTypeLibData,
 };
 
@@ -45,61 +66,179 @@ where
     ShowHTMLDialogEx(hwndparent.into(), moniker, dialogflags, variant_arg_in)
 }*/
 
+#[derive(Clone)]
 pub struct OleData {
-    pub dispatch: IDispatch,
+    pub(crate) backend: Rc<dyn DispatchBackend>,
+    pub(crate) dispids: DispIdCache,
 }
-impl OleData {
-    pub fn new<S: AsRef<OsStr>>(prog_id: S) -> Result<Self> {
-        Ok(OleData {
-            dispatch: create_com_object(prog_id)?,
-        })
+
+impl std::fmt::Debug for OleData {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "OleData({:#x})", self.identity())
     }
-    pub fn get_ids_of_names<S: AsRef<OsStr> + Copy>(&self, names: &[S]) -> Result<Vec<i32>> {
-        let namelen = names.len();
-        let mut wnames = vec![PCWSTR::null(); namelen];
-        for i in 0..namelen {
-            let a = names[i].to_wide_null();
-            wnames[i] = PCWSTR(a.as_ptr());
-        }
+}
 
-        let mut dispids = 0;
+impl PartialEq for OleData {
+    fn eq(&self, other: &Self) -> bool {
+        self.same_object(other)
+    }
+}
 
-        unsafe {
-            self.dispatch.GetIDsOfNames(
-                &GUID::zeroed(),
-                wnames.as_ptr(),
-                wnames.len() as u32,
-                GetUserDefaultLCID(),
-                &mut dispids,
-            )
-        }?;
+/// An argument to [`OleData::call_args`]
+///
+/// `ByRef` arguments are passed as `VT_BYREF | VT_VARIANT` pointing at the caller's
+/// `VARIANT`, so values written back by the server are visible once the call returns.
+pub enum Argument<'a> {
+    ByVal(VARIANT),
+    ByRef(&'a mut VARIANT),
+}
 
-        let ids = unsafe { Vec::from_raw_parts(&mut dispids, wnames.len(), wnames.len()) };
+impl<'a> Argument<'a> {
+    fn into_variant(self) -> VARIANT {
+        match self {
+            Argument::ByVal(value) => value,
+            Argument::ByRef(target) => byref_variant(target),
+        }
+    }
+}
 
+impl<'a> From<VARIANT> for Argument<'a> {
+    fn from(value: VARIANT) -> Self {
+        Argument::ByVal(value)
+    }
+}
+
+impl<'a> From<&'a mut VARIANT> for Argument<'a> {
+    fn from(target: &'a mut VARIANT) -> Self {
+        Argument::ByRef(target)
+    }
+}
+impl OleData {
+    pub fn new<S: AsRef<OsStr>>(prog_id: S) -> Result<Self> {
+        if let Some(replayed) = record::replay_create(prog_id.as_ref()) {
+            return replayed;
+        }
+        let object = OleData::from_dispatch(create_com_object(&prog_id)?);
+        record::record_create(prog_id.as_ref(), &object);
+        Ok(object)
+    }
+    /// Wrap a COM object
+    ///
+    /// Wrappers created by [`as_dispatch`](OleData::as_dispatch) are unwrapped to their
+    /// backend, so objects passed through a server keep their identity.
+    pub fn from_dispatch(dispatch: IDispatch) -> OleData {
+        match BackendDispatch::unwrap(&dispatch) {
+            Some(backend) => OleData {
+                backend,
+                dispids: DispIdCache::default(),
+            },
+            None => OleData::from_backend(NativeDispatch::new(dispatch)),
+        }
+    }
+    /// Wrap an object implemented by any [`DispatchBackend`]
+    ///
+    pub fn from_backend<B: DispatchBackend + 'static>(backend: B) -> OleData {
+        OleData {
+            backend: Rc::new(backend),
+            dispids: DispIdCache::default(),
+        }
+    }
+    /// The COM object behind this `OleData`, if it is backed by one
+    ///
+    pub fn dispatch(&self) -> Option<IDispatch> {
+        self.backend.dispatch()
+    }
+    /// An `IDispatch` for this object, wrapping Rust backends so they can be passed to COM
+    ///
+    pub fn as_dispatch(&self) -> IDispatch {
+        BackendDispatch::wrap(self.backend.clone())
+    }
+    pub fn backend(&self) -> &Rc<dyn DispatchBackend> {
+        &self.backend
+    }
+    /// Whether two values refer to the same object
+    ///
+    pub fn same_object(&self, other: &OleData) -> bool {
+        self.identity() == other.identity()
+    }
+    pub(crate) fn identity(&self) -> usize {
+        self.backend.identity()
+    }
+    pub fn get_ids_of_names<S: AsRef<OsStr>>(&self, names: &[S]) -> Result<Vec<i32>> {
+        let (result, ids) = self.raw_ids_of_names(names);
+        result?;
+        record::record_names(self, names, &ids);
         Ok(ids)
     }
-    pub fn responds_to<S: AsRef<OsStr>>(&self, method: S) -> bool {
-        let method = method.to_wide_null();
-        let methods = [PCWSTR(method.as_ptr())];
-        let mut dispids = 0;
-
-        unsafe {
-            self.dispatch
-                .GetIDsOfNames(
-                    &GUID::zeroed(),
-                    methods.as_ptr(),
-                    1,
-                    GetUserDefaultLCID(),
-                    &mut dispids,
-                )
-                .is_ok()
+    fn raw_ids_of_names<S: AsRef<OsStr>>(&self, names: &[S]) -> (Result<()>, Vec<i32>) {
+        if let Some(ids) = self.dispids.get(names) {
+            return (Ok(()), ids);
         }
+        let names_owned: Vec<String> = names
+            .iter()
+            .map(|name| name.as_ref().to_string_lossy().into_owned())
+            .collect();
+        let (result, dispids) = self.backend.ids_of_names(&names_owned);
+        if result.is_ok() {
+            self.dispids.insert(names, &dispids);
+        }
+
+        (result, dispids)
+    }
+    /// Share name to DISPID mappings with every other object of the same type on this
+    /// thread, keyed by the GUID of the object's type info
+    ///
+    /// Every `OleData` starts with its own empty cache, so objects returned by properties or
+    /// collections resolve each name again. Keying the cache costs `GetTypeInfo` plus
+    /// `GetTypeAttr`, which for an out-of-process server is more round trips than the single
+    /// `GetIDsOfNames` it saves; it pays off for objects that call several members. When the
+    /// objects are known to be of one type, [`share_dispids_with`](OleData::share_dispids_with)
+    /// shares without any round trip.
+    pub fn share_dispids_by_type(&mut self) -> Result<()> {
+        let typeinfo = self.get_type_info()?;
+        let type_attr = unsafe { typeinfo.GetTypeAttr()? };
+        let guid = unsafe { (*type_attr).guid };
+        unsafe { typeinfo.ReleaseTypeAttr(type_attr) };
+        self.dispids = DispIdCache::for_type(guid.to_u128());
+        Ok(())
+    }
+    /// Use the DISPID cache of `other`, typically an earlier item of the same collection
+    ///
+    /// The caller vouches that both objects are of the same type; names are not re-checked.
+    pub fn share_dispids_with(&mut self, other: &OleData) {
+        self.dispids = other.dispids.clone();
+    }
+    /// Fill the DISPID cache with every member described by the object's type library
+    ///
+    pub fn prefill_dispids(&self) -> Result<()> {
+        for method in self.ole_methods()? {
+            self.dispids.insert(&[method.name()], &[method.dispid()]);
+        }
+        Ok(())
+    }
+    /// Forget the cached DISPID of `name`, for objects such as `IDispatchEx` implementations
+    /// whose members can change at runtime
+    ///
+    pub fn forget_dispid<S: AsRef<OsStr>>(&self, name: S) {
+        self.dispids.remove(name);
+    }
+    /// Forget every cached DISPID of this object
+    ///
+    pub fn forget_dispids(&self) {
+        self.dispids.clear();
+    }
+    pub fn responds_to<S: AsRef<OsStr>>(&self, method: S) -> bool {
+        self.get_ids_of_names(&[method]).is_ok()
     }
     fn get_type_info(&self) -> Result<ITypeInfo> {
-        let typeinfo = unsafe { self.dispatch.GetTypeInfo(0, GetUserDefaultLCID()) };
+        let typeinfo = self.backend.type_info();
         match typeinfo {
             Ok(typeinfo) => Ok(typeinfo),
-            Err(error) => Err(OleError::interface(error, "failed to GetTypeInfo").into()),
+            Err(error) => Err(OleError::interface(
+                error.hresult().unwrap_or(E_FAIL),
+                "failed to GetTypeInfo",
+            )
+            .into()),
         }
     }
     pub fn ole_type(&self) -> Result<OleTypeData> {
@@ -163,15 +302,18 @@ impl OleData {
     }
     pub fn ole_query_interface<S: AsRef<OsStr>>(&self, str_iid: S) -> Result<OleData> {
         let iid = get_class_id(str_iid)?;
+        let Some(dispatch) = self.dispatch() else {
+            return Err(Error::Windows(E_NOINTERFACE.into()));
+        };
         let mut dispatch_interface = ptr::null_mut();
-        let result = unsafe { self.dispatch.query(&iid, &mut dispatch_interface) };
+        let result = unsafe { dispatch.query(&iid, &mut dispatch_interface) };
         let result = result.ok();
         if let Err(error) = result {
             Err(error.into())
         } else {
             let dispatch: IDispatch =
                 unsafe { <IDispatch as Interface>::from_raw(dispatch_interface as *mut _) };
-            Ok(OleData { dispatch })
+            Ok(OleData::from_dispatch(dispatch))
         }
     }
     pub fn ole_method_help<S: AsRef<OsStr>>(&self, cmdname: S) -> Result<OleMethodData> {
@@ -194,45 +336,130 @@ impl OleData {
         }
     }
 
-    pub fn invoke<S: AsRef<OsStr> + Copy>(
+    pub fn invoke<S: AsRef<OsStr>>(
         &self,
         name: S,
         dp: &mut DISPPARAMS,
         flags: DISPATCH_FLAGS,
     ) -> Result<VARIANT> {
         let ids = self.get_ids_of_names(&[name])?;
+        self.invoke_dispid(ids[0], dp, flags)
+    }
 
-        let mut excep = EXCEPINFO::default();
-        let mut arg_err = 0;
-        let mut result = VARIANT::default();
-
-        let res = unsafe {
-            self.dispatch.Invoke(
-                ids[0],
-                &GUID::zeroed(),
-                0x0800, /*LOCALE_SYSTEM_DEFAULT*/
+    /// Invoke a member by DISPID, including the reserved ones such as `DISPID_VALUE`,
+    /// `DISPID_NEWENUM` or `DISPID_EVALUATE`
+    ///
+    pub fn invoke_dispid(
+        &self,
+        dispid: i32,
+        dp: &mut DISPPARAMS,
+        flags: DISPATCH_FLAGS,
+    ) -> Result<VARIANT> {
+        let recording = record::begin_call(self, dispid, dp, flags);
+        let result = match self.backend.dispatch() {
+            Some(dispatch) => invoke_native(
+                &dispatch,
+                self.backend.dispatch_ex().as_ref(),
+                dispid,
                 flags,
                 dp,
-                Some(&mut result),
-                Some(&mut excep),
-                Some(&mut arg_err),
-            )
+            ),
+            None => invoke_with_params(self.backend.as_ref(), dispid, flags, dp)
+                .map(Variant::into_variant),
         };
+        if let Some(recording) = recording {
+            recording.finish(result.as_ref());
+        }
+        result.map_err(|error| {
+            let (rgvarg, named) = dispparams_args(dp);
+            let described = matches!(error, Error::Exception(_) | Error::IDispatchArgument { .. });
+            let method = described.then(|| self.type_member(dispid)).flatten();
+            let member = method.as_ref().map(|method| method.name().to_string());
+            self.describe_error(error, dispid, member, named, rgvarg.len(), |position| {
+                method
+                    .as_ref()
+                    .and_then(|method| method.params().into_iter().nth(position))
+                    .and_then(|param| param.ok())
+                    .map(|param| param.name().to_string())
+            })
+        })
+    }
 
-        match res {
-            Ok(()) => Ok(result),
-            Err(e) => Err(match e.code() {
-                DISP_E_EXCEPTION => Error::Exception(excep),
-                DISP_E_TYPEMISMATCH => Error::IDispatchArgument {
-                    error_type: ComArgumentErrorType::TypeMismatch,
-                    arg_err,
-                },
-                DISP_E_PARAMNOTFOUND => Error::IDispatchArgument {
-                    error_type: ComArgumentErrorType::ParameterNotFound,
-                    arg_err,
-                },
-                _ => e.into(),
-            }),
+    /// Invoke a member by DISPID with Rust values
+    ///
+    /// `args` are in `DISPPARAMS::rgvarg` order: the named arguments first, in the order of
+    /// `named`, then the positional ones from last to first. Values the object passes back by
+    /// reference are written into `args`. Errors name the member and argument by the names the
+    /// member was resolved under, without consulting type information.
+    pub fn invoke_value(
+        &self,
+        dispid: i32,
+        flags: DISPATCH_FLAGS,
+        args: &mut [Variant],
+        named: &[i32],
+    ) -> Result<Variant> {
+        let recording = record::begin_call_with(self, dispid, flags, args, named);
+        let result = self.backend.invoke(dispid, flags, args, named);
+        if let Some(recording) = recording {
+            recording.finish_value(result.as_ref());
+        }
+        result
+            .map_err(|error| self.describe_error(error, dispid, None, named, args.len(), |_| None))
+    }
+
+    /// The type library's description of member `dispid`, if there is one
+    ///
+    fn type_member(&self, dispid: i32) -> Option<OleMethodData> {
+        self.ole_methods()
+            .ok()?
+            .into_iter()
+            .find(|method| method.dispid() == dispid)
+    }
+
+    /// Name the member and the rejected argument of an exception or argument error
+    ///
+    /// `member` and `param_name`, which names the parameter at a source position, come from the
+    /// type information when there is any. Otherwise the member is named as it was resolved and
+    /// the argument as `#n`.
+    fn describe_error(
+        &self,
+        error: Error,
+        dispid: i32,
+        member: Option<String>,
+        named: &[i32],
+        count: usize,
+        param_name: impl Fn(usize) -> Option<String>,
+    ) -> Error {
+        let member = member.or_else(|| self.dispids.name_of(dispid));
+        let argument = |arg_err: Option<u32>| {
+            arg_err.map(|arg_err| {
+                // Named arguments come first in rgvarg, followed by the positional ones reversed.
+                let position = match named.get(arg_err as usize) {
+                    Some(&id) => id as usize,
+                    None => count.saturating_sub(arg_err as usize + 1),
+                };
+                param_name(position).unwrap_or_else(|| format!("#{}", position + 1))
+            })
+        };
+        match error {
+            Error::Exception(exception) => {
+                let argument = argument(exception.arg_err);
+                (*exception)
+                    .with_member(member, dispid)
+                    .with_argument(argument)
+                    .into()
+            }
+            Error::IDispatchArgument {
+                error_type,
+                arg_err,
+                ..
+            } => Error::IDispatchArgument {
+                error_type,
+                arg_err,
+                member,
+                argument: argument(arg_err),
+            },
+            error => error,
         }
     }
 
@@ -243,9 +470,38 @@ impl OleData {
         self.invoke(name, &mut dp, DISPATCH_PROPERTYGET)
     }
 
+    /// Get a parameterized property from a COM object, such as `Range("A1:B2")` or
+    /// `Cells(row, col)`
+    ///
+    pub fn get_with(&self, name: &str, args: Vec<VARIANT>) -> Result<VARIANT> {
+        let dispid = self.dispid_of(name)?;
+        self.get_by_id(dispid, args)
+    }
+
+    /// Get a property by DISPID, passing any index arguments
+    ///
+    pub fn get_by_id(&self, dispid: i32, args: Vec<VARIANT>) -> Result<VARIANT> {
+        let mut rgvarg: Vec<VARIANT> = args.into_iter().rev().collect();
+        let mut dp = DISPPARAMS {
+            rgvarg: rgvarg.as_mut_ptr(),
+            cArgs: rgvarg.len() as u32,
+            ..Default::default()
+        };
+        self.invoke_dispid(
+            dispid,
+            &mut dp,
+            DISPATCH_FLAGS(DISPATCH_PROPERTYGET.0 | DISPATCH_METHOD.0),
+        )
+    }
+
     /// Set a property on a COM object
     ///
+    /// Object values are assigned with `DISPATCH_PROPERTYPUTREF` unless the type library
+    /// says the property only supports `DISPATCH_PROPERTYPUT`. Without type information both
+    /// flags are passed and the server picks.
     pub fn put(&self, name: &str, value: &mut VARIANT) -> Result<()> {
+        let dispid = self.dispid_of(name)?;
+        let flags = self.put_flags(dispid, value);
         let mut dp = DISPPARAMS {
             cArgs: 1,
             rgvarg: value,
@@ -254,10 +510,278 @@ impl OleData {
         };
         let mut id = DISPID_PROPERTYPUT;
         dp.rgdispidNamedArgs = &mut id as *mut _;
-        self.invoke(name, &mut dp, DISPATCH_PROPERTYPUT)?;
+        self.invoke_dispid(dispid, &mut dp, flags)?;
         Ok(())
     }
 
+    /// Set a parameterized property on a COM object, such as `Range("A1").Value`
+    ///
+    pub fn put_with(&self, name: &str, args: Vec<VARIANT>, value: VARIANT) -> Result<()> {
+        let dispid = self.dispid_of(name)?;
+        self.put_by_id(dispid, args, value)
+    }
+
+    /// Set a property by DISPID, passing any index arguments
+    ///
+    pub fn put_by_id(&self, dispid: i32, args: Vec<VARIANT>, value: VARIANT) -> Result<()> {
+        let flags = self.put_flags(dispid, &value);
+        // The value being assigned is the named DISPID_PROPERTYPUT argument, so it goes
+        // first, followed by the index arguments in reverse order.
+        let mut rgvarg = vec![value];
+        rgvarg.extend(args.into_iter().rev());
+        let mut id = DISPID_PROPERTYPUT;
+        let mut dp = DISPPARAMS {
+            rgvarg: rgvarg.as_mut_ptr(),
+            rgdispidNamedArgs: &mut id,
+            cArgs: rgvarg.len() as u32,
+            cNamedArgs: 1,
+        };
+        self.invoke_dispid(dispid, &mut dp, flags)?;
+        Ok(())
+    }
+
+    /// Get a property with Rust values, passing any index arguments in source order
+    ///
+    pub fn get_value(&self, name: &str, args: Vec<Variant>) -> Result<Variant> {
+        let dispid = self.dispid_of(name)?;
+        let mut args: Vec<Variant> = args.into_iter().rev().collect();
+        self.invoke_value(
+            dispid,
+            DISPATCH_FLAGS(DISPATCH_PROPERTYGET.0 | DISPATCH_METHOD.0),
+            &mut args,
+            &[],
+        )
+    }
+
+    /// Set a property with Rust values, passing any index arguments in source order
+    ///
+    /// Object values are assigned with the flags learnt for the property, if any, and
+    /// otherwise with both `DISPATCH_PROPERTYPUT` and `DISPATCH_PROPERTYPUTREF`, leaving the
+    /// choice to the object.
+    pub fn put_value(&self, name: &str, args: Vec<Variant>, value: Variant) -> Result<()> {
+        let dispid = self.dispid_of(name)?;
+        let flags = match value {
+            Variant::Object(_) | Variant::Nothing => self.dispids.put_flags(dispid).unwrap_or(
+                DISPATCH_FLAGS(DISPATCH_PROPERTYPUT.0 | DISPATCH_PROPERTYPUTREF.0),
+            ),
+            _ => DISPATCH_PROPERTYPUT,
+        };
+        let mut rgvarg = vec![value];
+        rgvarg.extend(args.into_iter().rev());
+        self.invoke_value(dispid, flags, &mut rgvarg, &[DISPID_PROPERTYPUT])?;
+        Ok(())
+    }
+
+    /// Call a method with Rust values, passing the arguments in source order
+    ///
+    pub fn call_value(&self, name: &str, args: Vec<Variant>) -> Result<Variant> {
+        let dispid = self.dispid_of(name)?;
+        let mut args: Vec<Variant> = args.into_iter().rev().collect();
+        self.invoke_value(dispid, DISPATCH_METHOD, &mut args, &[])
+    }
+
+    fn put_flags(&self, dispid: i32, value: &VARIANT) -> DISPATCH_FLAGS {
+        if value.vt() != VT_DISPATCH && value.vt() != VT_UNKNOWN {
+            return DISPATCH_PROPERTYPUT;
+        }
+        if let Some(flags) = self.dispids.put_flags(dispid) {
+            return flags;
+        }
+        let invkinds: Vec<INVOKEKIND> = match self.ole_put_methods() {
+            Ok(methods) => methods
+                .iter()
+                .filter(|method| method.dispid() == dispid)
+                .map(|method| method.invkind())
+                .collect(),
+            Err(_) => vec![],
+        };
+        let put = invkinds.contains(&INVOKE_PROPERTYPUT);
+        let putref = invkinds.contains(&INVOKE_PROPERTYPUTREF);
+        let flags = match (put, putref) {
+            (true, false) => DISPATCH_PROPERTYPUT,
+            (_, true) => DISPATCH_PROPERTYPUTREF,
+            // Without type info let the server pick, as Ruby's win32ole does.
+            (false, false) => DISPATCH_FLAGS(DISPATCH_PROPERTYPUT.0 | DISPATCH_PROPERTYPUTREF.0),
+        };
+        self.dispids.insert_put_flags(dispid, flags);
+        flags
+    }
+
+    fn dispid_of(&self, name: &str) -> Result<i32> {
+        let ids = self.get_ids_of_names(&[name])?;
+        Ok(ids[0])
+    }
+
+    /// Get the object's default value (`DISPID_VALUE`), as in VBA's `coll(3)`
+    ///
+    pub fn get_default(&self, args: Vec<VARIANT>) -> Result<VARIANT> {
+        self.get_by_id(DISPID_VALUE, args)
+    }
+
+    /// Set the object's default value (`DISPID_VALUE`), as in VBA's `ws.Range("A1") = 5`
+    ///
+    pub fn put_default(&self, args: Vec<VARIANT>, value: VARIANT) -> Result<()> {
+        self.put_by_id(DISPID_VALUE, args, value)
+    }
+
+    /// Call the object's default member (`DISPID_VALUE`) as a method
+    ///
+    pub fn call_default(&self, args: Vec<VARIANT>) -> Result<VARIANT> {
+        self.call_by_id(DISPID_VALUE, args)
+    }
+
+    /// Evaluate an expression through `DISPID_EVALUATE`, as Excel does for `[A1]`
+    ///
+    pub fn evaluate(&self, expression: &str) -> Result<VARIANT> {
+        self.get_by_id(DISPID_EVALUATE, vec![VARIANT::from(expression)])
+    }
+
+    /// The type library's description of the default member (`[id(0)]`), if any
+    ///
+    pub fn default_member(&self) -> Result<Option<OleMethodData>> {
+        let mut methods: Vec<OleMethodData> = self
+            .ole_methods()?
+            .into_iter()
+            .filter(|method| method.dispid() == DISPID_VALUE)
+            .collect();
+        // Prefer the getter when the default member is a read/write property.
+        let getter = methods.iter().position(|method| {
+            method.invkind() != INVOKE_PROPERTYPUT && method.invkind() != INVOKE_PROPERTYPUTREF
+        });
+        Ok(match getter {
+            Some(index) => Some(methods.swap_remove(index)),
+            None => methods.pop(),
+        })
+    }
+
+    /// Iterate over the members of a collection object
+    ///
+    /// Uses the `IEnumVARIANT` returned by `_NewEnum`, and falls back to `Count` and `Item`
+    /// for objects without a `_NewEnum` member. Any other failure of `_NewEnum` is returned.
+    pub fn iter(&self) -> Result<OleEnum> {
+        let mut dp = DISPPARAMS::default();
+        let result = self.invoke_dispid(
+            DISPID_NEWENUM,
+            &mut dp,
+            DISPATCH_FLAGS(DISPATCH_METHOD.0 | DISPATCH_PROPERTYGET.0),
+        );
+        match result {
+            Ok(result) => match enumerator_from_variant(&result) {
+                Some(enumerator) => Ok(OleEnum::from_enumerator(enumerator)),
+                None => Err(Error::Custom(
+                    "_NewEnum did not return an IEnumVARIANT".into(),
+                )),
+            },
+            Err(ref error)
+                if matches!(
+                    error.hresult(),
+                    Some(DISP_E_MEMBERNOTFOUND | DISP_E_UNKNOWNNAME)
+                ) =>
+            {
+                OleEnum::from_indexed(self.clone())
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Evaluate a VB-like member path against this object, such as
+    /// `Workbooks(1).Worksheets("Data").Range("A1:C10").Value`
+    ///
+    /// A path ending in `= value` assigns the value to the last member and returns `VT_EMPTY`.
+    /// Paths nested inside argument lists are evaluated against this object as well.
+    pub fn eval(&self, expression: &str) -> Result<VARIANT> {
+        let path = path::parse(expression).map_err(|error| Error::Expression {
+            expression: expression.to_string(),
+            position: error.position,
+            message: error.message,
+            source: None,
+        })?;
+        self.eval_path(expression, &path)
+    }
+
+    fn eval_path(&self, expression: &str, path: &PathExpr) -> Result<VARIANT> {
+        let mut assignment = match path.assignment {
+            Some(ref value) => Some(self.eval_arg(expression, value)?),
+            None => None,
+        };
+        let mut current = self.clone();
+        let mut result = VARIANT::default();
+        let last = path.segments.len() - 1;
+        for (i, segment) in path.segments.iter().enumerate() {
+            let fail = |error: Error| Error::Expression {
+                expression: expression.to_string(),
+                position: segment.position,
+                message: format!("`{}` failed", segment.text),
+                source: Some(Box::new(error)),
+            };
+            if i > 0 {
+                current = OleData::try_from(&result).map_err(fail)?;
+            }
+            let mut calls = vec![];
+            for args in &segment.calls {
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval_arg(expression, arg))
+                    .collect::<Result<Vec<VARIANT>>>()?;
+                calls.push(args);
+            }
+            if i == last {
+                if let Some(value) = assignment.take() {
+                    return current
+                        .assign_segment(&segment.kind, calls, value)
+                        .map(|_| VARIANT::default())
+                        .map_err(fail);
+                }
+            }
+            result = current.eval_segment(&segment.kind, calls).map_err(fail)?;
+        }
+        Ok(result)
+    }
+
+    fn eval_segment(&self, kind: &SegmentKind, calls: Vec<Vec<VARIANT>>) -> Result<VARIANT> {
+        let mut calls = calls.into_iter();
+        let mut result = match kind {
+            SegmentKind::Member(name) => self.get_with(name, calls.next().unwrap_or_default())?,
+            SegmentKind::Evaluate(text) => self.evaluate(text)?,
+        };
+        for args in calls {
+            result = OleData::try_from(&result)?.get_default(args)?;
+        }
+        Ok(result)
+    }
+
+    fn assign_segment(
+        &self,
+        kind: &SegmentKind,
+        mut calls: Vec<Vec<VARIANT>>,
+        value: VARIANT,
+    ) -> Result<()> {
+        if let SegmentKind::Member(name) = kind {
+            if calls.len() <= 1 {
+                return self.put_with(name, calls.pop().unwrap_or_default(), value);
+            }
+        }
+        let index = match kind {
+            SegmentKind::Evaluate(_) if calls.is_empty() => vec![],
+            _ => calls.pop().unwrap_or_default(),
+        };
+        let target = self.eval_segment(kind, calls)?;
+        OleData::try_from(&target)?.put_default(index, value)
+    }
+
+    fn eval_arg(&self, expression: &str, arg: &PathArg) -> Result<VARIANT> {
+        Ok(match arg {
+            PathArg::Str(text) => VARIANT::from(text.as_str()),
+            PathArg::Int(value) => match i32::try_from(*value) {
+                Ok(value) => VARIANT::from(value),
+                Err(_) => VARIANT::from(*value),
+            },
+            PathArg::Float(value) => VARIANT::from(*value),
+            PathArg::Bool(value) => VARIANT::from(*value),
+            PathArg::Path(path) => self.eval_path(expression, path)?,
+        })
+    }
+
     /// Call a method on a COM object
     ///
     pub fn call(&self, name: &str, args: Vec<VARIANT>) -> Result<VARIANT> {
@@ -267,6 +791,312 @@ impl OleData {
         dp.rgvarg = args.as_ptr() as *mut _;
         self.invoke(name, &mut dp, DISPATCH_METHOD)
     }
+
+    /// Call a method by DISPID
+    ///
+    pub fn call_by_id(&self, dispid: i32, args: Vec<VARIANT>) -> Result<VARIANT> {
+        let mut rgvarg: Vec<VARIANT> = args.into_iter().rev().collect();
+        let mut dp = DISPPARAMS {
+            rgvarg: rgvarg.as_mut_ptr(),
+            cArgs: rgvarg.len() as u32,
+            ..Default::default()
+        };
+        self.invoke_dispid(dispid, &mut dp, DISPATCH_METHOD)
+    }
+
+    /// Call a method on a COM object, passing each argument either by value or by reference
+    ///
+    pub fn call_args(&self, name: &str, args: Vec<Argument>) -> Result<VARIANT> {
+        let mut rgvarg: Vec<VARIANT> = args.into_iter().rev().map(Argument::into_variant).collect();
+        let mut dp = DISPPARAMS {
+            rgvarg: rgvarg.as_mut_ptr(),
+            cArgs: rgvarg.len() as u32,
+            ..Default::default()
+        };
+        self.invoke(name, &mut dp, DISPATCH_METHOD)
+    }
+
+    /// Call a method on a COM object and write `[out]` parameters back into `args`
+    ///
+    /// When the object's type library describes the method, only parameters flagged as
+    /// output are passed by reference. Those declared as a pointer to a simple type, such as
+    /// `long*`, are passed as a `VT_BYREF` of that type, pointing at a copy of the argument
+    /// converted to it, which replaces the argument once the call returns. Other outputs, and
+    /// every argument when there is no type information, are passed as
+    /// `VT_BYREF | VT_VARIANT`.
+    pub fn call_byref(&self, name: &str, args: &mut [VARIANT]) -> Result<VARIANT> {
+        let Ok(method) = self.ole_method_help(name) else {
+            return self.call_args(name, args.iter_mut().map(Argument::ByRef).collect());
+        };
+        let params: Vec<Option<OleParamData>> =
+            method.params().into_iter().map(Result::ok).collect();
+        // Storage of the declared type for each typed output, allocated up front so the
+        // pointers passed to the server stay valid.
+        let mut typed: Vec<Option<VARIANT>> = Vec::with_capacity(args.len());
+        for (i, arg) in args.iter().enumerate() {
+            let target = match params.get(i) {
+                Some(Some(param)) if param.output() => byref_target(param).map(|vt| (param, vt)),
+                _ => None,
+            };
+            typed.push(match target {
+                Some((param, vt)) => Some(byref_storage(&method, param, arg, vt)?),
+                None => None,
+            });
+        }
+        let mut rgvarg: Vec<VARIANT> = args
+            .iter_mut()
+            .zip(typed.iter_mut())
+            .enumerate()
+            .map(|(i, (arg, storage))| match (storage, params.get(i)) {
+                (Some(storage), _) => typed_byref_variant(storage),
+                (None, Some(Some(param))) if !param.output() => arg.clone(),
+                // Parameters whose description cannot be read are treated as outputs.
+                (None, Some(_)) => byref_variant(arg),
+                (None, None) => arg.clone(),
+            })
+            .rev()
+            .collect();
+        let mut dp = DISPPARAMS {
+            rgvarg: rgvarg.as_mut_ptr(),
+            cArgs: rgvarg.len() as u32,
+            ..Default::default()
+        };
+        let result = self.invoke(name, &mut dp, DISPATCH_METHOD);
+        for (arg, storage) in args.iter_mut().zip(typed) {
+            if let Some(storage) = storage {
+                *arg = storage;
+            }
+        }
+        result
+    }
+
+    /// Call a method on a COM object, checking and converting the arguments against the
+    /// method's type library description first
+    ///
+    /// The number of arguments is validated (including `[vararg]` methods), each argument is
+    /// coerced to its declared type, and omitted optional arguments are filled with their
+    /// `[defaultvalue]` or with `DISP_E_PARAMNOTFOUND`.
+    pub fn call_typed(&self, name: &str, args: Vec<VARIANT>) -> Result<VARIANT> {
+        let method = self.ole_method_help(name)?;
+        let params = method
+            .params()
+            .into_iter()
+            .collect::<Result<Vec<OleParamData>>>()?;
+        let params: Vec<OleParamData> = params.into_iter().filter(|p| !p.retval()).collect();
+
+        let vararg = method.size_opt_params() == -1;
+        let fixed = if vararg {
+            params.len().saturating_sub(1)
+        } else {
+            params.len()
+        };
+        let required = params[..fixed]
+            .iter()
+            .take_while(|param| !param.optional() && !param.has_default())
+            .count();
+        if args.len() < required || (!vararg && args.len() > fixed) {
+            return Err(Error::ArgumentCount {
+                member: method.name().to_string(),
+                min: required,
+                max: if vararg { None } else { Some(fixed) },
+                given: args.len(),
+            });
+        }
+
+        let mut typed_args = Vec::with_capacity(args.len().max(fixed));
+        for (i, arg) in args.into_iter().enumerate() {
+            match params.get(i).filter(|_| i < fixed) {
+                Some(param) => typed_args.push(coerce_argument(&method, param, arg)?),
+                None => typed_args.push(arg),
+            }
+        }
+        for param in &params[typed_args.len().min(fixed)..fixed] {
+            typed_args.push(param.default_value().unwrap_or_else(missing_argument));
+        }
+
+        let mut rgvarg: Vec<VARIANT> = typed_args.into_iter().rev().collect();
+        let mut dp = DISPPARAMS {
+            rgvarg: rgvarg.as_mut_ptr(),
+            cArgs: rgvarg.len() as u32,
+            ..Default::default()
+        };
+        // Without an argument the server did not say which one it rejected, so the error is
+        // returned as is rather than blaming a parameter.
+        match self.invoke_dispid(method.dispid(), &mut dp, DISPATCH_METHOD) {
+            Err(Error::IDispatchArgument {
+                error_type,
+                argument: Some(param),
+                ..
+            }) => Err(Error::InvalidArgument {
+                member: method.name().to_string(),
+                param,
+                reason: error_type.to_string(),
+            }),
+            result => result,
+        }
+    }
+
+    /// Call a method on a COM object, passing some of the arguments by name
+    ///
+    /// The method name and all parameter names are resolved in a single `GetIDsOfNames`
+    /// call. Parameter names the object does not know are reported before anything is invoked.
+    pub fn call_named(
+        &self,
+        name: &str,
+        args: Vec<VARIANT>,
+        named_args: Vec<(&str, VARIANT)>,
+    ) -> Result<VARIANT> {
+        let mut names = vec![name];
+        names.extend(named_args.iter().map(|(arg_name, _)| *arg_name));
+
+        let (result, ids) = self.raw_ids_of_names(&names);
+        if let Err(error) = result {
+            if error.hresult() != Some(DISP_E_UNKNOWNNAME) || ids[0] == DISPID_UNKNOWN {
+                return Err(error);
+            }
+            let unknown = names[1..]
+                .iter()
+                .zip(&ids[1..])
+                .filter(|(_, id)| **id == DISPID_UNKNOWN)
+                .map(|(arg_name, _)| arg_name.to_string())
+                .collect();
+            return Err(Error::UnknownArgumentNames {
+                member: name.to_string(),
+                names: unknown,
+            });
+        }
+        record::record_names(self, &names, &ids);
+
+        // Named arguments come first in `rgvarg`, in the same order as `rgdispidNamedArgs`,
+        // followed by the positional arguments in reverse order.
+        let mut named_ids = ids[1..].to_vec();
+        let mut rgvarg: Vec<VARIANT> = named_args.into_iter().map(|(_, value)| value).collect();
+        rgvarg.extend(args.into_iter().rev());
+
+        let mut dp = DISPPARAMS {
+            rgvarg: rgvarg.as_mut_ptr(),
+            rgdispidNamedArgs: named_ids.as_mut_ptr(),
+            cArgs: rgvarg.len() as u32,
+            cNamedArgs: named_ids.len() as u32,
+        };
+        self.invoke_dispid(ids[0], &mut dp, DISPATCH_METHOD)
+    }
+}
+
+impl TryFrom<&VARIANT> for OleData {
+    type Error = Error;
+
+    fn try_from(variant: &VARIANT) -> Result<OleData> {
+        match variant_dispatch(variant) {
+            Some(dispatch) => Ok(OleData::from_dispatch(dispatch)),
+            None => Err(Error::Custom(format!(
+                "expected an object but found a {} value",
+                vartype_name(variant.vt())
+            ))),
+        }
+    }
+}
+
+impl TryFrom<VARIANT> for OleData {
+    type Error = Error;
+
+    fn try_from(variant: VARIANT) -> Result<OleData> {
+        OleData::try_from(&variant)
+    }
+}
+
+fn coerce_argument(method: &OleMethodData, param: &OleParamData, arg: VARIANT) -> Result<VARIANT> {
+    if is_missing_argument(&arg) && (param.optional() || param.has_default()) {
+        return Ok(param.default_value().unwrap_or(arg));
+    }
+    let Some(vt) = coercion_target(param) else {
+        return Ok(arg);
+    };
+    if arg.vt() == vt {
+        return Ok(arg);
+    }
+    convert_argument(method, param, &arg, vt)
+}
+
+/// The storage a typed `VT_BYREF` argument of type `vt` points at, holding `arg`
+///
+/// An empty argument, as passed for a pure `[out]` parameter, becomes the zero value of `vt`.
+fn byref_storage(
+    method: &OleMethodData,
+    param: &OleParamData,
+    arg: &VARIANT,
+    vt: VARENUM,
+) -> Result<VARIANT> {
+    if arg.vt() == vt {
+        return Ok(arg.clone());
+    }
+    if arg.vt() == VT_EMPTY {
+        let mut storage = VARIANT::default();
+        unsafe { (*storage.Anonymous.Anonymous).vt = vt };
+        return Ok(storage);
+    }
+    convert_argument(method, param, arg, vt)
+}
+
+fn convert_argument(
+    method: &OleMethodData,
+    param: &OleParamData,
+    arg: &VARIANT,
+    vt: VARENUM,
+) -> Result<VARIANT> {
+    let mut coerced = VARIANT::default();
+    let result = unsafe { VariantChangeType(&mut coerced, arg, VAR_CHANGE_FLAGS(0), vt) };
+    match result {
+        Ok(()) => Ok(coerced),
+        Err(error) => Err(Error::InvalidArgument {
+            member: method.name().to_string(),
+            param: param.name().to_string(),
+            reason: format!(
+                "cannot convert {} to {}: {}",
+                vartype_name(arg.vt()),
+                vartype_name(vt),
+                hresult::describe(error.code())
+            ),
+        }),
+    }
+}
+
+fn coercion_target(param: &OleParamData) -> Option<VARENUM> {
+    type_target(param, &param.elem_desc().tdesc)
+}
+
+/// The type a parameter declared as a pointer points to, if it can be passed as a typed
+/// `VT_BYREF`
+fn byref_target(param: &OleParamData) -> Option<VARENUM> {
+    let tdesc = &param.elem_desc().tdesc;
+    if tdesc.vt != VT_PTR {
+        return None;
+    }
+    let pointee = unsafe { tdesc.Anonymous.lptdesc.as_ref() }?;
+    // A DECIMAL fills the whole VARIANT rather than its value field.
+    type_target(param, pointee).filter(|vt| *vt != VT_DECIMAL)
+}
+
+/// The `VARIANT` type arguments of type `tdesc` are converted to, if any
+fn type_target(param: &OleParamData, tdesc: &TYPEDESC) -> Option<VARENUM> {
+    match tdesc.vt {
+        VT_I1 | VT_UI1 | VT_I2 | VT_UI2 | VT_I4 | VT_UI4 | VT_I8 | VT_UI8 | VT_INT | VT_UINT
+        | VT_R4 | VT_R8 | VT_CY | VT_DATE | VT_BSTR | VT_BOOL | VT_DECIMAL | VT_DISPATCH
+        | VT_UNKNOWN => Some(tdesc.vt),
+        VT_USERDEFINED => {
+            let reftypeinfo =
+                unsafe { param.typeinfo().GetRefTypeInfo(tdesc.Anonymous.hreftype) }.ok()?;
+            let type_attr = unsafe { reftypeinfo.GetTypeAttr() }.ok()?;
+            let typekind = unsafe { (*type_attr).typekind };
+            unsafe { reftypeinfo.ReleaseTypeAttr(type_attr) };
+            if typekind == TKIND_ENUM {
+                Some(VT_I4)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
 }
 
 /*pub enum HelpTarget<'a> {
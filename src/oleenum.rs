@@ -0,0 +1,167 @@
+use std::collections::VecDeque;
+
+use windows::{
+    core::Interface,
+    Win32::System::{Ole::IEnumVARIANT, Variant::VARIANT},
+};
+
+use crate::{error::Result, util::variant::variant_unknown, OleData};
+
+const DEFAULT_CHUNK_SIZE: u32 = 16;
+
+enum EnumSource {
+    Enumerator(IEnumVARIANT),
+    Indexed {
+        collection: OleData,
+        next: i32,
+        count: i32,
+    },
+}
+
+/// Iterator over the members of a COM collection
+///
+/// Items are fetched from the collection's `IEnumVARIANT` in chunks. Collections without
+/// `_NewEnum` are walked with `Count` and `Item(i)`, starting at index 1.
+pub struct OleEnum {
+    source: EnumSource,
+    chunk_size: u32,
+    buffer: VecDeque<VARIANT>,
+    done: bool,
+}
+
+impl OleEnum {
+    pub fn from_enumerator(enumerator: IEnumVARIANT) -> OleEnum {
+        OleEnum {
+            source: EnumSource::Enumerator(enumerator),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            buffer: VecDeque::new(),
+            done: false,
+        }
+    }
+    pub fn from_indexed(collection: OleData) -> Result<OleEnum> {
+        let count = collection.get("Count")?;
+        let count = i32::try_from(&count)?;
+        Ok(OleEnum {
+            source: EnumSource::Indexed {
+                collection,
+                next: 1,
+                count,
+            },
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            buffer: VecDeque::new(),
+            done: false,
+        })
+    }
+    /// Number of items requested from `IEnumVARIANT::Next` at a time
+    ///
+    pub fn chunk_size(mut self, chunk_size: u32) -> OleEnum {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+    /// Skip over the next `count` items
+    ///
+    pub fn skip_items(&mut self, count: u32) -> Result<()> {
+        let buffered = (count as usize).min(self.buffer.len());
+        self.buffer.drain(..buffered);
+        let remaining = count - buffered as u32;
+        if remaining == 0 {
+            return Ok(());
+        }
+        match self.source {
+            EnumSource::Enumerator(ref enumerator) => {
+                unsafe { enumerator.Skip(remaining) }.ok()?;
+            }
+            EnumSource::Indexed { ref mut next, .. } => {
+                *next += remaining as i32;
+            }
+        }
+        Ok(())
+    }
+    /// Restart the enumeration from the first item
+    ///
+    pub fn reset(&mut self) -> Result<()> {
+        self.buffer.clear();
+        self.done = false;
+        match self.source {
+            EnumSource::Enumerator(ref enumerator) => unsafe { enumerator.Reset()? },
+            EnumSource::Indexed { ref mut next, .. } => *next = 1,
+        }
+        Ok(())
+    }
+    /// Create an independent enumerator at the same position
+    ///
+    pub fn try_clone(&self) -> Result<OleEnum> {
+        let source = match self.source {
+            EnumSource::Enumerator(ref enumerator) => {
+                EnumSource::Enumerator(unsafe { enumerator.Clone()? })
+            }
+            EnumSource::Indexed {
+                ref collection,
+                next,
+                count,
+            } => EnumSource::Indexed {
+                collection: collection.clone(),
+                next,
+                count,
+            },
+        };
+        Ok(OleEnum {
+            source,
+            chunk_size: self.chunk_size,
+            buffer: self.buffer.clone(),
+            done: self.done,
+        })
+    }
+    /// Iterate over the items as `OleData` objects
+    ///
+    pub fn objects(self) -> impl Iterator<Item = Result<OleData>> {
+        self.map(|item| OleData::try_from(&item?))
+    }
+    fn fill_buffer(&mut self) -> Result<()> {
+        match self.source {
+            EnumSource::Enumerator(ref enumerator) => {
+                let mut chunk = vec![VARIANT::default(); self.chunk_size as usize];
+                let mut fetched = 0;
+                unsafe { enumerator.Next(&mut chunk, &mut fetched) }.ok()?;
+                chunk.truncate(fetched as usize);
+                if fetched < self.chunk_size {
+                    self.done = true;
+                }
+                self.buffer.extend(chunk);
+            }
+            EnumSource::Indexed {
+                ref collection,
+                ref mut next,
+                count,
+            } => {
+                while self.buffer.len() < self.chunk_size as usize && *next <= count {
+                    let item = collection.get_with("Item", vec![VARIANT::from(*next)])?;
+                    self.buffer.push_back(item);
+                    *next += 1;
+                }
+                if *next > count {
+                    self.done = true;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for OleEnum {
+    type Item = Result<VARIANT>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffer.is_empty() && !self.done {
+            if let Err(error) = self.fill_buffer() {
+                self.done = true;
+                return Some(Err(error));
+            }
+        }
+        self.buffer.pop_front().map(Ok)
+    }
+}
+
+pub(crate) fn enumerator_from_variant(variant: &VARIANT) -> Option<IEnumVARIANT> {
+    variant_unknown(variant).and_then(|unknown| unknown.cast().ok())
+}
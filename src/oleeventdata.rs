@@ -1,57 +1,598 @@
-use std::ptr;
+//! Receiving events from COM objects.
+//!
+//! [`OleEventData`] connects to a source interface of an object, such as Excel's
+//! `AppEvents`, and calls Rust closures registered by event name:
+//!
+//! ```ignore
+//! let events = OleEventData::new(&workbook, None)?;
+//! events.on_event("BeforeClose", |args| {
+//!     // Keep the workbook open.
+//!     args.set(0, true)
+//! })?;
+//! // Deliver events until another thread calls `stop.cancel()`.
+//! let stop = CancelToken::new();
+//! MessagePump::new().run_until_cancelled(&stop);
+//! ```
+//!
+//! Arguments are decoded with the signature the type library declares for each event. A
+//! closure registered with [`OleEventData::on_decoded`] receives every event as an [`Event`],
+//! and [`EventInterface::handler_trait`] generates a trait with one method per event.
+//!
+//! Events are delivered while the thread pumps messages. A [`MessagePump`](crate::MessagePump)
+//! waits for them without spinning and stops when a condition holds, a deadline passes or a
+//! [`CancelToken`](crate::CancelToken) fires. The connection is closed when the `OleEventData`
+//! is dropped.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    ffi::c_void,
+    ptr,
+    rc::Rc,
+    sync::atomic::{AtomicU32, Ordering},
+};
 
 use windows::{
-    core::{implement, Interface, Vtable, BSTR, GUID, HSTRING},
+    core::{implement, IUnknown, IUnknown_Vtbl, Interface, BSTR, GUID, HRESULT, HSTRING, PCWSTR},
     Win32::{
-        Foundation::{DISP_E_BADINDEX, E_NOINTERFACE, HWND},
-        Globalization::GetUserDefaultLCID,
+        Foundation::{DISP_E_UNKNOWNNAME, E_NOINTERFACE, E_POINTER, S_OK},
         System::{
             Com::{
-                IConnectionPoint, IConnectionPointContainer, IDispatch, IDispatch_Impl, ITypeInfo,
-                IMPLTYPEFLAGS, IMPLTYPEFLAG_FDEFAULT, IMPLTYPEFLAG_FSOURCE, TKIND_COCLASS,
-                TYPEATTR,
+                IConnectionPoint, IConnectionPointContainer, IDispatch, IDispatch_Impl,
+                IDispatch_Vtbl, ITypeInfo, DISPATCH_FLAGS, DISPPARAMS, EXCEPINFO, IMPLTYPEFLAGS,
+                IMPLTYPEFLAG_FDEFAULT, IMPLTYPEFLAG_FSOURCE, TKIND_COCLASS, TYPEATTR,
             },
-            Ole::{IProvideClassInfo, IProvideClassInfo2, GUIDKIND_DEFAULT_SOURCE_DISP_IID},
-        },
-        UI::WindowsAndMessaging::{
-            DispatchMessageW, PeekMessageW, TranslateMessage, MSG, PM_REMOVE,
+            Ole::{
+                IProvideClassInfo, IProvideClassInfo2, DISPID_UNKNOWN,
+                GUIDKIND_DEFAULT_SOURCE_DISP_IID,
+            },
+            Variant::{VARIANT, VT_BYREF},
         },
     },
 };
 
-use crate::{error::Result, OleData};
+use crate::{
+    backend::report_error,
+    error::{Error, Result},
+    eventtype::{decode_argument, Event, EventInterface, EventParam, EventSignature},
+    msgpump::PumpExit,
+    server::FromVariant,
+    util::{
+        ole::{pump_messages, TypeRef},
+        variant::write_byref,
+    },
+    value::{IntoVariant, Variant},
+    OleData, OleTypeData,
+};
 
-pub struct IEventSinkObject {
-    event_sink: IEventSink,
-    m_ref: u32,
-    m_iid: GUID,
-    m_event_id: u64,
-    typeinfo: ITypeInfo,
+type Handler = Rc<RefCell<dyn FnMut(&mut EventArgs) -> Result<()>>>;
+
+struct SinkState {
+    interface: EventInterface,
+    /// Handlers keyed by lowercased event name
+    handlers: HashMap<String, Handler>,
+    any: Option<Handler>,
 }
 
+/// A connection to an object's event source
 pub struct OleEventData {
-    cookie: u32,
+    cookie: Cell<Option<u32>>,
     connection_point: IConnectionPoint,
-    dispatch: IDispatch,
-    event_id: u64,
+    state: Rc<RefCell<SinkState>>,
+    typeinfo: ITypeInfo,
+    iid: GUID,
+}
+
+impl OleEventData {
+    /// Connect to the default source interface of `ole`, or to the source interface named
+    /// `interface`
+    ///
+    pub fn new(ole: &OleData, interface: Option<&str>) -> Result<OleEventData> {
+        let guid_info = match interface {
+            Some(interface) => find_iid(ole, Some(interface), &GUID::zeroed())?,
+            None => find_default_source(ole)?,
+        };
+        let (Some(_), Some(typeinfo)) = (guid_info.guid, guid_info.typeinfo) else {
+            return Err(Error::Custom("failed to find the event interface".into()));
+        };
+        OleEventData::from_type(ole, &OleTypeData::try_from(typeinfo)?)
+    }
+    /// Connect to the source interface `source` of `ole`, such as one returned by
+    /// [`OleTypeData::default_event_sources`] or [`OleTypeData::source_ole_types`]
+    ///
+    pub fn from_type(ole: &OleData, source: &OleTypeData) -> Result<OleEventData> {
+        let interface = EventInterface::new(source)?;
+        let iid = interface.iid();
+        let Some(dispatch) = ole.dispatch() else {
+            return Err(Error::Custom("the object is not a COM object".into()));
+        };
+        let container: IConnectionPointContainer = dispatch.cast()?;
+        let connection_point = unsafe { container.FindConnectionPoint(&iid)? };
+
+        let state = Rc::new(RefCell::new(SinkState {
+            interface,
+            handlers: HashMap::new(),
+            any: None,
+        }));
+        let sink = SinkObject::create(
+            iid,
+            EventDispatch {
+                state: state.clone(),
+            }
+            .into(),
+        );
+        let cookie = unsafe { connection_point.Advise(&sink)? };
+        Ok(OleEventData {
+            cookie: Cell::new(Some(cookie)),
+            connection_point,
+            state,
+            typeinfo: source.typeinfo().clone(),
+            iid,
+        })
+    }
+    /// Call `handler` whenever the event `name` fires, replacing any previous handler
+    ///
+    pub fn on_event<F>(&self, name: &str, handler: F) -> Result<()>
+    where
+        F: FnMut(&mut EventArgs) -> Result<()> + 'static,
+    {
+        let mut state = self.state.borrow_mut();
+        let Some(event) = state.interface.find(name) else {
+            return Err(Error::Custom(format!("unknown event `{name}`")));
+        };
+        let key = event.name.to_lowercase();
+        state.handlers.insert(key, Rc::new(RefCell::new(handler)));
+        Ok(())
+    }
+    /// Call `handler` for every event that has no handler of its own
+    ///
+    pub fn on_any_event<F>(&self, handler: F)
+    where
+        F: FnMut(&mut EventArgs) -> Result<()> + 'static,
+    {
+        self.state.borrow_mut().any = Some(Rc::new(RefCell::new(handler)));
+    }
+    /// Call `handler` with every event that has no handler of its own, decoded into an
+    /// [`Event`]
+    ///
+    pub fn on_decoded<F>(&self, mut handler: F)
+    where
+        F: FnMut(&Event) -> Result<()> + 'static,
+    {
+        self.on_any_event(move |args| handler(&args.event()));
+    }
+    pub fn off_event(&self, name: &str) {
+        self.state
+            .borrow_mut()
+            .handlers
+            .remove(&name.to_lowercase());
+    }
+    /// Names of the events of the source interface
+    ///
+    pub fn events(&self) -> Vec<String> {
+        let state = self.state.borrow();
+        state
+            .interface
+            .events()
+            .map(|event| event.name.clone())
+            .collect()
+    }
+    /// Names, parameters and types of the events of the source interface
+    ///
+    pub fn event_interface(&self) -> EventInterface {
+        self.state.borrow().interface.clone()
+    }
+    pub fn interface(&self) -> Result<OleTypeData> {
+        OleTypeData::try_from(self.typeinfo.clone())
+    }
+    pub fn iid(&self) -> GUID {
+        self.iid
+    }
+    /// Disconnect from the source. Dropping the `OleEventData` does the same.
+    ///
+    pub fn unadvise(&self) -> Result<()> {
+        if let Some(cookie) = self.cookie.take() {
+            unsafe { self.connection_point.Unadvise(cookie)? };
+        }
+        Ok(())
+    }
+    /// Dispatch pending messages, which delivers the events queued for this thread
+    ///
+    /// Returns [`PumpExit::Quit`] if `WM_QUIT` was received, which the caller's own message
+    /// loop should honour. Use a [`MessagePump`](crate::MessagePump) to keep pumping until a
+    /// condition holds.
+    pub fn message_loop() -> Option<PumpExit> {
+        pump_messages()
+    }
 }
 
 impl Drop for OleEventData {
     fn drop(&mut self) {
-        unsafe { self.connection_point.Unadvise(self.cookie) };
+        let _ = self.unadvise();
     }
 }
 
-fn ole_msg_loop() {
-    let mut msg = MSG::default();
-    unsafe {
-        while PeekMessageW(&mut msg, HWND(0), 0, 0, PM_REMOVE).as_bool() {
-            TranslateMessage(&msg);
-            DispatchMessageW(&msg);
+/// The arguments of an event, in the order the event declares them
+pub struct EventArgs<'a> {
+    event: &'a EventSignature,
+    raw: Vec<Option<&'a VARIANT>>,
+    values: Vec<Variant>,
+    result: Option<VARIANT>,
+}
+
+impl<'a> EventArgs<'a> {
+    fn new(event: &'a EventSignature, params: &'a DISPPARAMS) -> EventArgs<'a> {
+        let rgvarg = if params.rgvarg.is_null() {
+            &[][..]
+        } else {
+            unsafe { std::slice::from_raw_parts(params.rgvarg, params.cArgs as usize) }
+        };
+        let named = if params.rgdispidNamedArgs.is_null() {
+            &[][..]
+        } else {
+            unsafe {
+                std::slice::from_raw_parts(params.rgdispidNamedArgs, params.cNamedArgs as usize)
+            }
+        };
+        let (named_values, positional) = rgvarg.split_at(named.len().min(rgvarg.len()));
+        let mut raw: Vec<Option<&VARIANT>> = positional.iter().rev().map(Some).collect();
+        for (dispid, value) in named.iter().zip(named_values) {
+            let Ok(index) = usize::try_from(*dispid) else {
+                continue;
+            };
+            if raw.len() <= index {
+                raw.resize(index + 1, None);
+            }
+            raw[index] = Some(value);
+        }
+        if raw.len() < event.params.len() {
+            raw.resize(event.params.len(), None);
+        }
+        let values = raw
+            .iter()
+            .enumerate()
+            .map(|(index, arg)| match arg {
+                Some(arg) => decode_argument(arg, event.params.get(index)),
+                None => Variant::Empty,
+            })
+            .collect();
+        EventArgs {
+            event,
+            raw,
+            values,
+            result: None,
+        }
+    }
+    /// Name of the event
+    ///
+    pub fn name(&self) -> &str {
+        &self.event.name
+    }
+    pub fn dispid(&self) -> i32 {
+        self.event.dispid
+    }
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+    /// Names and types of the parameters, as the type library declares them
+    ///
+    pub fn params(&self) -> &[EventParam] {
+        &self.event.params
+    }
+    pub fn get(&self, index: usize) -> Option<&Variant> {
+        self.values.get(index)
+    }
+    pub fn get_named(&self, param: &str) -> Option<&Variant> {
+        self.get(self.position(param)?)
+    }
+    pub fn values(&self) -> &[Variant] {
+        &self.values
+    }
+    /// The argument at `index` converted to `T`, as the generated handler traits take it
+    ///
+    pub fn arg<T: FromVariant>(&self, index: usize) -> Result<T> {
+        let Some(value) = self.get(index) else {
+            return Err(Error::Custom(format!(
+                "event `{}` has no argument {index}",
+                self.name()
+            )));
+        };
+        T::from_variant(&value.clone().into_variant())
+    }
+    /// A copy of the event with its arguments paired with their parameter names
+    ///
+    pub fn event(&self) -> Event {
+        let args = self
+            .values
+            .iter()
+            .enumerate()
+            .map(|(index, value)| (self.param_name(index), value.clone()))
+            .collect();
+        Event {
+            name: self.event.name.clone(),
+            dispid: self.event.dispid,
+            args,
+        }
+    }
+    /// The argument as passed by the source, `None` if it was omitted
+    ///
+    pub fn raw(&self, index: usize) -> Option<&VARIANT> {
+        self.raw.get(index).copied().flatten()
+    }
+    /// Assign a `ByRef` argument, such as the `Cancel` parameter of `BeforeClose`
+    ///
+    /// The value is written back to the source immediately, converted to the type the
+    /// source passed.
+    pub fn set<V: Into<Variant>>(&mut self, index: usize, value: V) -> Result<()> {
+        let Some(target) = self.raw(index) else {
+            return Err(Error::Custom(format!(
+                "event `{}` has no argument {index}",
+                self.name()
+            )));
+        };
+        if target.vt().0 & VT_BYREF.0 == 0 {
+            return Err(Error::Custom(format!(
+                "argument `{}` of event `{}` is not passed by reference",
+                self.param_name(index),
+                self.name()
+            )));
+        }
+        let value = value.into();
+        write_byref(target, &value.clone().into_variant())?;
+        self.values[index] = value;
+        Ok(())
+    }
+    pub fn set_named<V: Into<Variant>>(&mut self, param: &str, value: V) -> Result<()> {
+        let Some(index) = self.position(param) else {
+            return Err(Error::Custom(format!(
+                "event `{}` has no parameter `{param}`",
+                self.name()
+            )));
+        };
+        self.set(index, value)
+    }
+    /// Set the value returned to the source, for the few events that have one
+    ///
+    pub fn set_return<V: Into<Variant>>(&mut self, value: V) {
+        self.result = Some(value.into().into_variant());
+    }
+    fn position(&self, param: &str) -> Option<usize> {
+        self.event.position(param)
+    }
+    fn param_name(&self, index: usize) -> String {
+        match self.event.params.get(index) {
+            Some(param) => param.name.clone(),
+            None => format!("#{}", index + 1),
         }
     }
 }
 
+#[implement(IDispatch)]
+struct EventDispatch {
+    state: Rc<RefCell<SinkState>>,
+}
+
+impl IDispatch_Impl for EventDispatch {
+    fn GetTypeInfoCount(&self) -> windows::core::Result<u32> {
+        Ok(0)
+    }
+
+    fn GetTypeInfo(&self, _itinfo: u32, _lcid: u32) -> windows::core::Result<ITypeInfo> {
+        Err(E_NOINTERFACE.into())
+    }
+
+    fn GetIDsOfNames(
+        &self,
+        _riid: *const GUID,
+        rgsznames: *const PCWSTR,
+        cnames: u32,
+        _lcid: u32,
+        rgdispid: *mut i32,
+    ) -> windows::core::Result<()> {
+        let names = unsafe { std::slice::from_raw_parts(rgsznames, cnames as usize) };
+        let dispids = unsafe { std::slice::from_raw_parts_mut(rgdispid, cnames as usize) };
+        dispids.fill(DISPID_UNKNOWN);
+        let state = self.state.borrow();
+        let name = names
+            .first()
+            .map(|name| unsafe { name.to_string() }.unwrap_or_default());
+        let Some(event) = name.and_then(|name| state.interface.find(&name)) else {
+            return Err(DISP_E_UNKNOWNNAME.into());
+        };
+        dispids[0] = event.dispid;
+        let mut result = Ok(());
+        for (dispid, name) in dispids[1..].iter_mut().zip(&names[1..]) {
+            let name = unsafe { name.to_string() }.unwrap_or_default();
+            match event.position(&name) {
+                Some(index) => *dispid = index as i32,
+                None => result = Err(DISP_E_UNKNOWNNAME.into()),
+            }
+        }
+        result
+    }
+
+    fn Invoke(
+        &self,
+        dispidmember: i32,
+        _riid: *const GUID,
+        _lcid: u32,
+        _wflags: DISPATCH_FLAGS,
+        pdispparams: *const DISPPARAMS,
+        pvarresult: *mut VARIANT,
+        pexcepinfo: *mut EXCEPINFO,
+        puargerr: *mut u32,
+    ) -> windows::core::Result<()> {
+        let Some(params) = (unsafe { pdispparams.as_ref() }) else {
+            return Err(E_POINTER.into());
+        };
+        let (event, handler) = {
+            let state = self.state.borrow();
+            let event = match state.interface.get(dispidmember) {
+                Some(event) => event.clone(),
+                None => EventSignature::unknown(dispidmember),
+            };
+            let handler = state
+                .handlers
+                .get(&event.name.to_lowercase())
+                .or(state.any.as_ref())
+                .cloned();
+            (event, handler)
+        };
+        let Some(handler) = handler else {
+            return Ok(());
+        };
+        let mut args = EventArgs::new(&event, params);
+        // An event raised again from inside its own handler is not delivered.
+        let result = match handler.try_borrow_mut() {
+            Ok(mut handler) => (*handler)(&mut args),
+            Err(_) => Ok(()),
+        };
+        match result {
+            Ok(()) => {
+                if let (Some(value), false) = (args.result.take(), pvarresult.is_null()) {
+                    unsafe { pvarresult.write(value) };
+                }
+                Ok(())
+            }
+            Err(error) => Err(report_error(error, &event.name, pexcepinfo, puargerr)),
+        }
+    }
+}
+
+/// The object passed to `Advise`
+///
+/// Connection points query the sink for the IID of the source interface, which is only known
+/// at runtime, so `#[implement]` cannot produce it. The sink answers that IID itself and
+/// forwards the `IDispatch` methods to an [`EventDispatch`].
+#[repr(C)]
+struct SinkObject {
+    vtable: *const IDispatch_Vtbl,
+    refs: AtomicU32,
+    iid: GUID,
+    inner: IDispatch,
+}
+
+static SINK_VTABLE: IDispatch_Vtbl = IDispatch_Vtbl {
+    base__: IUnknown_Vtbl {
+        QueryInterface: sink_query_interface,
+        AddRef: sink_add_ref,
+        Release: sink_release,
+    },
+    GetTypeInfoCount: sink_get_type_info_count,
+    GetTypeInfo: sink_get_type_info,
+    GetIDsOfNames: sink_get_ids_of_names,
+    Invoke: sink_invoke,
+};
+
+impl SinkObject {
+    fn create(iid: GUID, inner: IDispatch) -> IDispatch {
+        let object = Box::new(SinkObject {
+            vtable: &SINK_VTABLE,
+            refs: AtomicU32::new(1),
+            iid,
+            inner,
+        });
+        unsafe { IDispatch::from_raw(Box::into_raw(object) as *mut c_void) }
+    }
+    unsafe fn from_this<'a>(this: *mut c_void) -> &'a SinkObject {
+        &*(this as *const SinkObject)
+    }
+}
+
+unsafe extern "system" fn sink_query_interface(
+    this: *mut c_void,
+    iid: *const GUID,
+    interface: *mut *mut c_void,
+) -> HRESULT {
+    if iid.is_null() || interface.is_null() {
+        return E_POINTER;
+    }
+    let object = SinkObject::from_this(this);
+    if *iid == IUnknown::IID || *iid == IDispatch::IID || *iid == object.iid {
+        sink_add_ref(this);
+        *interface = this;
+        S_OK
+    } else {
+        *interface = ptr::null_mut();
+        E_NOINTERFACE
+    }
+}
+
+unsafe extern "system" fn sink_add_ref(this: *mut c_void) -> u32 {
+    SinkObject::from_this(this)
+        .refs
+        .fetch_add(1, Ordering::Relaxed)
+        + 1
+}
+
+unsafe extern "system" fn sink_release(this: *mut c_void) -> u32 {
+    let remaining = SinkObject::from_this(this)
+        .refs
+        .fetch_sub(1, Ordering::Release)
+        - 1;
+    if remaining == 0 {
+        std::sync::atomic::fence(Ordering::Acquire);
+        drop(Box::from_raw(this as *mut SinkObject));
+    }
+    remaining
+}
+
+unsafe extern "system" fn sink_get_type_info_count(this: *mut c_void, count: *mut u32) -> HRESULT {
+    let inner = &SinkObject::from_this(this).inner;
+    (inner.vtable().GetTypeInfoCount)(inner.as_raw(), count)
+}
+
+unsafe extern "system" fn sink_get_type_info(
+    this: *mut c_void,
+    itinfo: u32,
+    lcid: u32,
+    typeinfo: *mut *mut c_void,
+) -> HRESULT {
+    let inner = &SinkObject::from_this(this).inner;
+    (inner.vtable().GetTypeInfo)(inner.as_raw(), itinfo, lcid, typeinfo)
+}
+
+unsafe extern "system" fn sink_get_ids_of_names(
+    this: *mut c_void,
+    riid: *const GUID,
+    names: *const PCWSTR,
+    count: u32,
+    lcid: u32,
+    dispids: *mut i32,
+) -> HRESULT {
+    let inner = &SinkObject::from_this(this).inner;
+    (inner.vtable().GetIDsOfNames)(inner.as_raw(), riid, names, count, lcid, dispids)
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe extern "system" fn sink_invoke(
+    this: *mut c_void,
+    dispid: i32,
+    riid: *const GUID,
+    lcid: u32,
+    flags: DISPATCH_FLAGS,
+    params: *const DISPPARAMS,
+    result: *mut VARIANT,
+    excepinfo: *mut EXCEPINFO,
+    arg_err: *mut u32,
+) -> HRESULT {
+    let inner = &SinkObject::from_this(this).inner;
+    (inner.vtable().Invoke)(
+        inner.as_raw(),
+        dispid,
+        riid,
+        lcid,
+        flags,
+        params,
+        result,
+        excepinfo,
+        arg_err,
+    )
+}
+
 #[derive(Debug)]
 struct GuidInfo {
     pub guid: Option<GUID>,
@@ -59,7 +600,7 @@ struct GuidInfo {
 }
 
 fn find_iid(oledata: &OleData, pitf: Option<&str>, piid: &GUID) -> Result<GuidInfo> {
-    let typeinfo = unsafe { oledata.dispatch.GetTypeInfo(0, GetUserDefaultLCID()) }?;
+    let typeinfo = oledata.backend.type_info()?;
 
     let mut typelib = None;
     let mut index = 0;
@@ -68,7 +609,7 @@ fn find_iid(oledata: &OleData, pitf: Option<&str>, piid: &GUID) -> Result<GuidIn
 
     if pitf.is_none() {
         return Ok(GuidInfo {
-            guid: None,
+            guid: Some(*piid),
             typeinfo: Some(unsafe { typelib.GetTypeInfoOfGuid(piid)? }),
         });
     }
@@ -113,10 +654,11 @@ fn find_iid(oledata: &OleData, pitf: Option<&str>, piid: &GUID) -> Result<GuidIn
                 if pitf == bstr {
                     let impl_type_attr = unsafe { impl_type_info.GetTypeAttr() };
                     if let Ok(impl_type_attr) = impl_type_attr {
+                        let guid = unsafe { (*impl_type_attr).guid };
                         unsafe { typeinfo.ReleaseTypeAttr(type_attr) };
                         unsafe { impl_type_info.ReleaseTypeAttr(impl_type_attr) };
                         return Ok(GuidInfo {
-                            guid: Some(unsafe { (*impl_type_attr).guid }),
+                            guid: Some(guid),
                             typeinfo: Some(impl_type_info),
                         });
                     } else {
@@ -142,65 +684,6 @@ impl Drop for ITypeInfoData<'_> {
     }
 }
 
-#[implement(IDispatch)]
-pub struct IEventSink();
-
-impl IDispatch_Impl for IEventSink {
-    fn GetTypeInfoCount(&self) -> windows::core::Result<u32> {
-        Ok(0)
-    }
-
-    fn GetTypeInfo(&self, _itinfo: u32, _lcid: u32) -> windows::core::Result<ITypeInfo> {
-        Err(DISP_E_BADINDEX.into())
-    }
-
-    fn GetIDsOfNames(
-        &self,
-        riid: *const windows::core::GUID,
-        rgsznames: *const windows::core::PCWSTR,
-        cnames: u32,
-        lcid: u32,
-        rgdispid: *mut i32,
-    ) -> windows::core::Result<()> {
-        todo!()
-    }
-
-    fn Invoke(
-        &self,
-        dispidmember: i32,
-        riid: *const windows::core::GUID,
-        lcid: u32,
-        wflags: windows::Win32::System::Com::DISPATCH_FLAGS,
-        pdispparams: *const windows::Win32::System::Com::DISPPARAMS,
-        pvarresult: *mut windows::Win32::System::Com::VARIANT,
-        pexcepinfo: *mut windows::Win32::System::Com::EXCEPINFO,
-        puargerr: *mut u32,
-    ) -> windows::core::Result<()> {
-        todo!()
-    }
-}
-
-/*fn ev_advise(oledata: &OleData, itf: Option<&str>) -> Result<()> {
-    let guid_info = if itf.is_some() {
-        //Creation of piid is just to appease the function signature's demands
-        let piid = GUID::new().unwrap();
-        find_iid(oledata, itf, &piid)
-    } else {
-        find_default_source(oledata)
-    };
-    let Ok(guid_info) = guid_info else {
-        return Err(guid_info.unwrap_err().into());
-    };
-    let connection_point_container = ptr::null();
-    let result = unsafe { oledata.dispatch.query(&IConnectionPointContainer::IID, &mut connection_point_container) };
-    let result = result.ok();
-    if let Err(error) = result {
-        return Err(error.into());
-    }
-    let connection_point_container = unsafe { <IConnectionPointContainer as Vtable>::from_raw(connection_point_container as *mut _) };
-    let connection_point = unsafe { connection_point_container.FindConnectionPoint(&guid_info.guid.unwrap()) }?;
-}*/
-
 fn find_coclass<'a>(typeinfo: &ITypeInfo, typeattr: &TYPEATTR) -> Result<ITypeInfoData<'a>> {
     let mut typelib = None;
     unsafe { typeinfo.GetContainingTypeLib(&mut typelib, ptr::null_mut()) }?;
@@ -239,15 +722,16 @@ fn find_coclass<'a>(typeinfo: &ITypeInfo, typeattr: &TYPEATTR) -> Result<ITypeIn
             let Ok(reftypeattr) = reftypeattr else {
                 continue;
             };
-            if typeattr.guid == unsafe { (*reftypeattr).guid } {
+            let found = typeattr.guid == unsafe { (*reftypeattr).guid };
+            unsafe { reftypeinfo.ReleaseTypeAttr(reftypeattr) };
+            if found {
                 return Ok(ITypeInfoData {
                     typeinfo: typeinfo2,
                     typedata: unsafe { &*typeattr2 },
                 });
-            } else {
-                unsafe { typeinfo2.ReleaseTypeAttr(typeattr2) };
             }
         }
+        unsafe { typeinfo2.ReleaseTypeAttr(typeattr2) };
     }
     let msg = HSTRING::from(format!(
         "failed to find ITypeInfoData for {:?}",
@@ -292,21 +776,20 @@ fn find_default_source_from_typeinfo(
     }
     if let Err(error) = result {
         Err(error.into())
+    } else if let Some(ret_type_info) = ret_type_info {
+        Ok(ret_type_info)
     } else {
-        Ok(ret_type_info.unwrap())
+        let msg = HSTRING::from("failed to find the default source interface");
+        Err(windows::core::Error::new(E_NOINTERFACE, msg).into())
     }
 }
 
 fn find_default_source(ole: &OleData) -> Result<GuidInfo> {
-    let mut provider_class_info_interface = ptr::null();
-    let result = unsafe {
-        ole.dispatch
-            .query(&IProvideClassInfo2::IID, &mut provider_class_info_interface)
-    };
-    if result.is_ok() {
-        let provide_class_info2 = unsafe {
-            <IProvideClassInfo2 as Vtable>::from_raw(provider_class_info_interface as *mut _)
-        };
+    let dispatch = ole.dispatch();
+    let provide_class_info2 = dispatch
+        .as_ref()
+        .and_then(|dispatch| dispatch.cast::<IProvideClassInfo2>().ok());
+    if let Some(provide_class_info2) = provide_class_info2 {
         let piid =
             unsafe { provide_class_info2.GetGUID(GUIDKIND_DEFAULT_SOURCE_DISP_IID.0 as u32) };
         if let Ok(piid) = piid {
@@ -318,21 +801,17 @@ fn find_default_source(ole: &OleData) -> Result<GuidInfo> {
     }
 
     let mut typeinfo = None;
-    let result = unsafe {
-        ole.dispatch
-            .query(&IProvideClassInfo::IID, &mut provider_class_info_interface)
-    };
-    if result.is_ok() {
-        let provide_class_info = unsafe {
-            <IProvideClassInfo as Vtable>::from_raw(provider_class_info_interface as *mut _)
-        };
+    let provide_class_info = dispatch
+        .as_ref()
+        .and_then(|dispatch| dispatch.cast::<IProvideClassInfo>().ok());
+    if let Some(provide_class_info) = provide_class_info {
         let classinfo = unsafe { provide_class_info.GetClassInfo() };
         if let Ok(classinfo) = classinfo {
             typeinfo = Some(classinfo);
         }
     }
     if typeinfo.is_none() {
-        typeinfo = Some(unsafe { ole.dispatch.GetTypeInfo(0, GetUserDefaultLCID()) }?);
+        typeinfo = Some(ole.backend.type_info()?);
     }
     let typeinfo = typeinfo.unwrap();
     let typeattr = unsafe { typeinfo.GetTypeAttr() }?;
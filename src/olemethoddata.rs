@@ -225,18 +225,19 @@ impl OleMethodData {
     }
     pub fn params(&self) -> Vec<Result<OleParamData>> {
         let cparams = unsafe { self.func_desc.as_ref().cParams };
-        let mut cmaxnames = cparams as u32 + 1;
-        let mut rgbstrnames = Vec::with_capacity(cmaxnames as usize);
+        let mut rgbstrnames = vec![BSTR::default(); cparams as usize + 1];
+        let mut cnames = 0;
         let result = unsafe {
             self.typeinfo.GetNames(
                 self.func_desc.as_ref().memid,
                 &mut rgbstrnames,
-                &mut cmaxnames,
+                &mut cnames,
             )
         };
         if result.is_err() {
             return vec![];
         }
+        rgbstrnames.truncate(cnames as usize);
         let mut params = vec![];
 
         if cparams > 0 {
@@ -2,7 +2,11 @@ use std::ptr::NonNull;
 
 use windows::Win32::System::{
     Com::{ITypeInfo, ELEMDESC, FUNCDESC, TYPEDESC},
-    Ole::{PARAMFLAGS, PARAMFLAG_FIN, PARAMFLAG_FOPT, PARAMFLAG_FOUT, PARAMFLAG_FRETVAL},
+    Ole::{
+        PARAMFLAGS, PARAMFLAG_FHASDEFAULT, PARAMFLAG_FIN, PARAMFLAG_FOPT, PARAMFLAG_FOUT,
+        PARAMFLAG_FRETVAL,
+    },
+    Variant::VARIANT,
 };
 
 use crate::{
@@ -84,24 +88,19 @@ impl OleParamData {
     pub fn retval(&self) -> bool {
         self.ole_param_flag_mask(PARAMFLAG_FRETVAL.0)
     }
-    /*pub fn default_val<T>(&self) -> Option<T> {
-        let mask = PARAMFLAGS(PARAMFLAG_FOPT.0 | PARAMFLAG_FHASDEFAULT.0);
-        let funcdesc = unsafe { self.typeinfo.GetFuncDesc(self.index) };
-        let funcdesc = if let Ok(funcdesc) = funcdesc {
-            funcdesc
-        } else {
+    pub fn has_default(&self) -> bool {
+        self.ole_param_flag_mask(PARAMFLAG_FHASDEFAULT.0)
+    }
+    pub fn default_value(&self) -> Option<VARIANT> {
+        if !self.has_default() {
+            return None;
+        }
+        let paramdescex = unsafe { self.elem_desc().Anonymous.paramdesc.pparamdescex };
+        if paramdescex.is_null() {
             return None;
-        };
-        let elemdesc = unsafe { (*funcdesc).lprgelemdescParam.offset(self.index as isize) };
-        let paramflags = unsafe { (*elemdesc).Anonymous.paramdesc.wParamFlags };
-        let mut defval = None;
-        if paramflags & mask == mask {
-            let paramdescex = unsafe { (*elemdesc).Anonymous.paramdesc.pparamdescex };
-            defval = ole_variant2val(unsafe { &(*paramdescex).varDefaultValue });
         }
-        unsafe { self.typeinfo.ReleaseFuncDesc(funcdesc) };
-        defval
-    }*/
+        Some(unsafe { (*paramdescex).varDefaultValue.clone() })
+    }
     pub fn elem_desc(&self) -> &ELEMDESC {
         unsafe {
             &*self
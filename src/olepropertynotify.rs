@@ -0,0 +1,202 @@
+//! Property change notifications through `IPropertyNotifySink`.
+//!
+//! ActiveX controls and many automation objects announce property changes through the
+//! `IPropertyNotifySink` connection point instead of dispinterface events. An
+//! [`OlePropertyNotify`] subscribes to it and reports each notification with the DISPID
+//! resolved to a property name:
+//!
+//! ```ignore
+//! let notify = OlePropertyNotify::new(&control)?;
+//! notify.on_changed(move |change| {
+//!     if let Some(name) = &change.name {
+//!         mirror.refresh(name);
+//!     }
+//! });
+//! // Keep the caption as it is.
+//! notify.on_request_edit(|change| change.name.as_deref() != Some("Caption"));
+//! ```
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+};
+
+use windows::{
+    core::{implement, Interface},
+    Win32::{
+        Foundation::S_FALSE,
+        System::{
+            Com::{IConnectionPoint, IConnectionPointContainer},
+            Ole::{IPropertyNotifySink, IPropertyNotifySink_Impl, DISPID_UNKNOWN},
+        },
+    },
+};
+
+use crate::{
+    error::{Error, Result},
+    OleData,
+};
+
+type ChangedHandler = Rc<RefCell<dyn FnMut(&PropertyChange)>>;
+type RequestEditHandler = Rc<RefCell<dyn FnMut(&PropertyChange) -> bool>>;
+
+/// A property the object reported through `IPropertyNotifySink`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PropertyChange {
+    pub dispid: i32,
+    /// Name of the property, `None` if the type info does not know the DISPID or the object
+    /// reported `DISPID_UNKNOWN`, meaning that any number of properties may have changed
+    pub name: Option<String>,
+}
+
+impl PropertyChange {
+    /// Whether the notification concerns every property rather than a single one
+    ///
+    pub fn is_unknown(&self) -> bool {
+        self.dispid == DISPID_UNKNOWN
+    }
+}
+
+#[derive(Default)]
+struct NotifyState {
+    names: HashMap<i32, String>,
+    changed: Option<ChangedHandler>,
+    request_edit: Option<RequestEditHandler>,
+}
+
+impl NotifyState {
+    fn change(&self, dispid: i32) -> PropertyChange {
+        PropertyChange {
+            dispid,
+            name: self.names.get(&dispid).cloned(),
+        }
+    }
+}
+
+/// A subscription to the property notifications of an object
+pub struct OlePropertyNotify {
+    cookie: Cell<Option<u32>>,
+    connection_point: IConnectionPoint,
+    state: Rc<RefCell<NotifyState>>,
+}
+
+impl OlePropertyNotify {
+    /// Connect to the `IPropertyNotifySink` connection point of `ole`
+    ///
+    pub fn new(ole: &OleData) -> Result<OlePropertyNotify> {
+        let Some(dispatch) = ole.dispatch() else {
+            return Err(Error::Custom("the object is not a COM object".into()));
+        };
+        let container: IConnectionPointContainer = dispatch.cast()?;
+        let connection_point = unsafe { container.FindConnectionPoint(&IPropertyNotifySink::IID)? };
+
+        let mut names = HashMap::new();
+        // Objects without type info still notify, just without names.
+        if let Ok(methods) = ole.ole_methods() {
+            for method in methods {
+                names
+                    .entry(method.dispid())
+                    .or_insert_with(|| method.name().to_string());
+            }
+        }
+        if let Ok(ole_type) = ole.ole_type() {
+            for variable in ole_type.variables().into_iter().flatten() {
+                names
+                    .entry(variable.member_id())
+                    .or_insert_with(|| variable.name().to_string());
+            }
+        }
+        let state = Rc::new(RefCell::new(NotifyState {
+            names,
+            ..Default::default()
+        }));
+        let sink: IPropertyNotifySink = PropertyNotifySink {
+            state: state.clone(),
+        }
+        .into();
+        let cookie = unsafe { connection_point.Advise(&sink)? };
+        Ok(OlePropertyNotify {
+            cookie: Cell::new(Some(cookie)),
+            connection_point,
+            state,
+        })
+    }
+    /// Call `handler` after a property has changed, replacing any previous handler
+    ///
+    pub fn on_changed<F>(&self, handler: F)
+    where
+        F: FnMut(&PropertyChange) + 'static,
+    {
+        self.state.borrow_mut().changed = Some(Rc::new(RefCell::new(handler)));
+    }
+    /// Call `handler` before a property is changed. Returning `false` vetoes the change.
+    ///
+    /// Without a handler every edit is allowed.
+    pub fn on_request_edit<F>(&self, handler: F)
+    where
+        F: FnMut(&PropertyChange) -> bool + 'static,
+    {
+        self.state.borrow_mut().request_edit = Some(Rc::new(RefCell::new(handler)));
+    }
+    /// Name of the property `dispid`, as reported in notifications
+    ///
+    pub fn property_name(&self, dispid: i32) -> Option<String> {
+        self.state.borrow().names.get(&dispid).cloned()
+    }
+    /// Disconnect from the object. Dropping the `OlePropertyNotify` does the same.
+    ///
+    pub fn unadvise(&self) -> Result<()> {
+        if let Some(cookie) = self.cookie.take() {
+            unsafe { self.connection_point.Unadvise(cookie)? };
+        }
+        Ok(())
+    }
+}
+
+impl Drop for OlePropertyNotify {
+    fn drop(&mut self) {
+        let _ = self.unadvise();
+    }
+}
+
+#[implement(IPropertyNotifySink)]
+struct PropertyNotifySink {
+    state: Rc<RefCell<NotifyState>>,
+}
+
+impl IPropertyNotifySink_Impl for PropertyNotifySink {
+    fn OnChanged(&self, dispid: i32) -> windows::core::Result<()> {
+        let (change, handler) = {
+            let state = self.state.borrow();
+            (state.change(dispid), state.changed.clone())
+        };
+        if let Some(handler) = handler {
+            // A change made from inside the handler itself is not reported again.
+            if let Ok(mut handler) = handler.try_borrow_mut() {
+                (*handler)(&change);
+            }
+        }
+        Ok(())
+    }
+
+    fn OnRequestEdit(&self, dispid: i32) -> windows::core::Result<()> {
+        let (change, handler) = {
+            let state = self.state.borrow();
+            (state.change(dispid), state.request_edit.clone())
+        };
+        let allowed = match handler {
+            Some(handler) => match handler.try_borrow_mut() {
+                Ok(mut handler) => (*handler)(&change),
+                Err(_) => true,
+            },
+            None => true,
+        };
+        if allowed {
+            Ok(())
+        } else {
+            // S_FALSE tells the object not to change the property.
+            Err(S_FALSE.into())
+        }
+    }
+}
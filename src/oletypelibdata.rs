@@ -7,11 +7,9 @@ use std::{
 
 use crate::{
     error::{Error, OleError, Result},
+    registry::{Hive, NativeRegistry, Registry, RegistryKey},
     types::{OleClassNames, TypeInfos},
-    util::{
-        conv::{os_string_from_ptr, ToWide},
-        RegKey,
-    },
+    util::conv::{os_string_from_ptr, ToWide},
     OleTypeData,
 };
 use windows::{
@@ -21,12 +19,10 @@ use windows::{
         Globalization::GetUserDefaultLCID,
         System::{
             Com::{ITypeInfo, ITypeLib, SYSKIND, TLIBATTR},
-            Environment::ExpandEnvironmentStringsW,
             Ole::{
                 LoadTypeLibEx, QueryPathOfRegTypeLib, LIBFLAG_FHIDDEN, LIBFLAG_FRESTRICTED,
                 REGKIND_NONE,
             },
-            Registry::HKEY_CLASSES_ROOT,
         },
     },
 };
@@ -104,9 +100,10 @@ pub struct OleTypeLibData {
 
 impl OleTypeLibData {
     pub fn new1<S: AsRef<str>>(typelib_str: S) -> Result<OleTypeLibData> {
-        let mut typelibdata = oletypelib_search_registry(&typelib_str);
+        let mut typelibdata = oletypelib_search_registry(&NativeRegistry, &typelib_str);
         if typelibdata.is_err() {
-            typelibdata = oletypelib_search_registry2([typelib_str.as_ref(), "", ""]);
+            typelibdata =
+                oletypelib_search_registry2(&NativeRegistry, [typelib_str.as_ref(), "", ""]);
         } else {
             return typelibdata;
         }
@@ -134,10 +131,13 @@ impl OleTypeLibData {
         }
     }
     pub fn new2<S: AsRef<str>>(typelib_str: S, version: f64) -> Result<OleTypeLibData> {
-        let mut typelibdata = oletypelib_search_registry(&typelib_str);
+        let mut typelibdata = oletypelib_search_registry(&NativeRegistry, &typelib_str);
         if typelibdata.is_err() {
             let version_str = version.to_string();
-            typelibdata = oletypelib_search_registry2([typelib_str.as_ref(), &version_str, ""]);
+            typelibdata = oletypelib_search_registry2(
+                &NativeRegistry,
+                [typelib_str.as_ref(), &version_str, ""],
+            );
         } else {
             return typelibdata;
         }
@@ -165,10 +165,12 @@ impl OleTypeLibData {
         }
     }
     pub fn new3<S: AsRef<str>>(typelib_str: S, major: S, minor: S) -> Result<OleTypeLibData> {
-        let mut typelibdata = oletypelib_search_registry(&typelib_str);
+        let mut typelibdata = oletypelib_search_registry(&NativeRegistry, &typelib_str);
         if typelibdata.is_err() {
-            typelibdata =
-                oletypelib_search_registry2([typelib_str.as_ref(), major.as_ref(), minor.as_ref()]);
+            typelibdata = oletypelib_search_registry2(
+                &NativeRegistry,
+                [typelib_str.as_ref(), major.as_ref(), minor.as_ref()],
+            );
         } else {
             return typelibdata;
         }
@@ -288,8 +290,8 @@ impl TryFrom<&ITypeInfo> for OleTypeLibData {
     }
 }
 
-fn typelib_file_from_typelib<P: AsRef<OsStr>>(ole: P) -> Result<PathBuf> {
-    let htypelib = RegKey::predef(HKEY_CLASSES_ROOT).open_subkey("TypeLib")?;
+fn typelib_file_from_typelib<P: AsRef<OsStr>>(registry: &dyn Registry, ole: P) -> Result<PathBuf> {
+    let htypelib = RegistryKey::open(registry, Hive::ClassesRoot, "TypeLib")?;
     let mut found = false;
     let mut file = None;
 
@@ -343,7 +345,7 @@ fn typelib_file_from_typelib<P: AsRef<OsStr>>(ole: P) -> Result<PathBuf> {
     file.unwrap()
 }
 
-fn reg_get_typelib_file_path(hkey: RegKey) -> Option<Result<PathBuf>> {
+fn reg_get_typelib_file_path(hkey: RegistryKey) -> Option<Result<PathBuf>> {
     let hwin64 = hkey.open_subkey("win64");
     if let Ok(hwin64) = hwin64 {
         let path = hwin64.get_value("");
@@ -370,40 +372,47 @@ fn reg_get_typelib_file_path(hkey: RegKey) -> Option<Result<PathBuf>> {
     None
 }
 
-fn typelib_file_from_clsid<P: AsRef<OsStr>>(ole: P) -> Result<PathBuf> {
-    let hroot = RegKey::predef(HKEY_CLASSES_ROOT).open_subkey("CLSID")?;
+fn typelib_file_from_clsid<P: AsRef<OsStr>>(registry: &dyn Registry, ole: P) -> Result<PathBuf> {
+    let hroot = RegistryKey::open(registry, Hive::ClassesRoot, "CLSID")?;
 
-    let hclsid = hroot.open_subkey(ole)?;
+    let hclsid = hroot.open_subkey(ole.as_ref().to_string_lossy())?;
     let htypelib = hclsid.open_subkey("InprocServer32");
     let typelib = if let Ok(htypelib) = htypelib {
         htypelib.get_value("")
     } else {
         hclsid.get_value("InprocServer32")
     };
-    match typelib {
-        Ok(typelib) => {
-            let typelib_pcwstr = PCWSTR::from_raw(typelib.to_wide_null().as_ptr());
-            let len = unsafe { ExpandEnvironmentStringsW(typelib_pcwstr, None) };
-            let mut path = vec![0; len as usize + 1];
-            unsafe { ExpandEnvironmentStringsW(typelib_pcwstr, Some(&mut path)) };
-            let path = PathBuf::from(unsafe { typelib_pcwstr.to_string()? });
-            Ok(path)
-        }
-        Err(error) => Err(error),
-    }
+    // `REG_EXPAND_SZ` values come back with the environment variables already expanded.
+    typelib.map(PathBuf::from)
 }
 
 pub(crate) fn typelib_file<P: AsRef<OsStr>>(ole: P) -> Result<PathBuf> {
-    let file = typelib_file_from_clsid(&ole);
+    typelib_file_in(&NativeRegistry, ole)
+}
+
+/// The file implementing the class or type library `ole`, as registered in `registry`
+///
+pub fn typelib_file_in<P: AsRef<OsStr>>(registry: &dyn Registry, ole: P) -> Result<PathBuf> {
+    let file = typelib_file_from_clsid(registry, &ole);
     match file {
         Ok(file) => Ok(file),
-        Err(_) => typelib_file_from_typelib(&ole),
+        Err(_) => typelib_file_from_typelib(registry, &ole),
     }
 }
 
 pub fn oletypelib_path(guid: &str, version: &str) -> Option<Result<PathBuf>> {
+    oletypelib_path_in(&NativeRegistry, guid, version)
+}
+
+/// The file of the type library `guid` at `version`, as registered in `registry`
+///
+pub fn oletypelib_path_in(
+    registry: &dyn Registry,
+    guid: &str,
+    version: &str,
+) -> Option<Result<PathBuf>> {
     let key = format!(r"TypeLib\{guid}\{version}");
-    let hkey = RegKey::predef(HKEY_CLASSES_ROOT).open_subkey(key);
+    let hkey = RegistryKey::open(registry, Hive::ClassesRoot, &key);
     if let Ok(hkey) = hkey {
         let mut iter = hkey.enum_keys();
         loop {
@@ -427,7 +436,17 @@ pub fn oletypelib_path(guid: &str, version: &str) -> Option<Result<PathBuf>> {
 }
 
 pub fn oletypelib_from_guid(guid: &str, version: &str) -> Result<ITypeLib> {
-    let path = oletypelib_path(guid, version);
+    oletypelib_from_guid_in(&NativeRegistry, guid, version)
+}
+
+/// Load the type library `guid` at `version` from the file registered in `registry`
+///
+pub fn oletypelib_from_guid_in(
+    registry: &dyn Registry,
+    guid: &str,
+    version: &str,
+) -> Result<ITypeLib> {
+    let path = oletypelib_path_in(registry, guid, version);
     let Some(path) = path else {
         return Err(windows::core::Error::from(E_UNEXPECTED).into());
     };
@@ -440,10 +459,13 @@ pub fn oletypelib_from_guid(guid: &str, version: &str) -> Result<ITypeLib> {
     }
 }
 
-fn oletypelib_search_registry<S: AsRef<str>>(typelib_str: S) -> Result<OleTypeLibData> {
+fn oletypelib_search_registry<S: AsRef<str>>(
+    registry: &dyn Registry,
+    typelib_str: S,
+) -> Result<OleTypeLibData> {
     let mut found = false;
     let mut maybe_oletypelibdata = None;
-    let htypelib = RegKey::predef(HKEY_CLASSES_ROOT).open_subkey("TypeLib")?;
+    let htypelib = RegistryKey::open(registry, Hive::ClassesRoot, "TypeLib")?;
 
     for guid_or_error in htypelib.enum_keys() {
         if found {
@@ -472,7 +494,7 @@ fn oletypelib_search_registry<S: AsRef<str>>(typelib_str: S) -> Result<OleTypeLi
                 continue;
             };
             if typelib_str.as_ref() == tlib {
-                let typelib = oletypelib_from_guid(&guid, &version);
+                let typelib = oletypelib_from_guid_in(registry, &guid, &version);
                 if let Ok(typelib) = typelib {
                     let name = name_from_typelib(&typelib);
                     let tlib_attr = unsafe { typelib.GetLibAttr() }?;
@@ -497,12 +519,12 @@ fn oletypelib_search_registry<S: AsRef<str>>(typelib_str: S) -> Result<OleTypeLi
     }
 }
 
-fn oletypelib_search_registry2(args: [&str; 3]) -> Result<OleTypeLibData> {
+fn oletypelib_search_registry2(registry: &dyn Registry, args: [&str; 3]) -> Result<OleTypeLibData> {
     let mut maybe_oletypelibdata = None;
     let guid = args[0];
     let version_str = make_version_str(args[1], args[2]);
 
-    let htypelib = RegKey::predef(HKEY_CLASSES_ROOT).open_subkey("TypeLib")?;
+    let htypelib = RegistryKey::open(registry, Hive::ClassesRoot, "TypeLib")?;
 
     let hguid = htypelib.open_subkey(guid)?;
 
@@ -540,7 +562,7 @@ fn oletypelib_search_registry2(args: [&str; 3]) -> Result<OleTypeLibData> {
         }
     }
     if !typelib_str.is_empty() {
-        let typelib = oletypelib_from_guid(guid, &version);
+        let typelib = oletypelib_from_guid_in(registry, guid, &version);
         if let Ok(typelib) = typelib {
             let name = name_from_typelib(&typelib);
             let tlib_attr = unsafe { typelib.GetLibAttr() }?;
@@ -607,3 +629,106 @@ fn ole_types_from_typelib(typelib: &ITypeLib) -> Vec<Result<OleTypeData>> {
     }
     classes
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::MemoryRegistry;
+
+    /// The value of `result`, without `Debug`ging the error, which needs oleaut32
+    fn value<T>(result: Result<T>) -> T {
+        match result {
+            Ok(value) => value,
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    const GUID: &str = "{00020813-0000-0000-C000-000000000046}";
+    const CLSID: &str = "{00024500-0000-0000-C000-000000000046}";
+
+    fn registry() -> MemoryRegistry {
+        let mut registry = MemoryRegistry::new();
+        let version = format!(r"TypeLib\{GUID}\1.9");
+        registry.set_value(
+            Hive::ClassesRoot,
+            &version,
+            "",
+            "Microsoft Excel 16.0 Object Library",
+        );
+        registry.set_value(
+            Hive::ClassesRoot,
+            &format!(r"{version}\0\win32"),
+            "",
+            r"C:\Office\EXCEL32.EXE",
+        );
+        registry
+    }
+
+    #[test]
+    fn typelib_path() {
+        let path = value(oletypelib_path_in(&registry(), GUID, "1.9").unwrap());
+        assert_eq!(path, PathBuf::from(r"C:\Office\EXCEL32.EXE"));
+    }
+
+    #[test]
+    fn typelib_path_prefers_win64() {
+        let mut registry = registry();
+        registry.set_value(
+            Hive::ClassesRoot,
+            &format!(r"TypeLib\{GUID}\1.9\0\win64"),
+            "",
+            r"C:\Office\EXCEL.EXE",
+        );
+        let path = value(oletypelib_path_in(&registry, GUID, "1.9").unwrap());
+        assert_eq!(path, PathBuf::from(r"C:\Office\EXCEL.EXE"));
+    }
+
+    #[test]
+    fn typelib_path_missing() {
+        let registry = registry();
+        assert!(oletypelib_path_in(&registry, GUID, "2.0").is_none());
+        assert!(oletypelib_path_in(&registry, CLSID, "1.9").is_none());
+
+        let mut registry = registry;
+        registry.create_key(Hive::ClassesRoot, &format!(r"TypeLib\{GUID}\1.9\0"));
+        registry.delete_key(Hive::ClassesRoot, &format!(r"TypeLib\{GUID}\1.9\0\win32"));
+        assert!(oletypelib_path_in(&registry, GUID, "1.9").is_none());
+    }
+
+    #[test]
+    fn file_of_class() {
+        let mut registry = registry();
+        registry.set_value(
+            Hive::ClassesRoot,
+            &format!(r"CLSID\{CLSID}\InprocServer32"),
+            "",
+            r"C:\Office\excel.dll",
+        );
+        let file = value(typelib_file_in(&registry, CLSID));
+        assert_eq!(file, PathBuf::from(r"C:\Office\excel.dll"));
+    }
+
+    #[test]
+    fn file_of_class_value() {
+        let mut registry = registry();
+        registry.set_value(
+            Hive::ClassesRoot,
+            &format!(r"CLSID\{CLSID}"),
+            "InprocServer32",
+            r"C:\Office\excel.dll",
+        );
+        let file = value(typelib_file_in(&registry, CLSID));
+        assert_eq!(file, PathBuf::from(r"C:\Office\excel.dll"));
+    }
+
+    #[test]
+    fn file_of_typelib() {
+        let mut registry = registry();
+        registry.create_key(Hive::ClassesRoot, "CLSID");
+        let file = value(typelib_file_in(
+            &registry,
+            "Microsoft Excel 16.0 Object Library",
+        ));
+        assert_eq!(file, PathBuf::from(r"C:\Office\EXCEL32.EXE"));
+    }
+}
@@ -0,0 +1,500 @@
+//! Parser for VB-like member paths such as `Workbooks(1).Worksheets("Data").Range("A1").Value`.
+//!
+//! Parsing is pure Rust; evaluation against a live object happens in [`crate::OleData::eval`].
+
+use std::fmt;
+
+/// A parsed path, optionally ending in an assignment
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathExpr {
+    pub segments: Vec<Segment>,
+    pub assignment: Option<Box<PathArg>>,
+}
+
+/// One member access in a path
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub kind: SegmentKind,
+    /// Argument lists following the member. The first list is passed to the member itself;
+    /// each further list indexes the result's default member, as in `Sheets(1)(2)`.
+    pub calls: Vec<Vec<PathArg>>,
+    /// Character offset of the segment in the source text
+    pub position: usize,
+    pub text: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SegmentKind {
+    /// `Name` or `Name(args)`
+    Member(String),
+    /// Excel's bracket syntax, `[A1:B2]`, evaluated through `DISPID_EVALUATE`
+    Evaluate(String),
+}
+
+/// An argument or assigned value
+#[derive(Clone, Debug, PartialEq)]
+pub enum PathArg {
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Path(PathExpr),
+}
+
+/// A syntax error with the character offset where it was detected
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{} at column {}", self.message, self.position + 1)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Bracket(String),
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Dot,
+    Comma,
+    Open,
+    Close,
+    Assign,
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(fmt, "`{name}`"),
+            Token::Bracket(text) => write!(fmt, "`[{text}]`"),
+            Token::Str(text) => write!(fmt, "string \"{text}\""),
+            Token::Int(value) => write!(fmt, "number {value}"),
+            Token::Float(value) => write!(fmt, "number {value}"),
+            Token::Dot => write!(fmt, "`.`"),
+            Token::Comma => write!(fmt, "`,`"),
+            Token::Open => write!(fmt, "`(`"),
+            Token::Close => write!(fmt, "`)`"),
+            Token::Assign => write!(fmt, "`=`"),
+            Token::End => write!(fmt, "end of input"),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '.' if !chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()) => {
+                tokens.push((Token::Dot, start));
+                i += 1;
+            }
+            ',' => {
+                tokens.push((Token::Comma, start));
+                i += 1;
+            }
+            '(' => {
+                tokens.push((Token::Open, start));
+                i += 1;
+            }
+            ')' => {
+                tokens.push((Token::Close, start));
+                i += 1;
+            }
+            '=' => {
+                tokens.push((Token::Assign, start));
+                i += 1;
+            }
+            '"' => {
+                let mut text = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => {
+                            return Err(ParseError {
+                                position: start,
+                                message: "unterminated string".into(),
+                            })
+                        }
+                        // VB escapes a quote inside a string by doubling it.
+                        Some('"') if chars.get(i + 1) == Some(&'"') => {
+                            text.push('"');
+                            i += 2;
+                        }
+                        Some('"') => {
+                            i += 1;
+                            break;
+                        }
+                        Some(&c) => {
+                            text.push(c);
+                            i += 1;
+                        }
+                    }
+                }
+                tokens.push((Token::Str(text), start));
+            }
+            '[' => {
+                let Some(len) = chars[i + 1..].iter().position(|c| *c == ']') else {
+                    return Err(ParseError {
+                        position: start,
+                        message: "unterminated `[`".into(),
+                    });
+                };
+                let text: String = chars[i + 1..i + 1 + len].iter().collect();
+                tokens.push((Token::Bracket(text), start));
+                i += len + 2;
+            }
+            c if c.is_ascii_digit() || c == '-' || c == '.' => {
+                i += 1;
+                while i < chars.len()
+                    && (chars[i].is_ascii_digit()
+                        || chars[i] == '.'
+                        || chars[i] == 'e'
+                        || chars[i] == 'E'
+                        || ((chars[i] == '-' || chars[i] == '+')
+                            && matches!(chars[i - 1], 'e' | 'E')))
+                {
+                    i += 1;
+                }
+                let text: String = chars[start..i].iter().collect();
+                let token = if let Ok(value) = text.parse::<i64>() {
+                    Token::Int(value)
+                } else if let Ok(value) = text.parse::<f64>() {
+                    Token::Float(value)
+                } else {
+                    return Err(ParseError {
+                        position: start,
+                        message: format!("invalid number `{text}`"),
+                    });
+                };
+                tokens.push((token, start));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                tokens.push((Token::Ident(chars[start..i].iter().collect()), start));
+            }
+            c => {
+                return Err(ParseError {
+                    position: start,
+                    message: format!("unexpected character `{c}`"),
+                })
+            }
+        }
+    }
+    tokens.push((Token::End, chars.len()));
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<(Token, usize)>,
+    index: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.index].0
+    }
+    fn position(&self) -> usize {
+        self.tokens[self.index].1
+    }
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.index].0.clone();
+        if token != Token::End {
+            self.index += 1;
+        }
+        token
+    }
+    fn unexpected(&self, expected: &str) -> ParseError {
+        ParseError {
+            position: self.position(),
+            message: format!("expected {expected} but found {}", self.peek()),
+        }
+    }
+    fn text_between(&self, start: usize, end: usize) -> String {
+        self.source
+            .chars()
+            .skip(start)
+            .take(end - start)
+            .collect::<String>()
+            .trim()
+            .to_string()
+    }
+    fn path(&mut self) -> Result<PathExpr, ParseError> {
+        let mut segments = vec![self.segment()?];
+        while *self.peek() == Token::Dot {
+            self.advance();
+            segments.push(self.segment()?);
+        }
+        Ok(PathExpr {
+            segments,
+            assignment: None,
+        })
+    }
+    fn segment(&mut self) -> Result<Segment, ParseError> {
+        let position = self.position();
+        let kind = match self.peek().clone() {
+            Token::Ident(name) => SegmentKind::Member(name),
+            Token::Bracket(text) => SegmentKind::Evaluate(text),
+            _ => return Err(self.unexpected("a member name")),
+        };
+        self.advance();
+        let mut calls = vec![];
+        while *self.peek() == Token::Open {
+            self.advance();
+            calls.push(self.args()?);
+        }
+        let end = self.position();
+        Ok(Segment {
+            kind,
+            calls,
+            position,
+            text: self.text_between(position, end),
+        })
+    }
+    fn args(&mut self) -> Result<Vec<PathArg>, ParseError> {
+        let mut args = vec![];
+        if *self.peek() == Token::Close {
+            self.advance();
+            return Ok(args);
+        }
+        loop {
+            args.push(self.arg()?);
+            match self.peek() {
+                Token::Comma => self.advance(),
+                Token::Close => {
+                    self.advance();
+                    break;
+                }
+                _ => return Err(self.unexpected("`,` or `)`")),
+            };
+        }
+        Ok(args)
+    }
+    fn arg(&mut self) -> Result<PathArg, ParseError> {
+        match self.peek().clone() {
+            Token::Str(text) => {
+                self.advance();
+                Ok(PathArg::Str(text))
+            }
+            Token::Int(value) => {
+                self.advance();
+                Ok(PathArg::Int(value))
+            }
+            Token::Float(value) => {
+                self.advance();
+                Ok(PathArg::Float(value))
+            }
+            Token::Ident(ref name)
+                if name.eq_ignore_ascii_case("true") || name.eq_ignore_ascii_case("false") =>
+            {
+                self.advance();
+                Ok(PathArg::Bool(name.eq_ignore_ascii_case("true")))
+            }
+            Token::Ident(_) | Token::Bracket(_) => Ok(PathArg::Path(self.path()?)),
+            _ => Err(self.unexpected("an argument")),
+        }
+    }
+}
+
+/// Parse a path expression such as `Workbooks(1).Worksheets("Data").Range("A1").Value = 5`
+///
+pub fn parse(source: &str) -> Result<PathExpr, ParseError> {
+    let mut parser = Parser {
+        source,
+        tokens: tokenize(source)?,
+        index: 0,
+    };
+    let mut path = parser.path()?;
+    if *parser.peek() == Token::Assign {
+        parser.advance();
+        path.assignment = Some(Box::new(parser.arg()?));
+    }
+    if *parser.peek() != Token::End {
+        return Err(parser.unexpected("end of input"));
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(name: &str, calls: Vec<Vec<PathArg>>, position: usize, text: &str) -> Segment {
+        Segment {
+            kind: SegmentKind::Member(name.into()),
+            calls,
+            position,
+            text: text.into(),
+        }
+    }
+
+    fn error(source: &str) -> (usize, String) {
+        let error = parse(source).unwrap_err();
+        (error.position, error.message)
+    }
+
+    #[test]
+    fn chained_members() {
+        let path = parse(r#"Workbooks(1).Worksheets("Data").Value"#).unwrap();
+        assert_eq!(
+            path.segments,
+            [
+                member("Workbooks", vec![vec![PathArg::Int(1)]], 0, "Workbooks(1)"),
+                member(
+                    "Worksheets",
+                    vec![vec![PathArg::Str("Data".into())]],
+                    13,
+                    r#"Worksheets("Data")"#
+                ),
+                member("Value", vec![], 32, "Value"),
+            ]
+        );
+        assert_eq!(path.assignment, None);
+    }
+
+    #[test]
+    fn arguments() {
+        let path =
+            parse(r#"Cells(2, -1.5e3, True, false, "a ""b""", Sheets(1).Name, [A1])"#).unwrap();
+        let [segment] = &path.segments[..] else {
+            panic!("expected one segment");
+        };
+        assert_eq!(
+            segment.calls,
+            [vec![
+                PathArg::Int(2),
+                PathArg::Float(-1500.0),
+                PathArg::Bool(true),
+                PathArg::Bool(false),
+                PathArg::Str(r#"a "b""#.into()),
+                PathArg::Path(PathExpr {
+                    segments: vec![
+                        member("Sheets", vec![vec![PathArg::Int(1)]], 41, "Sheets(1)"),
+                        member("Name", vec![], 51, "Name"),
+                    ],
+                    assignment: None,
+                }),
+                PathArg::Path(PathExpr {
+                    segments: vec![Segment {
+                        kind: SegmentKind::Evaluate("A1".into()),
+                        calls: vec![],
+                        position: 57,
+                        text: "[A1]".into(),
+                    }],
+                    assignment: None,
+                }),
+            ]]
+        );
+    }
+
+    #[test]
+    fn empty_and_repeated_calls() {
+        let path = parse("Refresh() . Sheets(1)(2)").unwrap();
+        assert_eq!(
+            path.segments,
+            [
+                member("Refresh", vec![vec![]], 0, "Refresh()"),
+                member(
+                    "Sheets",
+                    vec![vec![PathArg::Int(1)], vec![PathArg::Int(2)]],
+                    12,
+                    "Sheets(1)(2)"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn evaluate_segment() {
+        let path = parse("[A1:B2].Value").unwrap();
+        assert_eq!(path.segments[0].kind, SegmentKind::Evaluate("A1:B2".into()));
+        assert_eq!(path.segments[1], member("Value", vec![], 8, "Value"));
+    }
+
+    #[test]
+    fn assignment() {
+        let path = parse(r#"Range("A1").Value = 5"#).unwrap();
+        assert_eq!(path.segments.len(), 2);
+        assert_eq!(path.assignment, Some(Box::new(PathArg::Int(5))));
+
+        let path = parse("Caption = Sheets(1).Name").unwrap();
+        assert!(matches!(path.assignment.as_deref(), Some(PathArg::Path(_))));
+    }
+
+    #[test]
+    fn missing_member() {
+        let expected = "expected a member name but found end of input".to_string();
+        assert_eq!(error(""), (0, expected.clone()));
+        assert_eq!(error("   "), (3, expected.clone()));
+        assert_eq!(error("Foo."), (4, expected));
+        assert_eq!(
+            error("Foo.(1)"),
+            (4, "expected a member name but found `(`".into())
+        );
+    }
+
+    #[test]
+    fn unclosed_arguments() {
+        assert_eq!(
+            error("Foo(1"),
+            (5, "expected `,` or `)` but found end of input".into())
+        );
+        assert_eq!(
+            error("Foo(1 2)"),
+            (6, "expected `,` or `)` but found number 2".into())
+        );
+        assert_eq!(
+            error("Foo(1,)"),
+            (6, "expected an argument but found `)`".into())
+        );
+        assert_eq!(
+            error("Foo("),
+            (4, "expected an argument but found end of input".into())
+        );
+    }
+
+    #[test]
+    fn trailing_input() {
+        assert_eq!(
+            error("Foo Bar"),
+            (4, "expected end of input but found `Bar`".into())
+        );
+        assert_eq!(
+            error("Foo ="),
+            (5, "expected an argument but found end of input".into())
+        );
+    }
+
+    #[test]
+    fn lexical_errors() {
+        assert_eq!(error(r#"Foo("abc"#), (4, "unterminated string".into()));
+        assert_eq!(error("[A1"), (0, "unterminated `[`".into()));
+        assert_eq!(error("Foo#"), (3, "unexpected character `#`".into()));
+        assert_eq!(error("Foo(1.2.3)"), (4, "invalid number `1.2.3`".into()));
+    }
+
+    #[test]
+    fn error_display() {
+        assert_eq!(
+            parse("Foo(1").unwrap_err().to_string(),
+            "expected `,` or `)` but found end of input at column 6"
+        );
+    }
+}
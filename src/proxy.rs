@@ -0,0 +1,936 @@
+//! Thread-safe access to automation objects that live on a dedicated STA thread.
+//!
+//! An [`OleData`] must only be used on the apartment that created it. [`StaThread`] owns a
+//! single-threaded apartment with its own message loop, and [`OleProxy`] handles post every
+//! request to it over a channel, so they can be shared freely between threads and tasks.
+//!
+//! Events of proxied objects are received through an [`EventStream`]. The STA thread pumps
+//! messages on its own, so events arrive without the consumer doing anything but polling:
+//!
+//! ```ignore
+//! let excel = sta.create("Excel.Application")?;
+//! let mut events = excel.events(Some("AppEvents"), Backpressure::DropOldest(100))?;
+//! while let Some(event) = events.next().await {
+//!     println!("{} {:?}", event.name, event.args);
+//! }
+//! ```
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    future::{self, Future},
+    pin::Pin,
+    rc::Rc,
+    sync::{
+        mpsc::{self, Receiver, Sender, TryRecvError},
+        Arc, Condvar, Mutex,
+    },
+    task::{Context, Poll, Waker},
+    thread::{self, JoinHandle},
+};
+
+use windows::Win32::{
+    Foundation::{CloseHandle, HANDLE},
+    System::{
+        Com::{CoInitializeEx, CoUninitialize, COINIT_APARTMENTTHREADED},
+        Threading::{CreateEventW, SetEvent},
+        Variant::VARIANT,
+    },
+};
+
+use crate::{
+    error::{Error, Result},
+    eventtype::Event,
+    msgpump::{MessagePump, PumpExit},
+    retry::{MessageFilterGuard, RetryPolicy},
+    value::{IntoVariant, Variant},
+    OleData, OleEventData,
+};
+
+thread_local!(static RETRY_FILTER: RefCell<Option<MessageFilterGuard>> = const { RefCell::new(None) });
+
+/// A value returned by or passed to an [`OleProxy`]
+pub type ProxyValue = Variant<OleProxy>;
+
+/// An event received through an [`EventStream`]
+pub type ProxyEvent = Event<OleProxy>;
+
+struct SlotState<T> {
+    value: Option<T>,
+    waker: Option<Waker>,
+}
+
+/// One-shot rendezvous between the worker and a waiting caller
+struct Slot<T> {
+    state: Mutex<SlotState<T>>,
+    ready: Condvar,
+}
+
+impl<T> Slot<T> {
+    fn new() -> Arc<Slot<T>> {
+        Arc::new(Slot {
+            state: Mutex::new(SlotState {
+                value: None,
+                waker: None,
+            }),
+            ready: Condvar::new(),
+        })
+    }
+    fn fill(&self, value: T) {
+        let mut state = self.state.lock().unwrap();
+        state.value = Some(value);
+        self.ready.notify_all();
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+    fn wait(&self) -> T {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(value) = state.value.take() {
+                return value;
+            }
+            state = self.ready.wait(state).unwrap();
+        }
+    }
+    fn poll(&self, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.state.lock().unwrap();
+        match state.value.take() {
+            Some(value) => Poll::Ready(value),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+    fn take(&self) -> Option<T> {
+        self.state.lock().unwrap().value.take()
+    }
+}
+
+/// The worker's end of a [`Slot`]. Dropping it unanswered reports that the thread has gone.
+struct Reply<T> {
+    slot: Option<Arc<Slot<std::result::Result<T, String>>>>,
+}
+
+impl<T> Reply<T> {
+    fn send(mut self, value: std::result::Result<T, String>) {
+        if let Some(slot) = self.slot.take() {
+            slot.fill(value);
+        }
+    }
+}
+
+impl<T> Drop for Reply<T> {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot.take() {
+            slot.fill(Err("the STA thread has terminated".into()));
+        }
+    }
+}
+
+enum Call {
+    Get {
+        name: String,
+        args: Vec<Variant<u64>>,
+    },
+    Put {
+        name: String,
+        args: Vec<Variant<u64>>,
+        value: Variant<u64>,
+    },
+    Method {
+        name: String,
+        args: Vec<Variant<u64>>,
+    },
+    Eval(String),
+}
+
+enum Request {
+    Create {
+        prog_id: String,
+        reply: Reply<Variant<u64>>,
+    },
+    Invoke {
+        object: u64,
+        call: Call,
+        reply: Reply<Variant<u64>>,
+    },
+    Run(Box<dyn FnOnce() + Send>),
+    Release(u64),
+    Advise {
+        object: u64,
+        interface: Option<String>,
+        queue: Arc<EventQueue>,
+        reply: Reply<u64>,
+    },
+    Unadvise {
+        sink: u64,
+        reply: Reply<()>,
+    },
+    Shutdown,
+}
+
+/// The objects owned by the worker thread, keyed by the ids handed out to proxies
+///
+/// Event sinks register the objects passed to events while the apartment may be in the middle
+/// of a call, so the table is shared and only borrowed for the duration of a lookup.
+#[derive(Default)]
+struct ObjectTable {
+    objects: HashMap<u64, OleData>,
+    next_id: u64,
+}
+
+impl ObjectTable {
+    fn register(&mut self, object: OleData) -> u64 {
+        self.next_id += 1;
+        self.objects.insert(self.next_id, object);
+        self.next_id
+    }
+}
+
+/// An advised event source, whose stream ends when it is dropped
+struct Subscription {
+    _events: OleEventData,
+    queue: Arc<EventQueue>,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.queue.finish();
+    }
+}
+
+#[derive(Default)]
+struct Apartment {
+    table: Rc<RefCell<ObjectTable>>,
+    sinks: HashMap<u64, Subscription>,
+    next_sink: u64,
+}
+
+impl Apartment {
+    fn handle(&mut self, request: Request) {
+        match request {
+            Request::Create { prog_id, reply } => {
+                let result =
+                    OleData::new(&prog_id).map(|object| Variant::Object(self.register(object)));
+                reply.send(result.map_err(|error| format!("{error:#}")));
+            }
+            Request::Invoke {
+                object,
+                call,
+                reply,
+            } => {
+                let result = self.invoke(object, call);
+                reply.send(result.map_err(|error| format!("{error:#}")));
+            }
+            Request::Run(task) => task(),
+            Request::Release(id) => {
+                self.table.borrow_mut().objects.remove(&id);
+            }
+            Request::Advise {
+                object,
+                interface,
+                queue,
+                reply,
+            } => {
+                let result = self.advise(object, interface.as_deref(), queue);
+                reply.send(result.map_err(|error| format!("{error:#}")));
+            }
+            Request::Unadvise { sink, reply } => {
+                self.sinks.remove(&sink);
+                reply.send(Ok(()));
+            }
+            Request::Shutdown => {}
+        }
+    }
+    fn register(&self, object: OleData) -> u64 {
+        self.table.borrow_mut().register(object)
+    }
+    fn object(&self, id: u64) -> Result<OleData> {
+        self.table
+            .borrow()
+            .objects
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| Error::Custom(format!("proxy object {id} has been released")))
+    }
+    fn argument(&self, value: Variant<u64>) -> Result<VARIANT> {
+        Ok(value
+            .try_map_objects(&mut |id| self.object(id))?
+            .into_variant())
+    }
+    fn arguments(&self, args: Vec<Variant<u64>>) -> Result<Vec<VARIANT>> {
+        args.into_iter().map(|arg| self.argument(arg)).collect()
+    }
+    fn invoke(&mut self, id: u64, call: Call) -> Result<Variant<u64>> {
+        let object = self.object(id)?;
+        let result = match call {
+            Call::Get { name, args } => object.get_with(&name, self.arguments(args)?)?,
+            Call::Put { name, args, value } => {
+                let mut value = self.argument(value)?;
+                if args.is_empty() {
+                    object.put(&name, &mut value)?;
+                } else {
+                    object.put_with(&name, self.arguments(args)?, value)?;
+                }
+                return Ok(Variant::Empty);
+            }
+            Call::Method { name, args } => object.call(&name, self.arguments(args)?)?,
+            Call::Eval(expression) => object.eval(&expression)?,
+        };
+        Variant::try_from(&result)?.try_map_objects(&mut |object| Ok(self.register(object)))
+    }
+    fn advise(&mut self, id: u64, interface: Option<&str>, queue: Arc<EventQueue>) -> Result<u64> {
+        let events = OleEventData::new(&self.object(id)?, interface)?;
+        let table = self.table.clone();
+        let producer = queue.clone();
+        events.on_decoded(move |event| {
+            let event = event
+                .clone()
+                .try_map_objects(&mut |object| Ok::<_, ()>(table.borrow_mut().register(object)))
+                .unwrap();
+            if let Some(rejected) = producer.push(event) {
+                let _ = rejected.try_map_objects(&mut |id| {
+                    table.borrow_mut().objects.remove(&id);
+                    Ok::<_, ()>(())
+                });
+            }
+            Ok(())
+        });
+        self.next_sink += 1;
+        self.sinks.insert(
+            self.next_sink,
+            Subscription {
+                _events: events,
+                queue,
+            },
+        );
+        Ok(self.next_sink)
+    }
+}
+
+/// An auto-reset event, signalled whenever a request is posted to the STA thread
+struct WakeEvent(HANDLE);
+
+// Events can be signalled and waited on from any thread.
+unsafe impl Send for WakeEvent {}
+unsafe impl Sync for WakeEvent {}
+
+impl WakeEvent {
+    fn new() -> Result<WakeEvent> {
+        Ok(WakeEvent(unsafe {
+            CreateEventW(None, false, false, None)?
+        }))
+    }
+    fn set(&self) {
+        let _ = unsafe { SetEvent(self.0) };
+    }
+}
+
+impl Drop for WakeEvent {
+    fn drop(&mut self) {
+        let _ = unsafe { CloseHandle(self.0) };
+    }
+}
+
+fn run_apartment(
+    receiver: Receiver<Request>,
+    wake: Arc<WakeEvent>,
+    started: mpsc::SyncSender<std::result::Result<(), String>>,
+) {
+    if let Err(error) = unsafe { CoInitializeEx(None, COINIT_APARTMENTTHREADED) }.ok() {
+        let _ = started.send(Err(format!("{error:#}")));
+        return;
+    }
+    let _ = started.send(Ok(()));
+    let mut apartment = Apartment::default();
+    // Events and incoming calls are dispatched as soon as they arrive; posting a request
+    // signals `wake`, which ends the wait. `WM_QUIT` or a failed wait shuts the thread down
+    // like `Shutdown`, and the requests still queued are dropped, which fails their replies.
+    let mut pump = MessagePump::new().wait_on(wake.0);
+    'apartment: loop {
+        loop {
+            match receiver.try_recv() {
+                Ok(Request::Shutdown) | Err(TryRecvError::Disconnected) => break 'apartment,
+                Ok(request) => {
+                    apartment.handle(request);
+                    if let Some(PumpExit::Quit(_)) = pump.pump() {
+                        break 'apartment;
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+        if let PumpExit::Quit(_) | PumpExit::Failed(_) = pump.run_until(|| false) {
+            break;
+        }
+    }
+    drop(apartment);
+    RETRY_FILTER.with(|filter| filter.borrow_mut().take());
+    unsafe { CoUninitialize() };
+}
+
+struct Worker {
+    sender: Sender<Request>,
+    wake: Arc<WakeEvent>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    fn post(&self, request: Request) {
+        // A failed send drops the request, and with it any `Reply`, which reports the error.
+        let _ = self.sender.send(request);
+        self.wake.set();
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.post(Request::Shutdown);
+        if let Some(thread) = self.thread.take() {
+            if thread.thread().id() != thread::current().id() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+/// A dedicated single-threaded apartment with its own message loop
+///
+/// The thread lives for as long as the `StaThread` or any proxy created on it.
+#[derive(Clone)]
+pub struct StaThread {
+    worker: Arc<Worker>,
+}
+
+impl StaThread {
+    /// Start a new STA worker thread
+    ///
+    pub fn spawn() -> Result<StaThread> {
+        let (sender, receiver) = mpsc::channel();
+        let (started, startup) = mpsc::sync_channel(1);
+        let wake = Arc::new(WakeEvent::new()?);
+        let thread_wake = wake.clone();
+        let thread = thread::Builder::new()
+            .name("win32ole-sta".into())
+            .spawn(move || run_apartment(receiver, thread_wake, started))?;
+        match startup.recv() {
+            Ok(Ok(())) => {}
+            Ok(Err(message)) => return Err(Error::Custom(message)),
+            Err(_) => return Err(Error::Custom("the STA thread failed to start".into())),
+        }
+        Ok(StaThread {
+            worker: Arc::new(Worker {
+                sender,
+                wake,
+                thread: Some(thread),
+            }),
+        })
+    }
+    /// Create a COM object on the STA thread
+    ///
+    pub fn create(&self, prog_id: &str) -> Result<OleProxy> {
+        self.create_async(prog_id).wait()
+    }
+    pub fn create_async(&self, prog_id: &str) -> ProxyFuture<OleProxy> {
+        let (reply, future) = self.pending(|value| match value {
+            Variant::Object(object) => Ok(object),
+            _ => Err(Error::Custom("the server did not return an object".into())),
+        });
+        self.worker.post(Request::Create {
+            prog_id: prog_id.to_string(),
+            reply,
+        });
+        future
+    }
+    /// Run a closure on the STA thread and wait for its result
+    ///
+    pub fn run<T, F>(&self, task: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let slot = Slot::new();
+        let reply = Reply {
+            slot: Some(slot.clone()),
+        };
+        self.worker
+            .post(Request::Run(Box::new(move || reply.send(Ok(task())))));
+        slot.wait().map_err(Error::Custom)
+    }
+    /// Retry calls that the server rejects while busy, see [`RetryPolicy::install`]
+    ///
+    /// `None` removes the policy again.
+    pub fn set_retry_policy(&self, policy: Option<RetryPolicy>) -> Result<()> {
+        self.run(move || {
+            RETRY_FILTER
+                .with(|filter| -> Result<()> {
+                    // Restore the previous filter before registering the new one.
+                    filter.borrow_mut().take();
+                    *filter.borrow_mut() = policy.map(|policy| policy.install()).transpose()?;
+                    Ok(())
+                })
+                .map_err(|error| format!("{error:#}"))
+        })?
+        .map_err(Error::Custom)
+    }
+    fn pending<T>(
+        &self,
+        finish: fn(ProxyValue) -> Result<T>,
+    ) -> (Reply<Variant<u64>>, ProxyFuture<T>) {
+        let slot = Slot::new();
+        let reply = Reply {
+            slot: Some(slot.clone()),
+        };
+        let future = ProxyFuture {
+            slot,
+            worker: self.worker.clone(),
+            finish,
+        };
+        (reply, future)
+    }
+}
+
+struct ProxyHandle {
+    worker: Arc<Worker>,
+    id: u64,
+}
+
+impl Drop for ProxyHandle {
+    fn drop(&mut self) {
+        self.worker.post(Request::Release(self.id));
+    }
+}
+
+/// A `Send + Sync` handle to an automation object owned by a [`StaThread`]
+///
+/// Cloning the proxy shares the underlying object, which is released on the STA thread once
+/// the last clone is dropped.
+#[derive(Clone)]
+pub struct OleProxy {
+    handle: Arc<ProxyHandle>,
+}
+
+impl std::fmt::Debug for OleProxy {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "OleProxy({})", self.handle.id)
+    }
+}
+
+impl PartialEq for OleProxy {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.handle, &other.handle)
+    }
+}
+
+impl From<OleProxy> for ProxyValue {
+    fn from(value: OleProxy) -> Self {
+        Variant::Object(value)
+    }
+}
+
+impl OleProxy {
+    /// The STA thread that owns this object
+    ///
+    pub fn thread(&self) -> StaThread {
+        StaThread {
+            worker: self.handle.worker.clone(),
+        }
+    }
+    /// Get a property from the COM object
+    ///
+    pub fn get(&self, name: &str) -> Result<ProxyValue> {
+        self.get_async(name).wait()
+    }
+    pub fn get_async(&self, name: &str) -> ProxyFuture<ProxyValue> {
+        self.get_with_async(name, vec![])
+    }
+    /// Get a parameterized property, such as `Range("A1")`
+    ///
+    pub fn get_with(&self, name: &str, args: Vec<ProxyValue>) -> Result<ProxyValue> {
+        self.get_with_async(name, args).wait()
+    }
+    pub fn get_with_async(&self, name: &str, args: Vec<ProxyValue>) -> ProxyFuture<ProxyValue> {
+        self.post(Ok, |this| {
+            Ok(Call::Get {
+                name: name.to_string(),
+                args: this.arguments(args)?,
+            })
+        })
+    }
+    /// Set a property on the COM object
+    ///
+    pub fn put(&self, name: &str, value: ProxyValue) -> Result<()> {
+        self.put_with(name, vec![], value)
+    }
+    pub fn put_async(&self, name: &str, value: ProxyValue) -> ProxyFuture<()> {
+        self.put_with_async(name, vec![], value)
+    }
+    /// Set a parameterized property
+    ///
+    pub fn put_with(&self, name: &str, args: Vec<ProxyValue>, value: ProxyValue) -> Result<()> {
+        self.put_with_async(name, args, value).wait()
+    }
+    pub fn put_with_async(
+        &self,
+        name: &str,
+        args: Vec<ProxyValue>,
+        value: ProxyValue,
+    ) -> ProxyFuture<()> {
+        self.post(
+            |_| Ok(()),
+            |this| {
+                Ok(Call::Put {
+                    name: name.to_string(),
+                    args: this.arguments(args)?,
+                    value: this.argument(value)?,
+                })
+            },
+        )
+    }
+    /// Call a method on the COM object
+    ///
+    pub fn call(&self, name: &str, args: Vec<ProxyValue>) -> Result<ProxyValue> {
+        self.call_async(name, args).wait()
+    }
+    pub fn call_async(&self, name: &str, args: Vec<ProxyValue>) -> ProxyFuture<ProxyValue> {
+        self.post(Ok, |this| {
+            Ok(Call::Method {
+                name: name.to_string(),
+                args: this.arguments(args)?,
+            })
+        })
+    }
+    /// Receive the events of the object's default source interface, or of the source interface
+    /// named `interface`
+    ///
+    /// The source stays connected until the [`EventStream`] is closed or dropped.
+    pub fn events(
+        &self,
+        interface: Option<&str>,
+        backpressure: Backpressure,
+    ) -> Result<EventStream> {
+        let queue = Arc::new(EventQueue::new(backpressure));
+        let slot = Slot::new();
+        self.handle.worker.post(Request::Advise {
+            object: self.handle.id,
+            interface: interface.map(str::to_string),
+            queue: queue.clone(),
+            reply: Reply {
+                slot: Some(slot.clone()),
+            },
+        });
+        let sink = slot.wait().map_err(Error::Custom)?;
+        Ok(EventStream {
+            queue,
+            worker: self.handle.worker.clone(),
+            sink: Some(sink),
+        })
+    }
+    /// Evaluate a member path on the STA thread, see [`OleData::eval`]
+    ///
+    pub fn eval(&self, expression: &str) -> Result<ProxyValue> {
+        self.eval_async(expression).wait()
+    }
+    pub fn eval_async(&self, expression: &str) -> ProxyFuture<ProxyValue> {
+        self.post(Ok, |_| Ok(Call::Eval(expression.to_string())))
+    }
+    fn post<T>(
+        &self,
+        finish: fn(ProxyValue) -> Result<T>,
+        call: impl FnOnce(&Self) -> Result<Call>,
+    ) -> ProxyFuture<T> {
+        let (reply, future) = self.thread().pending(finish);
+        match call(self) {
+            Ok(call) => self.handle.worker.post(Request::Invoke {
+                object: self.handle.id,
+                call,
+                reply,
+            }),
+            Err(error) => reply.send(Err(format!("{error:#}"))),
+        }
+        future
+    }
+    fn argument(&self, value: ProxyValue) -> Result<Variant<u64>> {
+        value.try_map_objects(&mut |proxy: OleProxy| {
+            if Arc::ptr_eq(&proxy.handle.worker, &self.handle.worker) {
+                Ok(proxy.handle.id)
+            } else {
+                Err(Error::Custom(
+                    "the object belongs to a different STA thread".into(),
+                ))
+            }
+        })
+    }
+    fn arguments(&self, args: Vec<ProxyValue>) -> Result<Vec<Variant<u64>>> {
+        args.into_iter().map(|arg| self.argument(arg)).collect()
+    }
+}
+
+/// The pending result of a request posted to a [`StaThread`]
+///
+/// Await it from async code, or call [`ProxyFuture::wait`] to block the current thread.
+pub struct ProxyFuture<T> {
+    slot: Arc<Slot<std::result::Result<Variant<u64>, String>>>,
+    worker: Arc<Worker>,
+    finish: fn(ProxyValue) -> Result<T>,
+}
+
+impl<T> ProxyFuture<T> {
+    /// Block until the STA thread has answered
+    ///
+    pub fn wait(self) -> Result<T> {
+        let value = self.slot.wait();
+        self.complete(value)
+    }
+    fn complete(&self, value: std::result::Result<Variant<u64>, String>) -> Result<T> {
+        (self.finish)(self.claim(value.map_err(Error::Custom)?))
+    }
+    fn claim(&self, value: Variant<u64>) -> ProxyValue {
+        value
+            .try_map_objects(&mut |id| Ok::<_, ()>(claim(&self.worker, id)))
+            .unwrap()
+    }
+}
+
+/// Wrap an object id in a proxy, which takes over releasing it
+fn claim(worker: &Arc<Worker>, id: u64) -> OleProxy {
+    OleProxy {
+        handle: Arc::new(ProxyHandle {
+            worker: worker.clone(),
+            id,
+        }),
+    }
+}
+
+impl<T> Future for ProxyFuture<T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<T>> {
+        self.slot.poll(cx).map(|value| self.complete(value))
+    }
+}
+
+impl<T> Drop for ProxyFuture<T> {
+    fn drop(&mut self) {
+        // Release any objects in an answer nobody collected.
+        if let Some(Ok(value)) = self.slot.take() {
+            drop(self.claim(value));
+        }
+    }
+}
+
+/// What an [`EventStream`] does when events arrive faster than they are consumed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Queue up to this many events and drop new ones while the queue is full
+    Bounded(usize),
+    /// Queue up to this many events, discarding the oldest to make room
+    DropOldest(usize),
+    /// Queue up to this many events, then hold the STA thread inside the event until the
+    /// consumer makes room
+    ///
+    /// The STA thread serves no other requests meanwhile, so the consumer must not wait for
+    /// a proxy of the same thread while it lags behind.
+    Block(usize),
+}
+
+impl Default for Backpressure {
+    fn default() -> Self {
+        Backpressure::Bounded(256)
+    }
+}
+
+impl Backpressure {
+    fn capacity(self) -> usize {
+        match self {
+            Backpressure::Bounded(capacity)
+            | Backpressure::DropOldest(capacity)
+            | Backpressure::Block(capacity) => capacity.max(1),
+        }
+    }
+}
+
+struct QueueState {
+    events: VecDeque<Event<u64>>,
+    dropped: u64,
+    /// The source is disconnected, no more events will arrive
+    finished: bool,
+    /// The stream is gone, nobody will take the queued events
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+/// Events on their way from the sink on the STA thread to an [`EventStream`]
+struct EventQueue {
+    state: Mutex<QueueState>,
+    changed: Condvar,
+    backpressure: Backpressure,
+}
+
+impl EventQueue {
+    fn new(backpressure: Backpressure) -> EventQueue {
+        EventQueue {
+            state: Mutex::new(QueueState {
+                events: VecDeque::new(),
+                dropped: 0,
+                finished: false,
+                closed: false,
+                waker: None,
+            }),
+            changed: Condvar::new(),
+            backpressure,
+        }
+    }
+    /// Queue an event, returning whichever event did not fit so its objects can be released
+    fn push(&self, event: Event<u64>) -> Option<Event<u64>> {
+        let capacity = self.backpressure.capacity();
+        let mut state = self.state.lock().unwrap();
+        if let Backpressure::Block(_) = self.backpressure {
+            while state.events.len() >= capacity && !state.closed {
+                state = self.changed.wait(state).unwrap();
+            }
+        }
+        if state.closed {
+            return Some(event);
+        }
+        let mut rejected = None;
+        if state.events.len() >= capacity {
+            state.dropped += 1;
+            match self.backpressure {
+                Backpressure::DropOldest(_) => rejected = state.events.pop_front(),
+                _ => return Some(event),
+            }
+        }
+        state.events.push_back(event);
+        self.notify(&mut state);
+        rejected
+    }
+    fn finish(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.finished = true;
+        self.notify(&mut state);
+    }
+    fn notify(&self, state: &mut QueueState) {
+        self.changed.notify_all();
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// The events of an object owned by a [`StaThread`], see [`OleProxy::events`]
+///
+/// Events are copies: `ByRef` arguments cannot be assigned from here. The stream ends once the
+/// source has been disconnected, and dropping it disconnects the source.
+pub struct EventStream {
+    queue: Arc<EventQueue>,
+    worker: Arc<Worker>,
+    sink: Option<u64>,
+}
+
+impl EventStream {
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<ProxyEvent>> {
+        let mut state = self.queue.state.lock().unwrap();
+        if let Some(event) = state.events.pop_front() {
+            self.queue.changed.notify_all();
+            return Poll::Ready(Some(self.claim(event)));
+        }
+        if state.finished {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+    /// Wait for the next event, `None` once the source has been disconnected
+    ///
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> impl Future<Output = Option<ProxyEvent>> + '_ {
+        future::poll_fn(|cx| self.poll_next(cx))
+    }
+    /// Block the current thread until the next event
+    ///
+    pub fn recv(&mut self) -> Option<ProxyEvent> {
+        let mut state = self.queue.state.lock().unwrap();
+        loop {
+            if let Some(event) = state.events.pop_front() {
+                self.queue.changed.notify_all();
+                return Some(self.claim(event));
+            }
+            if state.finished {
+                return None;
+            }
+            state = self.queue.changed.wait(state).unwrap();
+        }
+    }
+    /// The next event if one is queued
+    ///
+    pub fn try_recv(&mut self) -> Option<ProxyEvent> {
+        let event = self.queue.state.lock().unwrap().events.pop_front()?;
+        self.queue.changed.notify_all();
+        Some(self.claim(event))
+    }
+    /// Number of events discarded because the queue was full
+    ///
+    pub fn dropped(&self) -> u64 {
+        self.queue.state.lock().unwrap().dropped
+    }
+    /// Disconnect from the source and wait until the STA thread has done so
+    ///
+    pub fn close(mut self) -> Result<()> {
+        let Some(sink) = self.sink.take() else {
+            return Ok(());
+        };
+        // With `Backpressure::Block` the STA thread may be waiting for room in the queue, and
+        // it could not serve the Unadvise until then.
+        {
+            let mut state = self.queue.state.lock().unwrap();
+            state.closed = true;
+            self.queue.notify(&mut state);
+        }
+        let slot = Slot::new();
+        self.worker.post(Request::Unadvise {
+            sink,
+            reply: Reply {
+                slot: Some(slot.clone()),
+            },
+        });
+        slot.wait().map_err(Error::Custom)
+    }
+    fn claim(&self, event: Event<u64>) -> ProxyEvent {
+        event
+            .try_map_objects(&mut |id| Ok::<_, ()>(claim(&self.worker, id)))
+            .unwrap()
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        let events = {
+            let mut state = self.queue.state.lock().unwrap();
+            state.closed = true;
+            self.queue.changed.notify_all();
+            std::mem::take(&mut state.events)
+        };
+        if let Some(sink) = self.sink.take() {
+            self.worker.post(Request::Unadvise {
+                sink,
+                reply: Reply { slot: None },
+            });
+        }
+        // Release the objects of the events nobody collected.
+        for event in events {
+            drop(self.claim(event));
+        }
+    }
+}
+
+#[cfg(feature = "futures-core")]
+impl futures_core::Stream for EventStream {
+    type Item = ProxyEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<ProxyEvent>> {
+        EventStream::poll_next(self.get_mut(), cx)
+    }
+}
@@ -0,0 +1,1274 @@
+//! Recording automation calls and replaying them without the server.
+//!
+//! A [`Recorder`] captures every call made through [`OleData`] on the current thread: the
+//! object, member, DISPID, flags, arguments and the result or failure. Recordings are text
+//! with one entry per line, so they diff well:
+//!
+//! ```text
+//! # win32ole recording 1
+//! create #1 "Excel.Application"
+//! call #1 "Workbooks" 572 get () -> #2
+//! call #2 "Add" 181 method|get () -> #3
+//! names #3 "Range" "Cell1" = 197 0
+//! call #4 "Value" 6 put (i4:5) named(-3) -> empty
+//! call #4 "Formula" 261 get () -> error 0x80020009 exception(0, 0x800a03ec, "Microsoft Excel", "Bad formula", "", 0)
+//! ```
+//!
+//! A [`Replay`] serves the same calls back through a [`DispatchBackend`], so the `OleData` API
+//! works unchanged, and reports where a script diverges from the recording.
+//! Values written back through `ByRef` arguments are not replayed.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    ffi::OsStr,
+    fmt, fs,
+    path::Path,
+    rc::{Rc, Weak},
+};
+
+use windows::{
+    core::HRESULT,
+    Win32::{
+        Foundation::{
+            DISP_E_EXCEPTION, DISP_E_PARAMNOTFOUND, DISP_E_TYPEMISMATCH, DISP_E_UNKNOWNNAME,
+            E_FAIL, E_UNEXPECTED,
+        },
+        System::{
+            Com::{DISPATCH_FLAGS, DISPPARAMS},
+            Ole::DISPID_UNKNOWN,
+            Variant::VARIANT,
+        },
+    },
+};
+
+use crate::{
+    backend::DispatchBackend,
+    error::{exception_hresult, ComArgumentErrorType, ComException, Error, Result},
+    util::variant::dispparams_args,
+    value::Variant,
+    OleData,
+};
+
+const HEADER: &str = "# win32ole recording 1";
+
+/// Result of a recorded call
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Value(Variant<u64>),
+    Failed {
+        hresult: HRESULT,
+        exception: Option<ExceptionRecord>,
+    },
+}
+
+/// The interesting fields of an `EXCEPINFO`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExceptionRecord {
+    pub code: u16,
+    pub scode: i32,
+    pub source: String,
+    pub description: String,
+    pub help_file: String,
+    pub help_context: u32,
+}
+
+impl ExceptionRecord {
+    fn from_exception(exception: &ComException) -> ExceptionRecord {
+        ExceptionRecord {
+            code: exception.code,
+            scode: if exception.code != 0 {
+                0
+            } else {
+                exception.hresult.0
+            },
+            source: exception.source.clone(),
+            description: exception.description.clone(),
+            help_file: exception.help_file.clone(),
+            help_context: exception.help_context,
+        }
+    }
+    fn to_exception(&self) -> ComException {
+        ComException {
+            help_file: self.help_file.clone(),
+            help_context: self.help_context,
+            code: self.code,
+            ..ComException::new(
+                &self.source,
+                &self.description,
+                exception_hresult(self.code, self.scode),
+            )
+        }
+    }
+}
+
+/// One invocation of a member
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallRecord {
+    /// Recording-local id of the object, as in `#3`
+    pub object: u64,
+    pub member: Option<String>,
+    pub dispid: i32,
+    pub flags: u16,
+    /// Arguments in source order, i.e. the reverse of `DISPPARAMS::rgvarg`
+    pub args: Vec<Variant<u64>>,
+    pub named: Vec<i32>,
+    pub outcome: Outcome,
+}
+
+impl CallRecord {
+    /// Whether two calls match, ignoring the outcome and the member name
+    ///
+    /// `PROPERTYPUT` and `PROPERTYPUTREF` are treated alike because the choice between them
+    /// depends on type information a replay does not have.
+    fn same_call(&self, other: &CallRecord) -> bool {
+        const PUT_ANY: u16 = FLAG_PUT | FLAG_PUTREF;
+        let normalize = |flags: u16| {
+            if flags & PUT_ANY != 0 {
+                flags | PUT_ANY
+            } else {
+                flags
+            }
+        };
+        self.object == other.object
+            && self.dispid == other.dispid
+            && normalize(self.flags) == normalize(other.flags)
+            && self.args == other.args
+            && self.named == other.named
+    }
+}
+
+/// A line of a recording
+#[derive(Clone, Debug, PartialEq)]
+pub enum Entry {
+    /// `OleData::new(prog_id)` returned object `object`
+    Create {
+        object: u64,
+        prog_id: String,
+    },
+    /// A lookup of several names at once, as done for named arguments
+    Names {
+        object: u64,
+        names: Vec<String>,
+        ids: Vec<i32>,
+    },
+    Call(CallRecord),
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Entry::Create { object, prog_id } => {
+                write!(fmt, "create #{object} {}", quote(prog_id))
+            }
+            Entry::Names { object, names, ids } => {
+                write!(fmt, "names #{object}")?;
+                for name in names {
+                    write!(fmt, " {}", quote(name))?;
+                }
+                write!(fmt, " =")?;
+                for id in ids {
+                    write!(fmt, " {id}")?;
+                }
+                Ok(())
+            }
+            Entry::Call(call) => {
+                let member = call.member.as_deref().map_or("-".into(), quote);
+                write!(
+                    fmt,
+                    "call #{} {member} {} {} (",
+                    call.object,
+                    call.dispid,
+                    format_flags(call.flags)
+                )?;
+                write_list(fmt, &call.args)?;
+                write!(fmt, ")")?;
+                if !call.named.is_empty() {
+                    let named: Vec<String> = call.named.iter().map(|id| id.to_string()).collect();
+                    write!(fmt, " named({})", named.join(", "))?;
+                }
+                write!(fmt, " -> ")?;
+                match call.outcome {
+                    Outcome::Value(ref value) => write_value(fmt, value),
+                    Outcome::Failed {
+                        hresult,
+                        ref exception,
+                    } => {
+                        write!(fmt, "error {:#010x}", hresult.0 as u32)?;
+                        if let Some(exception) = exception {
+                            write!(
+                                fmt,
+                                " exception({}, {:#010x}, {}, {}, {}, {})",
+                                exception.code,
+                                exception.scode as u32,
+                                quote(&exception.source),
+                                quote(&exception.description),
+                                quote(&exception.help_file),
+                                exception.help_context
+                            )?;
+                        }
+                        Ok(())
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Write a recording in the text format
+///
+pub fn to_text(entries: &[Entry]) -> String {
+    let mut text = String::from(HEADER);
+    text.push('\n');
+    for entry in entries {
+        text.push_str(&entry.to_string());
+        text.push('\n');
+    }
+    text
+}
+
+/// Parse a recording, returning each entry with its line number
+///
+pub fn parse(text: &str) -> Result<Vec<(usize, Entry)>> {
+    let mut entries = vec![];
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut cursor = Cursor {
+            chars: line.chars().collect(),
+            position: 0,
+        };
+        let entry = cursor.entry().map_err(|message| {
+            Error::Custom(format!(
+                "recording line {}, column {}: {message}",
+                index + 1,
+                cursor.position + 1
+            ))
+        })?;
+        entries.push((index + 1, entry));
+    }
+    Ok(entries)
+}
+
+const FLAG_METHOD: u16 = 0x1;
+const FLAG_GET: u16 = 0x2;
+const FLAG_PUT: u16 = 0x4;
+const FLAG_PUTREF: u16 = 0x8;
+const FLAG_CONSTRUCT: u16 = 0x4000;
+
+const FLAG_NAMES: [(u16, &str); 5] = [
+    (FLAG_METHOD, "method"),
+    (FLAG_GET, "get"),
+    (FLAG_PUT, "put"),
+    (FLAG_PUTREF, "putref"),
+    (FLAG_CONSTRUCT, "construct"),
+];
+
+fn format_flags(flags: u16) -> String {
+    let mut parts = vec![];
+    let mut rest = flags;
+    for (bit, name) in FLAG_NAMES {
+        if flags & bit != 0 {
+            parts.push(name.to_string());
+            rest &= !bit;
+        }
+    }
+    if rest != 0 || parts.is_empty() {
+        parts.push(format!("{rest:#x}"));
+    }
+    parts.join("|")
+}
+
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn write_list(fmt: &mut fmt::Formatter<'_>, values: &[Variant<u64>]) -> fmt::Result {
+    for (index, value) in values.iter().enumerate() {
+        if index > 0 {
+            write!(fmt, ", ")?;
+        }
+        write_value(fmt, value)?;
+    }
+    Ok(())
+}
+
+fn write_value(fmt: &mut fmt::Formatter<'_>, value: &Variant<u64>) -> fmt::Result {
+    match value {
+        Variant::Empty => write!(fmt, "empty"),
+        Variant::Null => write!(fmt, "null"),
+        Variant::Nothing => write!(fmt, "nothing"),
+        Variant::Bool(value) => write!(fmt, "{value}"),
+        Variant::UI1(value) => write!(fmt, "ui1:{value}"),
+        Variant::I2(value) => write!(fmt, "i2:{value}"),
+        Variant::UI2(value) => write!(fmt, "ui2:{value}"),
+        Variant::I4(value) => write!(fmt, "i4:{value}"),
+        Variant::UI4(value) => write!(fmt, "ui4:{value}"),
+        Variant::I8(value) => write!(fmt, "i8:{value}"),
+        Variant::UI8(value) => write!(fmt, "ui8:{value}"),
+        // `{:?}` keeps enough digits to read the exact value back.
+        Variant::R4(value) => write!(fmt, "r4:{value:?}"),
+        Variant::R8(value) => write!(fmt, "r8:{value:?}"),
+        Variant::Currency(value) => write!(fmt, "cy:{value}"),
+        Variant::Date(value) => write!(fmt, "date:{value:?}"),
+        Variant::String(value) => write!(fmt, "{}", quote(value)),
+        Variant::Error(scode) => write!(fmt, "err:{:#010x}", *scode as u32),
+        Variant::Array(items) => {
+            write!(fmt, "[")?;
+            write_list(fmt, items)?;
+            write!(fmt, "]")
+        }
+        Variant::Object(id) => write!(fmt, "#{id}"),
+    }
+}
+
+struct Cursor {
+    chars: Vec<char>,
+    position: usize,
+}
+
+type ParseResult<T> = std::result::Result<T, String>;
+
+impl Cursor {
+    fn skip_space(&mut self) {
+        while self
+            .chars
+            .get(self.position)
+            .is_some_and(|c| c.is_whitespace())
+        {
+            self.position += 1;
+        }
+    }
+    fn peek(&mut self) -> Option<char> {
+        self.skip_space();
+        self.chars.get(self.position).copied()
+    }
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+    fn expect(&mut self, expected: char) -> ParseResult<()> {
+        if self.eat(expected) {
+            Ok(())
+        } else {
+            Err(format!("expected `{expected}`"))
+        }
+    }
+    /// A run of characters up to whitespace or punctuation
+    fn word(&mut self) -> ParseResult<String> {
+        self.skip_space();
+        let start = self.position;
+        while let Some(&c) = self.chars.get(self.position) {
+            if c.is_whitespace() || matches!(c, '(' | ')' | '[' | ']' | ',' | '"' | '#' | '=') {
+                break;
+            }
+            self.position += 1;
+        }
+        if start == self.position {
+            return Err("expected a word".into());
+        }
+        Ok(self.chars[start..self.position].iter().collect())
+    }
+    fn keyword(&mut self, keyword: &str) -> ParseResult<()> {
+        let start = self.position;
+        match self.word() {
+            Ok(word) if word == keyword => Ok(()),
+            _ => {
+                self.position = start;
+                Err(format!("expected `{keyword}`"))
+            }
+        }
+    }
+    fn number<T: std::str::FromStr>(&mut self) -> ParseResult<T> {
+        let word = self.word()?;
+        let parsed = match word.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16)
+                .ok()
+                .and_then(|value| value.to_string().parse().ok()),
+            None => word.parse().ok(),
+        };
+        parsed.ok_or_else(|| format!("invalid number `{word}`"))
+    }
+    fn hex32(&mut self) -> ParseResult<i32> {
+        let word = self.word()?;
+        let hex = word
+            .strip_prefix("0x")
+            .ok_or_else(|| format!("expected a hexadecimal code, found `{word}`"))?;
+        u32::from_str_radix(hex, 16)
+            .map(|value| value as i32)
+            .map_err(|_| format!("invalid code `{word}`"))
+    }
+    fn object(&mut self) -> ParseResult<u64> {
+        self.expect('#')?;
+        self.number()
+    }
+    fn string(&mut self) -> ParseResult<String> {
+        self.expect('"')?;
+        let mut text = String::new();
+        loop {
+            let Some(&c) = self.chars.get(self.position) else {
+                return Err("unterminated string".into());
+            };
+            self.position += 1;
+            match c {
+                '"' => return Ok(text),
+                '\\' => {
+                    let escaped = self.chars.get(self.position).copied();
+                    self.position += 1;
+                    match escaped {
+                        Some('n') => text.push('\n'),
+                        Some('r') => text.push('\r'),
+                        Some('t') => text.push('\t'),
+                        Some('u') => {
+                            self.expect('{')?;
+                            let start = self.position;
+                            while self.chars.get(self.position).is_some_and(|c| *c != '}') {
+                                self.position += 1;
+                            }
+                            let hex: String = self.chars[start..self.position].iter().collect();
+                            self.expect('}')?;
+                            let c = u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| format!("invalid escape `\\u{{{hex}}}`"))?;
+                            text.push(c);
+                        }
+                        Some(c) => text.push(c),
+                        None => return Err("unterminated string".into()),
+                    }
+                }
+                c => text.push(c),
+            }
+        }
+    }
+    fn flags(&mut self) -> ParseResult<u16> {
+        let word = self.word()?;
+        let mut flags = 0;
+        for part in word.split('|') {
+            flags |= match FLAG_NAMES.iter().find(|(_, name)| *name == part) {
+                Some((bit, _)) => *bit,
+                None => part
+                    .strip_prefix("0x")
+                    .and_then(|hex| u16::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| format!("unknown flag `{part}`"))?,
+            };
+        }
+        Ok(flags)
+    }
+    fn value(&mut self) -> ParseResult<Variant<u64>> {
+        match self.peek() {
+            Some('"') => return Ok(Variant::String(self.string()?)),
+            Some('#') => return Ok(Variant::Object(self.object()?)),
+            Some('[') => {
+                self.position += 1;
+                let items = self.list(']')?;
+                return Ok(Variant::Array(items));
+            }
+            _ => {}
+        }
+        let word = self.word()?;
+        let (kind, text) = word.split_once(':').unwrap_or((word.as_str(), ""));
+        let invalid = || format!("invalid value `{word}`");
+        Ok(match kind {
+            "empty" => Variant::Empty,
+            "null" => Variant::Null,
+            "nothing" => Variant::Nothing,
+            "true" => Variant::Bool(true),
+            "false" => Variant::Bool(false),
+            "ui1" => Variant::UI1(text.parse().map_err(|_| invalid())?),
+            "i2" => Variant::I2(text.parse().map_err(|_| invalid())?),
+            "ui2" => Variant::UI2(text.parse().map_err(|_| invalid())?),
+            "i4" => Variant::I4(text.parse().map_err(|_| invalid())?),
+            "ui4" => Variant::UI4(text.parse().map_err(|_| invalid())?),
+            "i8" => Variant::I8(text.parse().map_err(|_| invalid())?),
+            "ui8" => Variant::UI8(text.parse().map_err(|_| invalid())?),
+            "r4" => Variant::R4(text.parse().map_err(|_| invalid())?),
+            "r8" => Variant::R8(text.parse().map_err(|_| invalid())?),
+            "cy" => Variant::Currency(text.parse().map_err(|_| invalid())?),
+            "date" => Variant::Date(text.parse().map_err(|_| invalid())?),
+            "err" => Variant::Error(
+                text.strip_prefix("0x")
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .ok_or_else(invalid)? as i32,
+            ),
+            _ => return Err(invalid()),
+        })
+    }
+    /// Values separated by commas, after the opening bracket
+    fn list(&mut self, close: char) -> ParseResult<Vec<Variant<u64>>> {
+        let mut items = vec![];
+        if self.eat(close) {
+            return Ok(items);
+        }
+        loop {
+            items.push(self.value()?);
+            if self.eat(close) {
+                return Ok(items);
+            }
+            self.expect(',')?;
+        }
+    }
+    fn entry(&mut self) -> ParseResult<Entry> {
+        let entry = match self.word()?.as_str() {
+            "create" => Entry::Create {
+                object: self.object()?,
+                prog_id: self.string()?,
+            },
+            "names" => {
+                let object = self.object()?;
+                let mut names = vec![];
+                while self.peek() == Some('"') {
+                    names.push(self.string()?);
+                }
+                self.expect('=')?;
+                let mut ids = vec![];
+                while self.peek().is_some() {
+                    ids.push(self.number()?);
+                }
+                Entry::Names { object, names, ids }
+            }
+            "call" => self.call()?,
+            other => return Err(format!("unknown entry `{other}`")),
+        };
+        match self.peek() {
+            None => Ok(entry),
+            Some(c) => Err(format!("unexpected `{c}`")),
+        }
+    }
+    fn call(&mut self) -> ParseResult<Entry> {
+        let object = self.object()?;
+        let member = if self.peek() == Some('"') {
+            Some(self.string()?)
+        } else {
+            self.keyword("-")?;
+            None
+        };
+        let dispid = self.number()?;
+        let flags = self.flags()?;
+        self.expect('(')?;
+        let args = self.list(')')?;
+        let mut named = vec![];
+        if self.keyword("named").is_ok() {
+            self.expect('(')?;
+            if !self.eat(')') {
+                loop {
+                    named.push(self.number()?);
+                    if self.eat(')') {
+                        break;
+                    }
+                    self.expect(',')?;
+                }
+            }
+        }
+        self.keyword("->")?;
+        let outcome = if self.keyword("error").is_ok() {
+            let hresult = HRESULT(self.hex32()?);
+            let exception = if self.keyword("exception").is_ok() {
+                self.expect('(')?;
+                let code = self.number()?;
+                self.expect(',')?;
+                let scode = self.hex32()?;
+                self.expect(',')?;
+                let source = self.string()?;
+                self.expect(',')?;
+                let description = self.string()?;
+                self.expect(',')?;
+                let help_file = self.string()?;
+                self.expect(',')?;
+                let help_context = self.number()?;
+                self.expect(')')?;
+                Some(ExceptionRecord {
+                    code,
+                    scode,
+                    source,
+                    description,
+                    help_file,
+                    help_context,
+                })
+            } else {
+                None
+            };
+            Outcome::Failed { hresult, exception }
+        } else {
+            Outcome::Value(self.value()?)
+        };
+        Ok(Entry::Call(CallRecord {
+            object,
+            member,
+            dispid,
+            flags,
+            args,
+            named,
+            outcome,
+        }))
+    }
+}
+
+#[derive(Default)]
+struct Recording {
+    /// Ids by object identity, with weak handles to the backends seen for it. While one of
+    /// them is alive the identity cannot be reused; once all are gone it may belong to a new
+    /// object, which then gets a new id.
+    ids: HashMap<usize, (u64, Vec<Weak<dyn DispatchBackend>>)>,
+    next_id: u64,
+    names: HashMap<(u64, i32), String>,
+    entries: Vec<Entry>,
+}
+
+impl Recording {
+    fn id_of(&mut self, object: &OleData) -> u64 {
+        let next_id = &mut self.next_id;
+        let (id, backends) = self
+            .ids
+            .entry(object.identity())
+            .or_insert_with(|| (0, vec![]));
+        backends.retain(|backend| backend.strong_count() > 0);
+        if backends.is_empty() {
+            *next_id += 1;
+            *id = *next_id;
+        }
+        if !backends
+            .iter()
+            .any(|backend| std::ptr::addr_eq(backend.as_ptr(), Rc::as_ptr(&object.backend)))
+        {
+            backends.push(Rc::downgrade(&object.backend));
+        }
+        *id
+    }
+    fn capture(&mut self, variant: &VARIANT) -> Variant<u64> {
+        match Variant::try_from(variant) {
+            Ok(value) => self.capture_value(&value),
+            Err(_) => Variant::Error(DISP_E_TYPEMISMATCH.0),
+        }
+    }
+    fn capture_value(&mut self, value: &Variant) -> Variant<u64> {
+        value
+            .clone()
+            .try_map_objects(&mut |object: OleData| Ok::<_, ()>(self.id_of(&object)))
+            .unwrap()
+    }
+}
+
+thread_local!(static RECORDING: RefCell<Option<Rc<RefCell<Recording>>>> = const { RefCell::new(None) });
+
+fn active_recording() -> Option<Rc<RefCell<Recording>>> {
+    RECORDING.with(|recording| recording.borrow().clone())
+}
+
+/// Records the calls made through [`OleData`] on the current thread
+///
+/// Recording stops when the recorder is dropped.
+pub struct Recorder {
+    recording: Rc<RefCell<Recording>>,
+    previous: Option<Rc<RefCell<Recording>>>,
+}
+
+impl Recorder {
+    /// Start recording on the current thread
+    ///
+    pub fn start() -> Recorder {
+        let recording = Rc::new(RefCell::new(Recording::default()));
+        let previous = RECORDING.with(|active| active.replace(Some(recording.clone())));
+        Recorder {
+            recording,
+            previous,
+        }
+    }
+    pub fn entries(&self) -> Vec<Entry> {
+        self.recording.borrow().entries.clone()
+    }
+    pub fn to_text(&self) -> String {
+        to_text(&self.recording.borrow().entries)
+    }
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        Ok(fs::write(path, self.to_text())?)
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        RECORDING.with(|active| *active.borrow_mut() = self.previous.take());
+    }
+}
+
+pub(crate) fn record_create(prog_id: &OsStr, object: &OleData) {
+    let Some(recording) = active_recording() else {
+        return;
+    };
+    let mut recording = recording.borrow_mut();
+    let id = recording.id_of(object);
+    recording.entries.push(Entry::Create {
+        object: id,
+        prog_id: prog_id.to_string_lossy().into_owned(),
+    });
+}
+
+pub(crate) fn record_names<S: AsRef<OsStr>>(object: &OleData, names: &[S], ids: &[i32]) {
+    let Some(recording) = active_recording() else {
+        return;
+    };
+    let mut recording = recording.borrow_mut();
+    let id = recording.id_of(object);
+    let names: Vec<String> = names
+        .iter()
+        .map(|name| name.as_ref().to_string_lossy().into_owned())
+        .collect();
+    if let (Some(name), Some(dispid)) = (names.first(), ids.first()) {
+        recording.names.insert((id, *dispid), name.clone());
+    }
+    if names.len() > 1 {
+        let entry = Entry::Names {
+            object: id,
+            names,
+            ids: ids.to_vec(),
+        };
+        if !recording.entries.contains(&entry) {
+            recording.entries.push(entry);
+        }
+    }
+}
+
+/// Arguments in source order, from arguments in `DISPPARAMS::rgvarg` order
+fn source_order<T, U>(args: &[T], capture: impl FnMut(&T) -> U) -> Vec<U> {
+    args.iter().rev().map(capture).collect()
+}
+
+/// A call being recorded; the arguments are captured before the server can modify them
+pub(crate) struct PendingCall {
+    recording: Rc<RefCell<Recording>>,
+    call: CallRecord,
+}
+
+fn pending_call(
+    object: &OleData,
+    dispid: i32,
+    flags: DISPATCH_FLAGS,
+    named: &[i32],
+    capture: impl FnOnce(&mut Recording) -> Vec<Variant<u64>>,
+) -> Option<PendingCall> {
+    let recording = active_recording()?;
+    let call = {
+        let mut state = recording.borrow_mut();
+        let id = state.id_of(object);
+        CallRecord {
+            object: id,
+            member: state.names.get(&(id, dispid)).cloned(),
+            dispid,
+            flags: flags.0,
+            args: capture(&mut state),
+            named: named.to_vec(),
+            outcome: Outcome::Value(Variant::Empty),
+        }
+    };
+    Some(PendingCall { recording, call })
+}
+
+pub(crate) fn begin_call(
+    object: &OleData,
+    dispid: i32,
+    dp: &DISPPARAMS,
+    flags: DISPATCH_FLAGS,
+) -> Option<PendingCall> {
+    let (rgvarg, named) = dispparams_args(dp);
+    pending_call(object, dispid, flags, named, |state| {
+        source_order(rgvarg, |arg| state.capture(arg))
+    })
+}
+
+/// Start recording a call whose arguments are in `DISPPARAMS::rgvarg` order
+///
+pub(crate) fn begin_call_with(
+    object: &OleData,
+    dispid: i32,
+    flags: DISPATCH_FLAGS,
+    args: &[Variant],
+    named: &[i32],
+) -> Option<PendingCall> {
+    pending_call(object, dispid, flags, named, |state| {
+        source_order(args, |arg| state.capture_value(arg))
+    })
+}
+
+impl PendingCall {
+    pub(crate) fn finish(self, result: std::result::Result<&VARIANT, &Error>) {
+        self.finish_with(result, Recording::capture)
+    }
+    pub(crate) fn finish_value(self, result: std::result::Result<&Variant, &Error>) {
+        self.finish_with(result, Recording::capture_value)
+    }
+    fn finish_with<T>(
+        mut self,
+        result: std::result::Result<&T, &Error>,
+        capture: impl FnOnce(&mut Recording, &T) -> Variant<u64>,
+    ) {
+        let mut recording = self.recording.borrow_mut();
+        self.call.outcome = match result {
+            Ok(value) => Outcome::Value(capture(&mut recording, value)),
+            Err(Error::Exception(exception)) => Outcome::Failed {
+                hresult: DISP_E_EXCEPTION,
+                exception: Some(ExceptionRecord::from_exception(exception)),
+            },
+            Err(error) => Outcome::Failed {
+                hresult: error.hresult().unwrap_or(E_FAIL),
+                exception: None,
+            },
+        };
+        recording.entries.push(Entry::Call(self.call));
+    }
+}
+
+/// A point where a replayed script diverged from its recording
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mismatch {
+    /// Line of the expected entry, or `None` past the end of the recording
+    pub line: Option<usize>,
+    pub expected: Option<String>,
+    pub actual: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, &self.expected) {
+            (Some(line), Some(expected)) => write!(
+                fmt,
+                "replay mismatch at line {line}: expected `{expected}`, got `{}`",
+                self.actual
+            ),
+            _ => write!(
+                fmt,
+                "replay mismatch: `{}` is past the end of the recording",
+                self.actual
+            ),
+        }
+    }
+}
+
+struct ReplaySession {
+    entries: Vec<(usize, Entry)>,
+    cursor: Cell<usize>,
+    names: HashMap<(u64, Vec<String>), Vec<i32>>,
+    objects: RefCell<HashMap<u64, OleData>>,
+    ids: RefCell<HashMap<usize, u64>>,
+    mismatches: RefCell<Vec<Mismatch>>,
+}
+
+impl ReplaySession {
+    fn object(self: &Rc<Self>, id: u64) -> OleData {
+        if let Some(object) = self.objects.borrow().get(&id) {
+            return object.clone();
+        }
+        let object = OleData::from_backend(ReplayObject {
+            id,
+            session: Rc::downgrade(self),
+        });
+        self.ids.borrow_mut().insert(object.identity(), id);
+        self.objects.borrow_mut().insert(id, object.clone());
+        object
+    }
+    /// Consume the next entry if `matches` accepts it, otherwise report a mismatch
+    fn next<T>(
+        &self,
+        actual: &Entry,
+        matches: impl FnOnce(&Entry) -> Option<T>,
+    ) -> std::result::Result<T, Mismatch> {
+        let expected = self
+            .entries
+            .iter()
+            .enumerate()
+            .skip(self.cursor.get())
+            .find(|(_, (_, entry))| !matches!(entry, Entry::Names { .. }));
+        let mismatch = match expected {
+            Some((index, (line, entry))) => match matches(entry) {
+                Some(value) => {
+                    self.cursor.set(index + 1);
+                    return Ok(value);
+                }
+                None => Mismatch {
+                    line: Some(*line),
+                    expected: Some(entry.to_string()),
+                    actual: actual.to_string(),
+                },
+            },
+            None => Mismatch {
+                line: None,
+                expected: None,
+                actual: actual.to_string(),
+            },
+        };
+        self.mismatches.borrow_mut().push(mismatch.clone());
+        Err(mismatch)
+    }
+    /// A value with its objects replaced by their replay ids, or 0 for objects from elsewhere
+    fn capture(&self, value: &Variant) -> Variant<u64> {
+        value
+            .clone()
+            .try_map_objects(&mut |object: OleData| {
+                let id = self.ids.borrow().get(&object.identity()).copied();
+                Ok::<_, ()>(id.unwrap_or(0))
+            })
+            .unwrap()
+    }
+}
+
+/// Serves recorded calls through the `OleData` API
+pub struct Replay {
+    session: Rc<ReplaySession>,
+}
+
+impl Replay {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Replay> {
+        Replay::from_text(&fs::read_to_string(path)?)
+    }
+    pub fn from_text(text: &str) -> Result<Replay> {
+        let entries = parse(text)?;
+        let mut names = HashMap::new();
+        for (_, entry) in &entries {
+            match entry {
+                Entry::Names {
+                    object,
+                    names: member_names,
+                    ids,
+                } => {
+                    let key = member_names
+                        .iter()
+                        .map(|name| name.to_lowercase())
+                        .collect();
+                    names.insert((*object, key), ids.clone());
+                }
+                Entry::Call(CallRecord {
+                    object,
+                    member: Some(member),
+                    dispid,
+                    ..
+                }) => {
+                    names.insert((*object, vec![member.to_lowercase()]), vec![*dispid]);
+                }
+                _ => {}
+            }
+        }
+        Ok(Replay {
+            session: Rc::new(ReplaySession {
+                entries,
+                cursor: Cell::new(0),
+                names,
+                objects: RefCell::new(HashMap::new()),
+                ids: RefCell::new(HashMap::new()),
+                mismatches: RefCell::new(vec![]),
+            }),
+        })
+    }
+    /// Serve `OleData::new` on the current thread from this replay until the guard is dropped
+    ///
+    pub fn activate(&self) -> ReplayGuard {
+        let previous = REPLAY.with(|active| active.replace(Some(self.session.clone())));
+        ReplayGuard { previous }
+    }
+    /// Replay the creation of an object with `prog_id`
+    ///
+    pub fn create(&self, prog_id: &str) -> Result<OleData> {
+        let actual = Entry::Create {
+            object: 0,
+            prog_id: prog_id.to_string(),
+        };
+        let object = self
+            .session
+            .next(&actual, |entry| match entry {
+                Entry::Create {
+                    object,
+                    prog_id: recorded,
+                } if recorded.eq_ignore_ascii_case(prog_id) => Some(*object),
+                _ => None,
+            })
+            .map_err(|mismatch| Error::Custom(mismatch.to_string()))?;
+        Ok(self.session.object(object))
+    }
+    pub fn mismatches(&self) -> Vec<Mismatch> {
+        self.session.mismatches.borrow().clone()
+    }
+    /// Number of recorded creations and calls not replayed yet
+    ///
+    pub fn remaining(&self) -> usize {
+        self.session.entries[self.session.cursor.get()..]
+            .iter()
+            .filter(|(_, entry)| !matches!(entry, Entry::Names { .. }))
+            .count()
+    }
+    /// Check that the script made exactly the recorded calls
+    ///
+    pub fn finish(&self) -> Result<()> {
+        if let Some(mismatch) = self.session.mismatches.borrow().first() {
+            return Err(Error::Custom(mismatch.to_string()));
+        }
+        match self.remaining() {
+            0 => Ok(()),
+            remaining => Err(Error::Custom(format!(
+                "replay finished with {remaining} recorded call(s) left"
+            ))),
+        }
+    }
+}
+
+thread_local!(static REPLAY: RefCell<Option<Rc<ReplaySession>>> = const { RefCell::new(None) });
+
+/// Keeps a [`Replay`] active on the current thread
+pub struct ReplayGuard {
+    previous: Option<Rc<ReplaySession>>,
+}
+
+impl Drop for ReplayGuard {
+    fn drop(&mut self) {
+        REPLAY.with(|active| *active.borrow_mut() = self.previous.take());
+    }
+}
+
+/// `OleData::new` served by the active replay, if there is one
+pub(crate) fn replay_create(prog_id: &OsStr) -> Option<Result<OleData>> {
+    let session = REPLAY.with(|active| active.borrow().clone())?;
+    Some(Replay { session }.create(&prog_id.to_string_lossy()))
+}
+
+/// A recorded object, answering calls from the recording
+struct ReplayObject {
+    id: u64,
+    session: Weak<ReplaySession>,
+}
+
+impl ReplayObject {
+    fn session(&self) -> Result<Rc<ReplaySession>> {
+        self.session.upgrade().ok_or(Error::HResult(E_UNEXPECTED))
+    }
+}
+
+impl DispatchBackend for ReplayObject {
+    fn ids_of_names(&self, names: &[String]) -> (Result<()>, Vec<i32>) {
+        let session = match self.session() {
+            Ok(session) => session,
+            Err(error) => return (Err(error), vec![DISPID_UNKNOWN; names.len()]),
+        };
+        let key: Vec<String> = names.iter().map(|name| name.to_lowercase()).collect();
+        match session.names.get(&(self.id, key)) {
+            Some(ids) if ids.len() == names.len() => (Ok(()), ids.clone()),
+            _ => (
+                Err(Error::HResult(DISP_E_UNKNOWNNAME)),
+                vec![DISPID_UNKNOWN; names.len()],
+            ),
+        }
+    }
+
+    fn invoke(
+        &self,
+        dispid: i32,
+        flags: DISPATCH_FLAGS,
+        args: &mut [Variant],
+        named: &[i32],
+    ) -> Result<Variant> {
+        let session = self.session()?;
+        let actual = CallRecord {
+            object: self.id,
+            member: None,
+            dispid,
+            flags: flags.0,
+            args: source_order(args, |arg| session.capture(arg)),
+            named: named.to_vec(),
+            outcome: Outcome::Value(Variant::Empty),
+        };
+        let outcome = session.next(&Entry::Call(actual.clone()), |entry| match entry {
+            Entry::Call(expected) if expected.same_call(&actual) => Some(expected.outcome.clone()),
+            _ => None,
+        });
+        match outcome {
+            Ok(Outcome::Value(value)) => Ok(value
+                .try_map_objects(&mut |id| Ok::<_, ()>(session.object(id)))
+                .unwrap()),
+            Ok(Outcome::Failed {
+                exception: Some(exception),
+                ..
+            }) => Err(exception.to_exception().into()),
+            Ok(Outcome::Failed { hresult, .. }) => Err(match hresult {
+                DISP_E_TYPEMISMATCH | DISP_E_PARAMNOTFOUND => Error::IDispatchArgument {
+                    error_type: if hresult == DISP_E_TYPEMISMATCH {
+                        ComArgumentErrorType::TypeMismatch
+                    } else {
+                        ComArgumentErrorType::ParameterNotFound
+                    },
+                    arg_err: None,
+                    member: None,
+                    argument: None,
+                },
+                hresult => Error::HResult(hresult),
+            }),
+            Err(mismatch) => {
+                Err(
+                    ComException::new("win32ole replay", &mismatch.to_string(), E_UNEXPECTED)
+                        .into(),
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECORDING: &str = r#"# win32ole recording 1
+create #1 "Excel.Application"
+call #1 "Workbooks" 572 method|get () -> #2
+call #2 "Count" 118 method|get () -> i4:3
+call #2 "Item" 170 method|get (i4:1) -> error 0x80020009 exception(1004, 0x00000000, "Microsoft Excel", "No such sheet", "", 0)
+"#;
+
+    /// The value of `result`, without `Debug`ging the error, which needs oleaut32
+    fn value<T>(result: Result<T>) -> T {
+        match result {
+            Ok(value) => value,
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    fn call(object: u64, member: &str, args: Vec<Variant<u64>>, outcome: Outcome) -> Entry {
+        Entry::Call(CallRecord {
+            object,
+            member: Some(member.into()),
+            dispid: 7,
+            flags: FLAG_METHOD | FLAG_GET,
+            args,
+            named: vec![],
+            outcome,
+        })
+    }
+
+    #[test]
+    fn round_trip() {
+        let entries = vec![
+            Entry::Create {
+                object: 1,
+                prog_id: "Scripting.Dictionary".into(),
+            },
+            Entry::Names {
+                object: 1,
+                names: vec!["Add".into(), "Key".into()],
+                ids: vec![1, -1],
+            },
+            call(
+                1,
+                "Add",
+                vec![
+                    Variant::String("tab\t \"quoted\" \\ line\n".into()),
+                    Variant::R8(0.1),
+                    Variant::Array(vec![Variant::Null, Variant::Object(2), Variant::Error(-1)]),
+                ],
+                Outcome::Value(Variant::Empty),
+            ),
+            Entry::Call(CallRecord {
+                object: 2,
+                member: None,
+                dispid: 0,
+                flags: FLAG_PUT,
+                args: vec![Variant::Bool(true)],
+                named: vec![-3],
+                outcome: Outcome::Failed {
+                    hresult: E_FAIL,
+                    exception: None,
+                },
+            }),
+            call(
+                1,
+                "Item",
+                vec![Variant::Currency(12_500)],
+                Outcome::Failed {
+                    hresult: DISP_E_EXCEPTION,
+                    exception: Some(ExceptionRecord {
+                        code: 0,
+                        scode: E_UNEXPECTED.0,
+                        source: "Dictionary".into(),
+                        description: "Key not found".into(),
+                        help_file: "C:\\help.chm".into(),
+                        help_context: 42,
+                    }),
+                },
+            ),
+        ];
+        let text = to_text(&entries);
+        let parsed: Vec<Entry> = value(parse(&text))
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .collect();
+        assert_eq!(parsed, entries);
+        assert_eq!(to_text(&parsed), text);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let lines = value(parse(RECORDING));
+        let numbers: Vec<usize> = lines.iter().map(|(line, _)| *line).collect();
+        assert_eq!(numbers, [2, 3, 4, 5]);
+
+        for (text, expected) in [
+            ("create #1", "recording line 1, column 10"),
+            (
+                "call #1 \"Count\" 1 get (i4:x) -> empty",
+                "invalid value `i4:x`",
+            ),
+            ("\n\nnames #1 \"Open", "recording line 3"),
+            ("explode #1", "recording line 1"),
+        ] {
+            match parse(text) {
+                Err(Error::Custom(message)) => {
+                    assert!(message.contains(expected), "{message}")
+                }
+                Err(error) => panic!("unexpected error {error}"),
+                Ok(entries) => panic!("parsed {entries:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn mismatches() {
+        let replay = value(Replay::from_text(RECORDING));
+        let app = value(replay.create("excel.application"));
+        let Variant::Object(books) = value(app.get_value("Workbooks", vec![])) else {
+            panic!("expected an object");
+        };
+        assert_eq!(value(books.get_value("Count", vec![])), Variant::I4(3));
+
+        let error = books.get_value("Item", vec![2.into()]).unwrap_err();
+        let exception = error.exception().expect("a replay exception");
+        assert_eq!(exception.source, "win32ole replay");
+        assert_eq!(exception.hresult, E_UNEXPECTED);
+        let mismatch = &replay.mismatches()[0];
+        assert_eq!(mismatch.line, Some(5));
+        assert!(mismatch.actual.contains("(i4:2)"), "{mismatch}");
+
+        // The mismatched call is not consumed, so the recorded one still replays.
+        let error = books.get_value("Item", vec![1.into()]).unwrap_err();
+        let exception = error.exception().expect("the recorded exception");
+        assert_eq!(exception.code, 1004);
+        assert_eq!(exception.hresult, HRESULT(0x8004_03ECu32 as i32));
+        assert_eq!(exception.member.as_deref(), Some("Item"));
+        assert_eq!(replay.remaining(), 0);
+
+        assert!(books.get_value("Count", vec![]).is_err());
+        assert_eq!(replay.mismatches()[1].line, None);
+        assert!(replay.finish().is_err());
+    }
+}
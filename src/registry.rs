@@ -0,0 +1,247 @@
+//! Read access to the registry behind COM discovery.
+//!
+//! ProgIDs, type libraries and their files are found by walking `HKEY_CLASSES_ROOT`. The walk
+//! is written against the [`Registry`] trait: [`NativeRegistry`] reads the machine registry,
+//! while [`MemoryRegistry`] is a tree built in code, so lookups can be exercised without
+//! touching the real registry:
+//!
+//! ```ignore
+//! let mut registry = MemoryRegistry::new();
+//! registry.set_value(Hive::ClassesRoot, r"CLSID\{00024500-0000-0000-C000-000000000046}\ProgID", "", "Excel.Application.16");
+//! assert_eq!(win32ole::progids_in(&registry)?, ["Excel.Application.16"]);
+//! ```
+
+use std::collections::BTreeMap;
+
+use windows::Win32::System::Registry::{
+    HKEY, HKEY_CLASSES_ROOT, HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE,
+};
+
+use crate::{
+    error::{Error, Result},
+    util::RegKey,
+};
+
+/// A predefined root key
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Hive {
+    ClassesRoot,
+    CurrentUser,
+    LocalMachine,
+}
+
+impl Hive {
+    fn hkey(self) -> HKEY {
+        match self {
+            Hive::ClassesRoot => HKEY_CLASSES_ROOT,
+            Hive::CurrentUser => HKEY_CURRENT_USER,
+            Hive::LocalMachine => HKEY_LOCAL_MACHINE,
+        }
+    }
+}
+
+/// The registry operations COM discovery needs
+///
+/// Paths are relative to the hive, separated by backslashes, and compared case-insensitively
+/// like in the Windows registry. The empty path is the hive itself and the empty value name
+/// is the default value.
+pub trait Registry {
+    fn key_exists(&self, hive: Hive, path: &str) -> bool;
+    /// Names of the subkeys of `path`
+    ///
+    fn subkeys(&self, hive: Hive, path: &str) -> Result<Vec<String>>;
+    /// The value `name` of the key `path` as a string
+    ///
+    fn value(&self, hive: Hive, path: &str, name: &str) -> Result<String>;
+}
+
+/// A key of a [`Registry`], for walking the tree one level at a time
+pub struct RegistryKey<'a> {
+    registry: &'a dyn Registry,
+    hive: Hive,
+    path: String,
+}
+
+impl<'a> RegistryKey<'a> {
+    /// Open the key `path` of `hive`
+    ///
+    pub fn open(registry: &'a dyn Registry, hive: Hive, path: &str) -> Result<RegistryKey<'a>> {
+        if !registry.key_exists(hive, path) {
+            return Err(key_not_found(hive, path));
+        }
+        Ok(RegistryKey {
+            registry,
+            hive,
+            path: path.to_string(),
+        })
+    }
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    pub fn open_subkey<P: AsRef<str>>(&self, path: P) -> Result<RegistryKey<'a>> {
+        RegistryKey::open(self.registry, self.hive, &join(&self.path, path.as_ref()))
+    }
+    /// Names of the subkeys. A failure to list them is reported as a single error item.
+    ///
+    pub fn enum_keys(&self) -> impl Iterator<Item = Result<String>> {
+        let (keys, error) = match self.registry.subkeys(self.hive, &self.path) {
+            Ok(keys) => (keys, None),
+            Err(error) => (vec![], Some(error)),
+        };
+        keys.into_iter().map(Ok).chain(error.map(Err))
+    }
+    pub fn get_value<N: AsRef<str>>(&self, name: N) -> Result<String> {
+        self.registry.value(self.hive, &self.path, name.as_ref())
+    }
+}
+
+fn key_not_found(hive: Hive, path: &str) -> Error {
+    Error::NotFound(format!(r"registry key `{hive:?}\{path}`"))
+}
+
+fn join(path: &str, subkey: &str) -> String {
+    match (path.is_empty(), subkey.is_empty()) {
+        (_, true) => path.to_string(),
+        (true, false) => subkey.to_string(),
+        (false, false) => format!(r"{path}\{subkey}"),
+    }
+}
+
+/// The registry of the machine
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NativeRegistry;
+
+impl Registry for NativeRegistry {
+    fn key_exists(&self, hive: Hive, path: &str) -> bool {
+        RegKey::predef(hive.hkey()).open_subkey(path).is_ok()
+    }
+    fn subkeys(&self, hive: Hive, path: &str) -> Result<Vec<String>> {
+        RegKey::predef(hive.hkey())
+            .open_subkey(path)?
+            .enum_keys()
+            .collect()
+    }
+    fn value(&self, hive: Hive, path: &str, name: &str) -> Result<String> {
+        RegKey::predef(hive.hkey())
+            .open_subkey(path)?
+            .get_value(name)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct MemoryKey {
+    /// Subkeys by lowercased name, with the name as it was created
+    subkeys: BTreeMap<String, (String, MemoryKey)>,
+    /// Values by lowercased name
+    values: BTreeMap<String, String>,
+}
+
+/// A registry tree held in memory, populated with [`MemoryRegistry::set_value`]
+#[derive(Clone, Debug, Default)]
+pub struct MemoryRegistry {
+    hives: BTreeMap<Hive, MemoryKey>,
+}
+
+impl MemoryRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Create the key `path` and any missing parent keys
+    ///
+    pub fn create_key(&mut self, hive: Hive, path: &str) {
+        self.key_mut(hive, path);
+    }
+    /// Set the string value `name` of `path`, creating the key if needed
+    ///
+    pub fn set_value(&mut self, hive: Hive, path: &str, name: &str, value: &str) {
+        self.key_mut(hive, path)
+            .values
+            .insert(name.to_lowercase(), value.to_string());
+    }
+    /// Remove the key `path` with all its subkeys, if it exists
+    ///
+    pub fn delete_key(&mut self, hive: Hive, path: &str) {
+        let (parent, name) = path.rsplit_once('\\').unwrap_or(("", path));
+        if let Some(parent) = self.existing_key_mut(hive, parent) {
+            parent.subkeys.remove(&name.to_lowercase());
+        }
+    }
+    fn key(&self, hive: Hive, path: &str) -> Option<&MemoryKey> {
+        let mut key = self.hives.get(&hive)?;
+        for name in components(path) {
+            key = &key.subkeys.get(&name.to_lowercase())?.1;
+        }
+        Some(key)
+    }
+    fn existing_key_mut(&mut self, hive: Hive, path: &str) -> Option<&mut MemoryKey> {
+        let mut key = self.hives.get_mut(&hive)?;
+        for name in components(path) {
+            key = &mut key.subkeys.get_mut(&name.to_lowercase())?.1;
+        }
+        Some(key)
+    }
+    fn key_mut(&mut self, hive: Hive, path: &str) -> &mut MemoryKey {
+        let mut key = self.hives.entry(hive).or_default();
+        for name in components(path) {
+            key = &mut key
+                .subkeys
+                .entry(name.to_lowercase())
+                .or_insert_with(|| (name.to_string(), MemoryKey::default()))
+                .1;
+        }
+        key
+    }
+}
+
+fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('\\').filter(|name| !name.is_empty())
+}
+
+impl Registry for MemoryRegistry {
+    fn key_exists(&self, hive: Hive, path: &str) -> bool {
+        self.key(hive, path).is_some()
+    }
+    fn subkeys(&self, hive: Hive, path: &str) -> Result<Vec<String>> {
+        let key = self
+            .key(hive, path)
+            .ok_or_else(|| key_not_found(hive, path))?;
+        Ok(key.subkeys.values().map(|(name, _)| name.clone()).collect())
+    }
+    fn value(&self, hive: Hive, path: &str, name: &str) -> Result<String> {
+        let key = self
+            .key(hive, path)
+            .ok_or_else(|| key_not_found(hive, path))?;
+        key.values
+            .get(&name.to_lowercase())
+            .cloned()
+            .ok_or_else(|| {
+                Error::NotFound(format!(r"registry value `{name}` of `{hive:?}\{path}`"))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delete_key() {
+        let mut registry = MemoryRegistry::new();
+        registry.set_value(
+            Hive::ClassesRoot,
+            r"TypeLib\{GUID}\1.0\0\win32",
+            "",
+            "a.tlb",
+        );
+        registry.delete_key(Hive::ClassesRoot, r"typelib\{guid}\1.0\0");
+        assert!(registry.key_exists(Hive::ClassesRoot, r"TypeLib\{GUID}\1.0"));
+        assert!(!registry.key_exists(Hive::ClassesRoot, r"TypeLib\{GUID}\1.0\0"));
+
+        // Deleting below a missing key leaves the tree alone.
+        registry.delete_key(Hive::ClassesRoot, r"CLSID\{GUID}\InprocServer32");
+        registry.delete_key(Hive::CurrentUser, r"Software\Classes");
+        assert!(!registry.key_exists(Hive::ClassesRoot, "CLSID"));
+        assert!(!registry.key_exists(Hive::CurrentUser, "Software"));
+        assert!(!registry.key_exists(Hive::CurrentUser, ""));
+    }
+}
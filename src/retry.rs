@@ -0,0 +1,509 @@
+//! Retrying calls that a busy out-of-process server rejects.
+//!
+//! Servers such as Excel answer `RPC_E_CALL_REJECTED` or `RPC_E_SERVERCALL_RETRYLATER` while a
+//! dialog is open. On an STA thread COM consults the registered `IMessageFilter`, which asks a
+//! [`RetryPolicy`] whether and when to retry. The policy itself is plain Rust: it only sees the
+//! attempt number, the elapsed time and the `HRESULT`, so it can be driven by scripted sequences.
+
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use windows::{
+    core::{implement, HRESULT},
+    Win32::{
+        Foundation::{RPC_E_CALL_REJECTED, RPC_E_SERVERCALL_RETRYLATER},
+        Media::{
+            Audio::{CoRegisterMessageFilter, IMessageFilter, IMessageFilter_Impl},
+            HTASK,
+        },
+        System::Com::{INTERFACEINFO, PENDINGMSG_WAITDEFPROCESS, SERVERCALL_ISHANDLED},
+    },
+};
+
+use crate::error::{Error, Result};
+
+/// Details of a rejected call, passed to the [`RetryPolicy::on_retry`] hook
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryAttempt {
+    /// Number of the attempt that was rejected, starting at 1
+    pub attempt: u32,
+    /// Time since the first attempt
+    pub elapsed: Duration,
+    pub hresult: HRESULT,
+    /// Delay the policy proposes before the next attempt
+    pub delay: Duration,
+}
+
+/// Why the policy stopped retrying
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GiveUpReason {
+    /// The `HRESULT` does not indicate a busy server
+    NotRetryable,
+    AttemptsExhausted,
+    DeadlineExceeded,
+    /// The `on_retry` hook declined to retry
+    Cancelled,
+}
+
+/// The policy's answer to a rejected call
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RetryDecision {
+    RetryAfter(Duration),
+    GiveUp(GiveUpReason),
+}
+
+type RetryHook = Arc<dyn Fn(&RetryAttempt) -> bool + Send + Sync>;
+
+/// Attempt limits, exponential backoff and an overall deadline for rejected calls
+///
+/// Defaults to at most 10 attempts within 30 seconds, waiting 100ms after the first rejection
+/// and doubling the delay up to 2 seconds.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_attempts: Option<u32>,
+    initial_delay: Duration,
+    max_delay: Duration,
+    multiplier: f64,
+    deadline: Option<Duration>,
+    on_retry: Option<RetryHook>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: Some(10),
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(2),
+            multiplier: 2.0,
+            deadline: Some(Duration::from_secs(30)),
+            on_retry: None,
+        }
+    }
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("initial_delay", &self.initial_delay)
+            .field("max_delay", &self.max_delay)
+            .field("multiplier", &self.multiplier)
+            .field("deadline", &self.deadline)
+            .field("on_retry", &self.on_retry.is_some())
+            .finish()
+    }
+}
+
+impl RetryPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// A policy that never retries
+    ///
+    pub fn never() -> Self {
+        RetryPolicy {
+            max_attempts: Some(1),
+            ..Self::default()
+        }
+    }
+    /// Total number of attempts, including the first. `None` retries until the deadline.
+    ///
+    pub fn max_attempts(mut self, max_attempts: Option<u32>) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+    pub fn initial_delay(mut self, delay: Duration) -> Self {
+        self.initial_delay = delay;
+        self
+    }
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+    /// Factor applied to the delay after each rejection
+    ///
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+    /// Give up once this much time has passed since the first attempt
+    ///
+    pub fn deadline(mut self, deadline: Option<Duration>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+    /// Called before every retry. Returning `false` cancels the call.
+    ///
+    pub fn on_retry(
+        mut self,
+        hook: impl Fn(&RetryAttempt) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.on_retry = Some(Arc::new(hook));
+        self
+    }
+    /// Delay before the attempt following rejected attempt number `attempt`
+    ///
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        let factor = self
+            .multiplier
+            .powi(attempt.saturating_sub(1).min(64) as i32);
+        self.initial_delay
+            .mul_f64(factor.min(u32::MAX as f64))
+            .min(self.max_delay)
+    }
+    /// Decide what to do after attempt number `attempt` was rejected with `hresult`
+    ///
+    pub fn decide(&self, attempt: u32, elapsed: Duration, hresult: HRESULT) -> RetryDecision {
+        if !is_retryable(hresult) {
+            return RetryDecision::GiveUp(GiveUpReason::NotRetryable);
+        }
+        if self.max_attempts.is_some_and(|max| attempt >= max) {
+            return RetryDecision::GiveUp(GiveUpReason::AttemptsExhausted);
+        }
+        let delay = self.delay_for(attempt);
+        if let Some(deadline) = self.deadline {
+            if elapsed + delay > deadline {
+                return RetryDecision::GiveUp(GiveUpReason::DeadlineExceeded);
+            }
+        }
+        if let Some(ref hook) = self.on_retry {
+            let details = RetryAttempt {
+                attempt,
+                elapsed,
+                hresult,
+                delay,
+            };
+            if !hook(&details) {
+                return RetryDecision::GiveUp(GiveUpReason::Cancelled);
+            }
+        }
+        RetryDecision::RetryAfter(delay)
+    }
+    /// Run `call`, retrying in Rust while it fails with a busy-server `HRESULT`
+    ///
+    /// This works on any thread. On STA threads [`RetryPolicy::install`] lets COM retry
+    /// inside the call instead.
+    pub fn run<T>(&self, mut call: impl FnMut() -> Result<T>) -> Result<T> {
+        let started = Instant::now();
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let error = match call() {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            let Some(hresult) = rejected_hresult(&error) else {
+                return Err(error);
+            };
+            let elapsed = started.elapsed();
+            match self.decide(attempt, elapsed, hresult) {
+                RetryDecision::RetryAfter(delay) => thread::sleep(delay),
+                RetryDecision::GiveUp(reason) => {
+                    return Err(timeout_error(reason, hresult, attempt, elapsed).unwrap_or(error))
+                }
+            }
+        }
+    }
+    /// Register an `IMessageFilter` applying this policy on the current STA thread
+    ///
+    /// The previous filter is restored when the guard is dropped. Calls that are still
+    /// rejected when the policy gives up fail with [`Error::Timeout`].
+    pub fn install(&self) -> Result<MessageFilterGuard> {
+        let filter: IMessageFilter = RetryFilter {
+            policy: self.clone(),
+            attempt: Cell::new(0),
+            last_tick: Cell::new(0),
+        }
+        .into();
+        let mut previous = None;
+        unsafe { CoRegisterMessageFilter(&filter, Some(&mut previous))? };
+        Ok(MessageFilterGuard {
+            previous,
+            _filter: filter,
+        })
+    }
+}
+
+/// Whether `hresult` means the server is busy and the call may succeed later
+///
+pub fn is_retryable(hresult: HRESULT) -> bool {
+    hresult == RPC_E_CALL_REJECTED || hresult == RPC_E_SERVERCALL_RETRYLATER
+}
+
+fn rejected_hresult(error: &Error) -> Option<HRESULT> {
+    match error {
+        Error::Windows(error) if is_retryable(error.code()) => Some(error.code()),
+        _ => None,
+    }
+}
+
+fn timeout_error(
+    reason: GiveUpReason,
+    hresult: HRESULT,
+    attempts: u32,
+    elapsed: Duration,
+) -> Option<Error> {
+    match reason {
+        GiveUpReason::AttemptsExhausted | GiveUpReason::DeadlineExceeded => Some(Error::Timeout {
+            hresult,
+            attempts,
+            elapsed,
+        }),
+        GiveUpReason::NotRetryable | GiveUpReason::Cancelled => None,
+    }
+}
+
+thread_local!(static GAVE_UP: RefCell<Option<(GiveUpReason, u32, Duration)>> = const { RefCell::new(None) });
+
+/// Turn a rejected call into [`Error::Timeout`] if this thread's message filter gave up on it
+///
+pub(crate) fn take_timeout(error: &windows::core::Error) -> Option<Error> {
+    let (reason, attempts, elapsed) = GAVE_UP.with(|gave_up| gave_up.borrow_mut().take())?;
+    if !is_retryable(error.code()) {
+        return None;
+    }
+    timeout_error(reason, error.code(), attempts, elapsed)
+}
+
+/// Keeps a retry message filter registered on the current thread
+pub struct MessageFilterGuard {
+    previous: Option<IMessageFilter>,
+    _filter: IMessageFilter,
+}
+
+impl Drop for MessageFilterGuard {
+    fn drop(&mut self) {
+        let _ = unsafe { CoRegisterMessageFilter(self.previous.as_ref(), None) };
+    }
+}
+
+const SERVERCALL_REJECTED: u32 = 1;
+const SERVERCALL_RETRYLATER: u32 = 2;
+/// Returned from `RetryRejectedCall` to cancel the call
+const CANCEL_CALL: u32 = u32::MAX;
+
+#[implement(IMessageFilter)]
+struct RetryFilter {
+    policy: RetryPolicy,
+    attempt: Cell<u32>,
+    last_tick: Cell<u32>,
+}
+
+impl IMessageFilter_Impl for RetryFilter {
+    fn HandleInComingCall(
+        &self,
+        _dwcalltype: u32,
+        _htaskcaller: HTASK,
+        _dwtickcount: u32,
+        _lpinterfaceinfo: *const INTERFACEINFO,
+    ) -> u32 {
+        SERVERCALL_ISHANDLED.0 as u32
+    }
+
+    fn RetryRejectedCall(&self, _htaskcallee: HTASK, dwtickcount: u32, dwrejecttype: u32) -> u32 {
+        // COM passes the time since the call started, so a smaller value means a new call.
+        if dwtickcount < self.last_tick.get() {
+            self.attempt.set(0);
+        }
+        self.last_tick.set(dwtickcount);
+        self.attempt.set(self.attempt.get() + 1);
+        let hresult = match dwrejecttype {
+            SERVERCALL_RETRYLATER => RPC_E_SERVERCALL_RETRYLATER,
+            SERVERCALL_REJECTED => RPC_E_CALL_REJECTED,
+            _ => return CANCEL_CALL,
+        };
+        let attempt = self.attempt.get();
+        let elapsed = Duration::from_millis(dwtickcount as u64);
+        match self.policy.decide(attempt, elapsed, hresult) {
+            RetryDecision::RetryAfter(delay) => {
+                delay.as_millis().min(CANCEL_CALL as u128 - 1) as u32
+            }
+            RetryDecision::GiveUp(reason) => {
+                GAVE_UP.with(|gave_up| *gave_up.borrow_mut() = Some((reason, attempt, elapsed)));
+                self.attempt.set(0);
+                self.last_tick.set(0);
+                CANCEL_CALL
+            }
+        }
+    }
+
+    fn MessagePending(&self, _htaskcallee: HTASK, _dwtickcount: u32, _dwpendingtype: u32) -> u32 {
+        PENDINGMSG_WAITDEFPROCESS.0 as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use windows::Win32::Foundation::E_FAIL;
+
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    /// The decisions for a call rejected at each `(elapsed, hresult)` in turn, up to the first
+    /// one that gives up
+    fn decisions(policy: &RetryPolicy, rejections: &[(u64, HRESULT)]) -> Vec<RetryDecision> {
+        let mut decisions = vec![];
+        for (attempt, (elapsed, hresult)) in (1..).zip(rejections) {
+            let decision = policy.decide(attempt, ms(*elapsed), *hresult);
+            decisions.push(decision);
+            if let RetryDecision::GiveUp(_) = decision {
+                break;
+            }
+        }
+        decisions
+    }
+
+    #[test]
+    fn backoff() {
+        let policy = RetryPolicy::new();
+        let delays: Vec<Duration> = (1..=7).map(|attempt| policy.delay_for(attempt)).collect();
+        assert_eq!(
+            delays,
+            [
+                ms(100),
+                ms(200),
+                ms(400),
+                ms(800),
+                ms(1600),
+                ms(2000),
+                ms(2000)
+            ]
+        );
+        let policy = RetryPolicy::new().multiplier(0.5);
+        assert_eq!(policy.delay_for(3), ms(100));
+    }
+
+    #[test]
+    fn attempts_exhausted() {
+        let policy = RetryPolicy::new().max_attempts(Some(3));
+        let rejections = [(0, RPC_E_SERVERCALL_RETRYLATER); 5];
+        assert_eq!(
+            decisions(&policy, &rejections),
+            [
+                RetryDecision::RetryAfter(ms(100)),
+                RetryDecision::RetryAfter(ms(200)),
+                RetryDecision::GiveUp(GiveUpReason::AttemptsExhausted),
+            ]
+        );
+        assert_eq!(
+            decisions(&RetryPolicy::never(), &rejections),
+            [RetryDecision::GiveUp(GiveUpReason::AttemptsExhausted)]
+        );
+    }
+
+    #[test]
+    fn not_retryable() {
+        let rejections = [(0, RPC_E_CALL_REJECTED), (100, E_FAIL)];
+        assert_eq!(
+            decisions(&RetryPolicy::new(), &rejections),
+            [
+                RetryDecision::RetryAfter(ms(100)),
+                RetryDecision::GiveUp(GiveUpReason::NotRetryable),
+            ]
+        );
+    }
+
+    #[test]
+    fn deadline() {
+        let policy = RetryPolicy::new()
+            .initial_delay(ms(300))
+            .deadline(Some(ms(1000)));
+        let rejections = [
+            (0, RPC_E_CALL_REJECTED),
+            (300, RPC_E_CALL_REJECTED),
+            (900, RPC_E_CALL_REJECTED),
+        ];
+        assert_eq!(
+            decisions(&policy, &rejections),
+            [
+                RetryDecision::RetryAfter(ms(300)),
+                RetryDecision::RetryAfter(ms(600)),
+                RetryDecision::GiveUp(GiveUpReason::DeadlineExceeded),
+            ]
+        );
+    }
+
+    #[test]
+    fn unlimited() {
+        let policy = RetryPolicy::new().max_attempts(None).deadline(None);
+        let rejections = [(u64::MAX / 2, RPC_E_SERVERCALL_RETRYLATER); 100];
+        let decisions = decisions(&policy, &rejections);
+        assert_eq!(decisions.len(), 100);
+        assert_eq!(decisions[99], RetryDecision::RetryAfter(ms(2000)));
+    }
+
+    #[test]
+    fn hook() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let log = seen.clone();
+        let policy = RetryPolicy::new().on_retry(move |attempt| {
+            log.lock().unwrap().push(*attempt);
+            attempt.attempt < 2
+        });
+        let rejections = [
+            (0, RPC_E_CALL_REJECTED),
+            (150, RPC_E_SERVERCALL_RETRYLATER),
+            (400, RPC_E_CALL_REJECTED),
+        ];
+        assert_eq!(
+            decisions(&policy, &rejections),
+            [
+                RetryDecision::RetryAfter(ms(100)),
+                RetryDecision::GiveUp(GiveUpReason::Cancelled),
+            ]
+        );
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                RetryAttempt {
+                    attempt: 1,
+                    elapsed: ms(0),
+                    hresult: RPC_E_CALL_REJECTED,
+                    delay: ms(100),
+                },
+                RetryAttempt {
+                    attempt: 2,
+                    elapsed: ms(150),
+                    hresult: RPC_E_SERVERCALL_RETRYLATER,
+                    delay: ms(200),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn message_filter() {
+        let filter = RetryFilter {
+            policy: RetryPolicy::new().max_attempts(Some(3)),
+            attempt: Cell::new(0),
+            last_tick: Cell::new(0),
+        };
+        let retry = |tick| filter.RetryRejectedCall(HTASK::default(), tick, SERVERCALL_RETRYLATER);
+        assert_eq!(retry(0), 100);
+        assert_eq!(retry(100), 200);
+        // A smaller tick count starts a new call.
+        assert_eq!(retry(50), 100);
+        assert_eq!(retry(150), 200);
+        assert_eq!(retry(350), CANCEL_CALL);
+        assert_eq!(
+            GAVE_UP.with(|gave_up| gave_up.borrow_mut().take()),
+            Some((GiveUpReason::AttemptsExhausted, 3, ms(350)))
+        );
+        // Giving up resets the count for the next call.
+        assert_eq!(retry(0), 100);
+        assert_eq!(
+            filter.RetryRejectedCall(HTASK::default(), 10, 0),
+            CANCEL_CALL
+        );
+    }
+}
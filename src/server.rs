@@ -0,0 +1,957 @@
+//! `IDispatch` servers for Rust types.
+//!
+//! `#[derive(Dispatch)]` exposes annotated struct fields as properties and
+//! `#[dispatch_impl]` exposes the public methods of an impl block:
+//!
+//! ```ignore
+//! #[derive(Dispatch)]
+//! #[dispatch(methods)]
+//! struct Counter {
+//!     #[dispatch(get, put)]
+//!     step: i32,
+//!     total: i32,
+//! }
+//!
+//! #[dispatch_impl]
+//! impl Counter {
+//!     pub fn add(&mut self, times: Option<i32>) -> i32 {
+//!         self.total += self.step * times.unwrap_or(1);
+//!         self.total
+//!     }
+//!     #[dispatch(get)]
+//!     pub fn total(&self) -> i32 {
+//!         self.total
+//!     }
+//! }
+//!
+//! let counter: IDispatch = Counter { step: 2, total: 0 }.into_dispatch();
+//! ```
+//!
+//! Names are exposed in PascalCase (`Add`, `Total`, `Step`) and matched case-insensitively.
+//! Arguments are coerced with `VariantChangeType`, can be passed by name, and `Option`
+//! parameters may be omitted. Errors returned by methods reach the caller as
+//! `DISP_E_EXCEPTION` with the error message in `EXCEPINFO`, whose `scode` is the error's own
+//! `HRESULT` or `E_FAIL`.
+//!
+//! A closure becomes a function object, as taken by `attachEvent` or `setTimeout`, through
+//! [`Callback`]:
+//!
+//! ```ignore
+//! let handler = Callback::new(|args| {
+//!     println!("clicked with {args:?}");
+//!     Ok(Variant::Empty)
+//! });
+//! button.call("attachEvent", vec!["onclick".into_variant(), handler.into_variant()])?;
+//! ```
+//!
+//! Served objects belong to the thread that created them. Calls from any other thread fail
+//! with `RPC_E_WRONG_THREAD`; other apartments reach them through COM marshaling.
+
+use std::{
+    any::TypeId,
+    cell::{Ref, RefCell, RefMut},
+    collections::HashMap,
+    thread::{self, ThreadId},
+};
+
+use windows::{
+    core::{implement, BSTR, GUID, PCWSTR, PWSTR},
+    Win32::{
+        Foundation::{
+            DISP_E_BADPARAMCOUNT, DISP_E_MEMBERNOTFOUND, DISP_E_PARAMNOTFOUND, DISP_E_TYPEMISMATCH,
+            DISP_E_UNKNOWNNAME, E_UNEXPECTED, RPC_E_WRONG_THREAD,
+        },
+        System::{
+            Com::{
+                IDispatch, IDispatch_Impl, ITypeInfo, CC_STDCALL, DISPATCH_FLAGS, DISPATCH_METHOD,
+                DISPATCH_PROPERTYGET, DISPATCH_PROPERTYPUT, DISPATCH_PROPERTYPUTREF, DISPPARAMS,
+                EXCEPINFO,
+            },
+            Ole::{
+                CreateDispTypeInfo, DISPID_PROPERTYPUT, DISPID_UNKNOWN, DISPID_VALUE,
+                INTERFACEDATA, METHODDATA, PARAMDATA,
+            },
+            Variant::{
+                VariantChangeType, VARENUM, VAR_CHANGE_FLAGS, VT_BOOL, VT_BSTR, VT_DISPATCH,
+                VT_EMPTY, VT_I2, VT_I4, VT_I8, VT_NULL, VT_R4, VT_R8, VT_UI1, VT_UI2, VT_UI4,
+                VT_UI8, VT_VARIANT,
+            },
+        },
+    },
+};
+
+pub use windows::Win32::System::Variant::VARIANT;
+
+use crate::{
+    backend::report_error,
+    error::{ComArgumentErrorType, Error, Result},
+    util::{
+        conv::ToWide,
+        variant::{is_missing_argument, variant_dispatch},
+    },
+    value::{IntoVariant, Variant},
+    OleData,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemberKind {
+    Method,
+    Property,
+}
+
+/// A parameter of an exposed member
+#[derive(Debug)]
+pub struct ParamInfo {
+    pub name: &'static str,
+    pub vt: u16,
+}
+
+/// Description of an exposed member, generated by the dispatch macros
+#[derive(Debug)]
+pub struct MemberInfo {
+    pub name: &'static str,
+    pub dispid: i32,
+    pub kind: MemberKind,
+    /// For properties, whether the property can be read and assigned
+    pub get: bool,
+    pub put: bool,
+    pub params: &'static [ParamInfo],
+    pub vt: u16,
+}
+
+/// How a member is being invoked
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    /// `DISPATCH_METHOD` or `DISPATCH_PROPERTYGET`
+    Get,
+    /// `DISPATCH_PROPERTYPUT` or `DISPATCH_PROPERTYPUTREF`
+    Put,
+}
+
+/// Properties generated by `#[derive(Dispatch)]`
+pub trait DispatchFields {
+    const FIELD_MEMBERS: &'static [MemberInfo];
+    fn get_field(&self, dispid: i32) -> Option<Result<VARIANT>>;
+    fn put_field(&mut self, dispid: i32, value: &VARIANT) -> Option<Result<()>>;
+}
+
+/// Implemented by `#[derive(Dispatch)]` for structs marked `#[dispatch(methods)]`, whose
+/// generated `invoke_member` dispatches to the `#[dispatch_impl]` block
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` has a `#[dispatch_impl]` block but is not marked `#[dispatch(methods)]`",
+    label = "its methods would never be invoked"
+)]
+pub trait ExposesMethods {}
+
+/// Methods generated by `#[dispatch_impl]`
+pub trait DispatchMethods: ExposesMethods + Sized {
+    const METHOD_MEMBERS: &'static [MemberInfo];
+    fn invoke_method(
+        this: &RefCell<Self>,
+        dispid: i32,
+        access: Access,
+        args: &Arguments,
+    ) -> Option<Result<VARIANT>>;
+}
+
+/// A Rust type that can be served through `IDispatch`
+pub trait DispatchObject: Sized + 'static {
+    const TYPE_NAME: &'static str;
+
+    fn members() -> Vec<&'static MemberInfo>;
+
+    /// Invoke a member, or return `None` if there is no member `dispid` for `access`
+    ///
+    fn invoke_member(
+        this: &RefCell<Self>,
+        dispid: i32,
+        access: Access,
+        args: &Arguments,
+    ) -> Option<Result<VARIANT>>;
+
+    fn into_dispatch(self) -> IDispatch {
+        DispatchServer {
+            object: Box::new(RefCell::new(self)),
+            thread: thread::current().id(),
+        }
+        .into()
+    }
+
+    fn into_ole(self) -> OleData {
+        OleData::from_dispatch(self.into_dispatch())
+    }
+}
+
+/// Fail to compile when a field and a method share a DISPID, used by the generated `members`
+///
+/// The derive and `#[dispatch_impl]` each reject duplicates among their own members, but only
+/// this check, evaluated when the type is compiled, sees both.
+pub const fn check_dispids(fields: &[MemberInfo], methods: &[MemberInfo]) {
+    let mut field = 0;
+    while field < fields.len() {
+        let mut method = 0;
+        while method < methods.len() {
+            if fields[field].dispid == methods[method].dispid {
+                panic!("a field and a method of a dispatch type have the same DISPID");
+            }
+            method += 1;
+        }
+        field += 1;
+    }
+}
+
+/// Get or put a field property, used by the generated `invoke_member`
+///
+pub fn invoke_fields<T: DispatchFields>(
+    this: &RefCell<T>,
+    dispid: i32,
+    access: Access,
+    args: &Arguments,
+) -> Option<Result<VARIANT>> {
+    T::FIELD_MEMBERS
+        .iter()
+        .find(|member| member.dispid == dispid)?;
+    match access {
+        Access::Get if args.count() > 0 => Some(Err(DISP_E_BADPARAMCOUNT.into())),
+        Access::Get => match borrow(this) {
+            Ok(object) => object.get_field(dispid),
+            Err(error) => Some(Err(error)),
+        },
+        Access::Put => {
+            let (value, position) = match args.value_variant() {
+                Ok(value) => value,
+                Err(error) => return Some(Err(error)),
+            };
+            match borrow_mut(this) {
+                Ok(mut object) => object.put_field(dispid, value).map(|result| {
+                    result.map(|()| VARIANT::default()).map_err(|_| {
+                        argument_error(ComArgumentErrorType::TypeMismatch, Some(position))
+                    })
+                }),
+                Err(error) => Some(Err(error)),
+            }
+        }
+    }
+}
+
+pub fn borrow<T>(this: &RefCell<T>) -> Result<Ref<'_, T>> {
+    this.try_borrow()
+        .map_err(|_| Error::Custom("the object is being modified by another call".into()))
+}
+
+pub fn borrow_mut<T>(this: &RefCell<T>) -> Result<RefMut<'_, T>> {
+    this.try_borrow_mut()
+        .map_err(|_| Error::Custom("the object is already in use by another call".into()))
+}
+
+/// Convert an error returned by an exposed method
+///
+pub fn method_error<E: Into<Box<dyn std::error::Error>>>(error: E) -> Error {
+    match error.into().downcast::<Error>() {
+        Ok(error) => *error,
+        Err(error) => Error::Custom(error.to_string()),
+    }
+}
+
+/// An argument error for the argument at `arg_err` in `rgvarg`, or for one that was not passed
+fn argument_error(error_type: ComArgumentErrorType, arg_err: Option<usize>) -> Error {
+    Error::IDispatchArgument {
+        error_type,
+        arg_err: arg_err.map(|arg_err| arg_err as u32),
+        member: None,
+        argument: None,
+    }
+}
+
+/// The arguments of an incoming call
+pub struct Arguments {
+    /// Positional arguments in source order
+    positional: Vec<VARIANT>,
+    /// Named arguments by DISPID, with their index into `rgvarg`
+    named: Vec<(i32, usize, VARIANT)>,
+    /// The assigned value of a property put, with its index into `rgvarg`
+    value: Option<(VARIANT, usize)>,
+    count: usize,
+}
+
+impl Arguments {
+    pub fn from_params(params: &DISPPARAMS) -> Arguments {
+        let rgvarg = if params.rgvarg.is_null() {
+            &[][..]
+        } else {
+            unsafe { std::slice::from_raw_parts(params.rgvarg, params.cArgs as usize) }
+        };
+        let dispids = if params.rgdispidNamedArgs.is_null() {
+            &[][..]
+        } else {
+            unsafe {
+                std::slice::from_raw_parts(params.rgdispidNamedArgs, params.cNamedArgs as usize)
+            }
+        };
+        let mut named = vec![];
+        let mut value = None;
+        for (position, (dispid, arg)) in dispids.iter().zip(rgvarg).enumerate() {
+            if *dispid == DISPID_PROPERTYPUT {
+                value = Some((arg.clone(), position));
+            } else {
+                named.push((*dispid, position, arg.clone()));
+            }
+        }
+        Arguments {
+            positional: rgvarg[dispids.len().min(rgvarg.len())..]
+                .iter()
+                .rev()
+                .cloned()
+                .collect(),
+            named,
+            value,
+            count: rgvarg.len(),
+        }
+    }
+    /// Number of arguments, not counting the value of a property put
+    ///
+    pub fn count(&self) -> usize {
+        self.positional.len() + self.named.len()
+    }
+    /// Fail with `DISP_E_BADPARAMCOUNT` if more than `max` arguments were passed
+    ///
+    pub fn expect_at_most(&self, max: usize) -> Result<()> {
+        let beyond = self
+            .named
+            .iter()
+            .any(|(dispid, _, _)| *dispid < 0 || *dispid as usize >= max);
+        if self.positional.len() > max || beyond {
+            return Err(DISP_E_BADPARAMCOUNT.into());
+        }
+        Ok(())
+    }
+    /// The parameter at `index`, passed by position or by name
+    ///
+    pub fn arg<T: FromVariant>(&self, index: usize) -> Result<T> {
+        let found = match self.positional.get(index) {
+            // rgvarg holds the arguments in reverse order.
+            Some(arg) => Some((arg, self.count - 1 - index)),
+            None => self
+                .named
+                .iter()
+                .find(|(dispid, _, _)| *dispid == index as i32)
+                .map(|(_, position, arg)| (arg, *position)),
+        };
+        match found {
+            Some((arg, position)) if is_missing_argument(arg) => T::missing().map_err(|_| {
+                argument_error(ComArgumentErrorType::ParameterNotFound, Some(position))
+            }),
+            Some((arg, position)) => T::from_variant(arg)
+                .map_err(|_| argument_error(ComArgumentErrorType::TypeMismatch, Some(position))),
+            // An argument that was not passed at all has no position to report.
+            None => T::missing()
+                .map_err(|_| argument_error(ComArgumentErrorType::ParameterNotFound, None)),
+        }
+    }
+    /// The value assigned by a property put
+    ///
+    pub fn value<T: FromVariant>(&self) -> Result<T> {
+        let (value, position) = self.value_variant()?;
+        T::from_variant(value)
+            .map_err(|_| argument_error(ComArgumentErrorType::TypeMismatch, Some(position)))
+    }
+    fn value_variant(&self) -> Result<(&VARIANT, usize)> {
+        match self.value {
+            Some((ref value, position)) => Ok((value, position)),
+            None => Err(argument_error(
+                ComArgumentErrorType::ParameterNotFound,
+                None,
+            )),
+        }
+    }
+}
+
+/// Conversion of an incoming argument into a Rust value
+pub trait FromVariant: Sized {
+    fn from_variant(variant: &VARIANT) -> Result<Self>;
+
+    /// The value for an omitted argument; an error unless the parameter is optional
+    ///
+    fn missing() -> Result<Self> {
+        Err(DISP_E_PARAMNOTFOUND.into())
+    }
+}
+
+fn change_type(variant: &VARIANT, vt: VARENUM) -> Result<VARIANT> {
+    let mut converted = VARIANT::default();
+    unsafe { VariantChangeType(&mut converted, variant, VAR_CHANGE_FLAGS(0), vt)? };
+    Ok(converted)
+}
+
+macro_rules! from_variant_scalar {
+    ($($ty:ty => $vt:ident . $field:ident),* $(,)?) => {
+        $(
+            impl FromVariant for $ty {
+                fn from_variant(variant: &VARIANT) -> Result<Self> {
+                    let converted = change_type(variant, $vt)?;
+                    Ok(unsafe { converted.Anonymous.Anonymous.Anonymous.$field })
+                }
+            }
+        )*
+    };
+}
+
+from_variant_scalar! {
+    u8 => VT_UI1.bVal,
+    i16 => VT_I2.iVal,
+    u16 => VT_UI2.uiVal,
+    i32 => VT_I4.lVal,
+    u32 => VT_UI4.ulVal,
+    i64 => VT_I8.llVal,
+    u64 => VT_UI8.ullVal,
+    f32 => VT_R4.fltVal,
+    f64 => VT_R8.dblVal,
+}
+
+impl FromVariant for bool {
+    fn from_variant(variant: &VARIANT) -> Result<Self> {
+        let converted = change_type(variant, VT_BOOL)?;
+        Ok(unsafe { converted.Anonymous.Anonymous.Anonymous.boolVal.0 != 0 })
+    }
+}
+
+impl FromVariant for BSTR {
+    fn from_variant(variant: &VARIANT) -> Result<Self> {
+        let converted = change_type(variant, VT_BSTR)?;
+        Ok(unsafe { (*converted.Anonymous.Anonymous.Anonymous.bstrVal).clone() })
+    }
+}
+
+impl FromVariant for String {
+    fn from_variant(variant: &VARIANT) -> Result<Self> {
+        Ok(BSTR::from_variant(variant)?.to_string())
+    }
+}
+
+impl FromVariant for VARIANT {
+    fn from_variant(variant: &VARIANT) -> Result<Self> {
+        Ok(variant.clone())
+    }
+}
+
+impl FromVariant for Variant {
+    fn from_variant(variant: &VARIANT) -> Result<Self> {
+        Variant::try_from(variant)
+    }
+}
+
+impl FromVariant for IDispatch {
+    fn from_variant(variant: &VARIANT) -> Result<Self> {
+        variant_dispatch(variant).ok_or_else(|| DISP_E_TYPEMISMATCH.into())
+    }
+}
+
+impl FromVariant for OleData {
+    fn from_variant(variant: &VARIANT) -> Result<Self> {
+        Ok(OleData::from_dispatch(IDispatch::from_variant(variant)?))
+    }
+}
+
+impl<T: FromVariant> FromVariant for Option<T> {
+    fn from_variant(variant: &VARIANT) -> Result<Self> {
+        match variant.vt() {
+            VT_EMPTY | VT_NULL => Ok(None),
+            _ => T::from_variant(variant).map(Some),
+        }
+    }
+
+    fn missing() -> Result<Self> {
+        Ok(None)
+    }
+}
+
+/// The `VARTYPE` recorded for a parameter or return type in the generated type information
+pub trait VarType {
+    const VT: VARENUM;
+}
+
+macro_rules! var_type {
+    ($($ty:ty => $vt:ident),* $(,)?) => {
+        $(impl VarType for $ty { const VT: VARENUM = $vt; })*
+    };
+}
+
+var_type! {
+    () => VT_EMPTY,
+    bool => VT_BOOL,
+    u8 => VT_UI1,
+    i16 => VT_I2,
+    u16 => VT_UI2,
+    i32 => VT_I4,
+    u32 => VT_UI4,
+    i64 => VT_I8,
+    u64 => VT_UI8,
+    f32 => VT_R4,
+    f64 => VT_R8,
+    String => VT_BSTR,
+    &str => VT_BSTR,
+    BSTR => VT_BSTR,
+    VARIANT => VT_VARIANT,
+    Variant => VT_VARIANT,
+    IDispatch => VT_DISPATCH,
+    OleData => VT_DISPATCH,
+}
+
+impl<T: VarType> VarType for Option<T> {
+    // An omitted argument arrives as VT_ERROR, which only a VARIANT parameter can hold.
+    const VT: VARENUM = VT_VARIANT;
+}
+
+/// A Rust closure served as a function object
+///
+/// Calling the object's default member, `DISPID_VALUE`, calls the closure with the
+/// positional arguments. Named arguments, such as the `this` object JScript passes, are
+/// ignored.
+pub struct Callback<F> {
+    function: F,
+}
+
+impl<F> Callback<F>
+where
+    F: Fn(&[Variant]) -> Result<Variant> + 'static,
+{
+    pub fn new(function: F) -> Callback<F> {
+        Callback { function }
+    }
+}
+
+static CALLBACK_MEMBERS: [MemberInfo; 1] = [MemberInfo {
+    name: "Invoke",
+    dispid: DISPID_VALUE,
+    kind: MemberKind::Method,
+    get: false,
+    put: false,
+    params: &[],
+    vt: VT_VARIANT.0,
+}];
+
+impl<F> DispatchObject for Callback<F>
+where
+    F: Fn(&[Variant]) -> Result<Variant> + 'static,
+{
+    const TYPE_NAME: &'static str = "Callback";
+
+    fn members() -> Vec<&'static MemberInfo> {
+        CALLBACK_MEMBERS.iter().collect()
+    }
+
+    fn invoke_member(
+        this: &RefCell<Self>,
+        dispid: i32,
+        access: Access,
+        args: &Arguments,
+    ) -> Option<Result<VARIANT>> {
+        if dispid != DISPID_VALUE || access == Access::Put {
+            return None;
+        }
+        let values = match args
+            .positional
+            .iter()
+            .map(Variant::try_from)
+            .collect::<Result<Vec<_>>>()
+        {
+            Ok(values) => values,
+            Err(error) => return Some(Err(error)),
+        };
+        // A shared borrow, so the closure may be called again while it runs.
+        let this = this.borrow();
+        Some((this.function)(&values).map(IntoVariant::into_variant))
+    }
+}
+
+impl<F> IntoVariant for Callback<F>
+where
+    F: Fn(&[Variant]) -> Result<Variant> + 'static,
+{
+    fn into_variant(self) -> VARIANT {
+        self.into_dispatch().into_variant()
+    }
+}
+
+/// A `DispatchObject` with its type erased, so a single COM class serves every type
+trait ErasedObject {
+    fn type_id(&self) -> TypeId;
+    fn type_name(&self) -> &'static str;
+    fn members(&self) -> Vec<&'static MemberInfo>;
+    fn invoke(&self, dispid: i32, access: Access, args: &Arguments) -> Option<Result<VARIANT>>;
+}
+
+impl<T: DispatchObject> ErasedObject for RefCell<T> {
+    fn type_id(&self) -> TypeId {
+        TypeId::of::<T>()
+    }
+    fn type_name(&self) -> &'static str {
+        T::TYPE_NAME
+    }
+    fn members(&self) -> Vec<&'static MemberInfo> {
+        T::members()
+    }
+    fn invoke(&self, dispid: i32, access: Access, args: &Arguments) -> Option<Result<VARIANT>> {
+        T::invoke_member(self, dispid, access, args)
+    }
+}
+
+#[implement(IDispatch)]
+struct DispatchServer {
+    object: Box<dyn ErasedObject>,
+    /// The thread that created the object, the only one allowed to call it
+    thread: ThreadId,
+}
+
+/// Buffers referenced by the `INTERFACEDATA` a type info was created from
+#[allow(dead_code)]
+struct TypeInfoData {
+    names: Vec<Vec<u16>>,
+    params: Vec<Vec<PARAMDATA>>,
+    methods: Vec<METHODDATA>,
+}
+
+thread_local!(static TYPE_INFOS: RefCell<HashMap<TypeId, (ITypeInfo, TypeInfoData)>> = RefCell::new(HashMap::new()));
+
+fn create_type_info(members: &[&'static MemberInfo]) -> Result<(ITypeInfo, TypeInfoData)> {
+    let mut data = TypeInfoData {
+        names: vec![],
+        params: vec![],
+        methods: vec![],
+    };
+    let mut entries = vec![];
+    for member in members {
+        let flags = match member.kind {
+            MemberKind::Method => vec![DISPATCH_METHOD],
+            MemberKind::Property => {
+                let mut flags = vec![];
+                if member.get {
+                    flags.push(DISPATCH_PROPERTYGET);
+                }
+                if member.put {
+                    flags.push(DISPATCH_PROPERTYPUT);
+                }
+                flags
+            }
+        };
+        for flag in flags {
+            let mut params: Vec<PARAMDATA> = vec![];
+            for param in member.params {
+                data.names.push(param.name.to_wide_null());
+                params.push(PARAMDATA {
+                    szName: PWSTR(data.names.last_mut().unwrap().as_mut_ptr()),
+                    vt: VARENUM(param.vt),
+                });
+            }
+            let vt_return = if flag == DISPATCH_PROPERTYPUT {
+                data.names.push("Value".to_wide_null());
+                params.push(PARAMDATA {
+                    szName: PWSTR(data.names.last_mut().unwrap().as_mut_ptr()),
+                    vt: VARENUM(member.vt),
+                });
+                VT_EMPTY
+            } else {
+                VARENUM(member.vt)
+            };
+            data.names.push(member.name.to_wide_null());
+            let name = PWSTR(data.names.last_mut().unwrap().as_mut_ptr());
+            entries.push((name, params, member.dispid, flag, vt_return));
+        }
+    }
+    for (index, (name, params, dispid, flag, vt_return)) in entries.into_iter().enumerate() {
+        data.params.push(params);
+        let params = data.params.last_mut().unwrap();
+        data.methods.push(METHODDATA {
+            szName: name,
+            ppdata: params.as_mut_ptr(),
+            dispid,
+            iMeth: index as u32,
+            cc: CC_STDCALL,
+            cArgs: params.len() as u32,
+            wFlags: flag.0,
+            vtReturn: vt_return,
+        });
+    }
+    let mut interface = INTERFACEDATA {
+        pmethdata: data.methods.as_mut_ptr(),
+        cMembers: data.methods.len() as u32,
+    };
+    let mut typeinfo = None;
+    unsafe {
+        CreateDispTypeInfo(
+            &mut interface,
+            0x0800, /*LOCALE_SYSTEM_DEFAULT*/
+            &mut typeinfo,
+        )?
+    };
+    let typeinfo = typeinfo.ok_or_else(|| Error::from(windows::core::Error::from(E_UNEXPECTED)))?;
+    Ok((typeinfo, data))
+}
+
+impl DispatchServer {
+    fn member_by_name(&self, name: &str) -> Option<&'static MemberInfo> {
+        self.object
+            .members()
+            .into_iter()
+            .find(|member| member.name.eq_ignore_ascii_case(name))
+    }
+}
+
+impl IDispatch_Impl for DispatchServer {
+    fn GetTypeInfoCount(&self) -> windows::core::Result<u32> {
+        Ok(1)
+    }
+
+    fn GetTypeInfo(&self, _itinfo: u32, _lcid: u32) -> windows::core::Result<ITypeInfo> {
+        let type_id = self.object.type_id();
+        if let Some(typeinfo) =
+            TYPE_INFOS.with(|infos| infos.borrow().get(&type_id).map(|(info, _)| info.clone()))
+        {
+            return Ok(typeinfo);
+        }
+        let (typeinfo, data) = create_type_info(&self.object.members())
+            .map_err(|_| windows::core::Error::from(E_UNEXPECTED))?;
+        TYPE_INFOS.with(|infos| infos.borrow_mut().insert(type_id, (typeinfo.clone(), data)));
+        Ok(typeinfo)
+    }
+
+    fn GetIDsOfNames(
+        &self,
+        _riid: *const GUID,
+        rgsznames: *const PCWSTR,
+        cnames: u32,
+        _lcid: u32,
+        rgdispid: *mut i32,
+    ) -> windows::core::Result<()> {
+        let names = unsafe { std::slice::from_raw_parts(rgsznames, cnames as usize) };
+        let dispids = unsafe { std::slice::from_raw_parts_mut(rgdispid, cnames as usize) };
+        dispids.fill(DISPID_UNKNOWN);
+        let names: Vec<String> = names
+            .iter()
+            .map(|name| unsafe { name.to_string() }.unwrap_or_default())
+            .collect();
+        let Some(member) = names.first().and_then(|name| self.member_by_name(name)) else {
+            return Err(DISP_E_UNKNOWNNAME.into());
+        };
+        dispids[0] = member.dispid;
+        let mut result = Ok(());
+        for (dispid, name) in dispids[1..].iter_mut().zip(&names[1..]) {
+            // Parameters are identified by their position.
+            match member
+                .params
+                .iter()
+                .position(|param| param.name.eq_ignore_ascii_case(name))
+            {
+                Some(index) => *dispid = index as i32,
+                None => result = Err(DISP_E_UNKNOWNNAME.into()),
+            }
+        }
+        result
+    }
+
+    fn Invoke(
+        &self,
+        dispidmember: i32,
+        _riid: *const GUID,
+        _lcid: u32,
+        wflags: DISPATCH_FLAGS,
+        pdispparams: *const DISPPARAMS,
+        pvarresult: *mut VARIANT,
+        pexcepinfo: *mut EXCEPINFO,
+        puargerr: *mut u32,
+    ) -> windows::core::Result<()> {
+        if thread::current().id() != self.thread {
+            return Err(RPC_E_WRONG_THREAD.into());
+        }
+        let args = match unsafe { pdispparams.as_ref() } {
+            Some(params) => Arguments::from_params(params),
+            None => Arguments::from_params(&DISPPARAMS::default()),
+        };
+        let access = if wflags.0 & (DISPATCH_PROPERTYPUT.0 | DISPATCH_PROPERTYPUTREF.0) != 0 {
+            Access::Put
+        } else {
+            Access::Get
+        };
+        match self.object.invoke(dispidmember, access, &args) {
+            None => Err(DISP_E_MEMBERNOTFOUND.into()),
+            Some(Ok(value)) => {
+                if !pvarresult.is_null() {
+                    unsafe { pvarresult.write(value) };
+                }
+                Ok(())
+            }
+            Some(Err(error)) => Err(report_error(
+                error,
+                self.object.type_name(),
+                pexcepinfo,
+                puargerr,
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(windows)]
+    use windows::{
+        core::HRESULT,
+        Win32::Foundation::{ERROR_FILE_NOT_FOUND, E_FAIL},
+    };
+
+    const fn member(name: &'static str, dispid: i32, kind: MemberKind) -> MemberInfo {
+        MemberInfo {
+            name,
+            dispid,
+            kind,
+            get: true,
+            put: false,
+            params: &[],
+            vt: VT_I4.0,
+        }
+    }
+
+    #[test]
+    fn distinct_dispids() {
+        check_dispids(
+            &[member("Owner", 1, MemberKind::Property)],
+            &[member("Deposit", 1001, MemberKind::Method)],
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "the same DISPID")]
+    fn colliding_dispids() {
+        check_dispids(
+            &[
+                member("Owner", 1, MemberKind::Property),
+                member("Balance", 2, MemberKind::Property),
+            ],
+            &[member("Deposit", 2, MemberKind::Method)],
+        );
+    }
+
+    #[cfg(windows)]
+    #[derive(crate::Dispatch)]
+    #[dispatch(methods)]
+    struct Account {
+        pub owner: String,
+        #[dispatch(get)]
+        balance: i32,
+    }
+
+    #[cfg(windows)]
+    #[crate::dispatch_impl]
+    impl Account {
+        pub fn deposit(&mut self, amount: i32, times: Option<i32>) -> i32 {
+            self.balance += amount * times.unwrap_or(1);
+            self.balance
+        }
+        pub fn withdraw(&mut self, amount: i32) -> Result<i32> {
+            if amount > self.balance {
+                return Err(Error::Custom("insufficient funds".into()));
+            }
+            self.balance -= amount;
+            Ok(self.balance)
+        }
+        pub fn statement(&self, month: String) -> Result<String> {
+            Err(Error::NotFound(format!("statement for {month}")))
+        }
+    }
+
+    /// The value of `result`, without `Debug`ging the error
+    #[cfg(windows)]
+    fn value<T>(result: Result<T>) -> T {
+        match result {
+            Ok(value) => value,
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    #[cfg(windows)]
+    fn account() -> OleData {
+        Account {
+            owner: "Ada".to_string(),
+            balance: 10,
+        }
+        .into_ole()
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn fields() {
+        let account = account();
+        assert_eq!(
+            value(account.get_value("Owner", vec![])),
+            Variant::from("Ada")
+        );
+        value(account.put_value("owner", vec![], "Grace".into()));
+        assert_eq!(
+            value(account.get_value("OWNER", vec![])),
+            Variant::from("Grace")
+        );
+        assert_eq!(value(account.get_value("Balance", vec![])), Variant::I4(10));
+        let error = account.put_value("Balance", vec![], 0.into()).unwrap_err();
+        assert_eq!(error.hresult(), Some(DISP_E_MEMBERNOTFOUND));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn arguments() {
+        let account = account();
+        // `times` is an `Option` and may be omitted, either left out or passed as missing.
+        assert_eq!(
+            value(account.call_value("Deposit", vec![5.into()])),
+            Variant::I4(15)
+        );
+        let args = vec![VARIANT::from(5), crate::util::variant::missing_argument()];
+        let total = value(account.call("Deposit", args));
+        assert_eq!(Variant::try_from(&total).ok(), Some(Variant::I4(20)));
+        let named = vec![("Times", VARIANT::from(3)), ("amount", VARIANT::from(2))];
+        let total = value(account.call_named("Deposit", vec![], named));
+        assert_eq!(Variant::try_from(&total).ok(), Some(Variant::I4(26)));
+
+        let error = account.call_value("Deposit", vec![]).unwrap_err();
+        assert_eq!(error.hresult(), Some(DISP_E_PARAMNOTFOUND));
+        let named = vec![("Amount", VARIANT::from("many"))];
+        match account.call_named("Deposit", vec![], named) {
+            Err(Error::IDispatchArgument {
+                error_type: ComArgumentErrorType::TypeMismatch,
+                arg_err,
+                ..
+            }) => assert_eq!(arg_err, Some(0)),
+            Err(error) => panic!("expected a type mismatch, got {error}"),
+            Ok(_) => panic!("expected a type mismatch"),
+        }
+        let error = account
+            .call_value("Deposit", vec![1.into(), 2.into(), 3.into()])
+            .unwrap_err();
+        assert_eq!(error.hresult(), Some(DISP_E_BADPARAMCOUNT));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn method_errors() {
+        let account = account();
+        match account.call_value("Withdraw", vec![100.into()]) {
+            Err(Error::Exception(exception)) => {
+                assert_eq!(exception.source, "Account");
+                assert!(exception.description.contains("insufficient funds"));
+                assert_eq!(exception.hresult, E_FAIL);
+            }
+            Err(error) => panic!("expected an exception, got {error}"),
+            Ok(_) => panic!("expected an exception"),
+        }
+        match account.call_value("Statement", vec!["May".into()]) {
+            Err(Error::Exception(exception)) => {
+                assert!(exception.description.contains("statement for May"));
+                assert_eq!(
+                    exception.hresult,
+                    HRESULT::from_win32(ERROR_FILE_NOT_FOUND.0)
+                );
+            }
+            Err(error) => panic!("expected an exception, got {error}"),
+            Ok(_) => panic!("expected an exception"),
+        }
+    }
+}
@@ -1,6 +1,7 @@
+#[cfg(windows)]
+use std::os::windows::prelude::{OsStrExt, OsStringExt};
 use std::{
     ffi::{OsStr, OsString},
-    os::windows::prelude::{OsStrExt, OsStringExt},
     slice,
 };
 
@@ -14,13 +15,37 @@ where
     T: AsRef<OsStr>,
 {
     fn to_wide(&self) -> Vec<u16> {
-        self.as_ref().encode_wide().collect()
+        encode_wide(self.as_ref()).collect()
     }
     fn to_wide_null(&self) -> Vec<u16> {
-        self.as_ref().encode_wide().chain(Some(0)).collect()
+        encode_wide(self.as_ref()).chain(Some(0)).collect()
     }
 }
 
+#[cfg(windows)]
+fn encode_wide(s: &OsStr) -> impl Iterator<Item = u16> + '_ {
+    s.encode_wide()
+}
+
+/// Outside Windows an `OsStr` is not UTF-16, so it is converted lossily
+#[cfg(not(windows))]
+fn encode_wide(s: &OsStr) -> impl Iterator<Item = u16> + '_ {
+    s.to_string_lossy()
+        .encode_utf16()
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+#[cfg(windows)]
+pub fn os_string_from_wide(wide: &[u16]) -> OsString {
+    OsStringExt::from_wide(wide)
+}
+
+#[cfg(not(windows))]
+pub fn os_string_from_wide(wide: &[u16]) -> OsString {
+    String::from_utf16_lossy(wide).into()
+}
+
 pub unsafe fn os_string_from_ptr(ptr: *const u16) -> OsString {
     let mut len = 0;
     while *ptr.offset(len) != 0 {
@@ -29,7 +54,7 @@ pub unsafe fn os_string_from_ptr(ptr: *const u16) -> OsString {
 
     // Push it onto the list.
     let buf = slice::from_raw_parts(ptr, len as usize);
-    OsStringExt::from_wide(buf)
+    os_string_from_wide(buf)
 }
 
 /*pub fn to_u16s<S: AsRef<OsStr>>(s: S) -> Result<Vec<u16>> {
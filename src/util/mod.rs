@@ -1,5 +1,6 @@
 pub mod conv;
 pub mod ole;
 mod registry;
+pub(crate) mod variant;
 
 pub(crate) use registry::RegKey;
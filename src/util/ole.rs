@@ -1,5 +1,6 @@
 use crate::{
     error::{OleError, Result},
+    msgpump::{MessagePump, PumpExit},
     ToWide, G_RUNNING_NANO,
 };
 use std::{ffi::OsStr, marker::PhantomData, ptr};
@@ -80,6 +81,15 @@ pub fn ole_initialized() {
     OLE_INITIALIZED.with(|_| {});
 }
 
+/// Dispatch every message currently queued for this thread
+///
+/// Single-threaded apartments receive cross-apartment calls and events as window messages,
+/// so a thread that owns STA objects must keep calling this while it is otherwise idle.
+/// Returns [`PumpExit::Quit`] if `WM_QUIT` was among the messages.
+pub(crate) fn pump_messages() -> Option<PumpExit> {
+    MessagePump::new().pump()
+}
+
 pub fn get_class_id<S: AsRef<OsStr>>(s: S) -> Result<GUID> {
     let prog_id = s.to_wide_null();
     let prog_id = PCWSTR::from_raw(prog_id.as_ptr());
@@ -1,9 +1,7 @@
-use crate::{error::Result, ToWide};
+use crate::{error::Result, util::conv::os_string_from_wide, ToWide};
 use std::{
     ffi::{OsStr, OsString},
-    fmt,
-    os::windows::prelude::OsStringExt,
-    slice,
+    fmt, slice,
 };
 use windows::{
     core::{PCWSTR, PWSTR},
@@ -92,7 +90,7 @@ impl FromRegValue for OsString {
                 while let Some(0) = words.last() {
                     words = &words[0..words.len() - 1];
                 }
-                let s = OsString::from_wide(words);
+                let s = os_string_from_wide(words);
                 Ok(s)
             }
             _ => Err(windows::core::Error::from(ERROR_BAD_FILE_TYPE).into()),
@@ -112,7 +110,7 @@ impl FromRegValue for Vec<OsString> {
                 }
                 let v: Vec<OsString> = words
                     .split(|ch| *ch == 0u16)
-                    .map(OsString::from_wide)
+                    .map(os_string_from_wide)
                     .collect();
                 Ok(v)
             }
@@ -229,7 +227,7 @@ impl RegKey {
 
     /// Return an iterator over subkeys names.
     ///
-    pub const fn enum_keys(&self) -> EnumKeys {
+    pub const fn enum_keys(&self) -> EnumKeys<'_> {
         EnumKeys {
             key: self,
             index: 0,
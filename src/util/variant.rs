@@ -0,0 +1,211 @@
+use std::ffi::c_void;
+
+use windows::{
+    core::{IUnknown, Interface},
+    Win32::{
+        Foundation::{DISP_E_PARAMNOTFOUND, DISP_E_TYPEMISMATCH},
+        System::{
+            Com::{IDispatch, DISPPARAMS},
+            Variant::{
+                VariantChangeType, VARENUM, VARIANT, VAR_CHANGE_FLAGS, VT_ARRAY, VT_BOOL, VT_BSTR,
+                VT_BYREF, VT_CY, VT_DATE, VT_DISPATCH, VT_ERROR, VT_I2, VT_I4, VT_I8, VT_R4, VT_R8,
+                VT_TYPEMASK, VT_UI1, VT_UI2, VT_UI4, VT_UI8, VT_UNKNOWN, VT_VARIANT,
+            },
+        },
+    },
+};
+
+/// Builds a `VT_BYREF | VT_VARIANT` argument pointing at caller-owned storage, so that
+/// whatever the server writes back ends up in `target`.
+pub(crate) fn byref_variant(target: &mut VARIANT) -> VARIANT {
+    let mut variant = VARIANT::default();
+    unsafe {
+        let inner = &mut *variant.Anonymous.Anonymous;
+        inner.vt = VARENUM(VT_BYREF.0 | VT_VARIANT.0);
+        inner.Anonymous.pvarVal = target;
+    }
+    variant
+}
+
+/// Builds a `VT_BYREF` argument of `storage`'s own type pointing at its value, for parameters
+/// declared as a pointer to that type. `storage` must not hold a `DECIMAL`, which has no value
+/// field to point at.
+pub(crate) fn typed_byref_variant(storage: &mut VARIANT) -> VARIANT {
+    let vt = storage.vt();
+    let mut variant = VARIANT::default();
+    unsafe {
+        let value = &mut (*storage.Anonymous.Anonymous).Anonymous;
+        let inner = &mut *variant.Anonymous.Anonymous;
+        inner.vt = VARENUM(VT_BYREF.0 | vt.0);
+        inner.Anonymous.byref = value as *mut _ as *mut c_void;
+    }
+    variant
+}
+
+/// The arguments of `params` in `rgvarg` order, and the DISPIDs of the named ones, which come
+/// first.
+pub(crate) fn dispparams_args(params: &DISPPARAMS) -> (&[VARIANT], &[i32]) {
+    let args = if params.rgvarg.is_null() {
+        &[][..]
+    } else {
+        unsafe { std::slice::from_raw_parts(params.rgvarg, params.cArgs as usize) }
+    };
+    let named = if params.rgdispidNamedArgs.is_null() {
+        &[][..]
+    } else {
+        unsafe { std::slice::from_raw_parts(params.rgdispidNamedArgs, params.cNamedArgs as usize) }
+    };
+    (args, named)
+}
+
+/// The conventional placeholder for an omitted optional argument: `VT_ERROR` holding
+/// `DISP_E_PARAMNOTFOUND`.
+pub(crate) fn missing_argument() -> VARIANT {
+    let mut variant = VARIANT::default();
+    unsafe {
+        let inner = &mut *variant.Anonymous.Anonymous;
+        inner.vt = VT_ERROR;
+        inner.Anonymous.scode = DISP_E_PARAMNOTFOUND.0;
+    }
+    variant
+}
+
+pub(crate) fn is_missing_argument(variant: &VARIANT) -> bool {
+    variant.vt() == VT_ERROR
+        && unsafe { variant.Anonymous.Anonymous.Anonymous.scode } == DISP_E_PARAMNOTFOUND.0
+}
+
+/// Store `value` where the `VT_BYREF` variant `target` points, converted to the referenced
+/// type. This is how a callee answers `[in, out]` parameters such as `Cancel`.
+pub(crate) fn write_byref(target: &VARIANT, value: &VARIANT) -> windows::core::Result<()> {
+    let vt = target.vt();
+    if vt.0 & VT_BYREF.0 == 0 || vt.0 & VT_ARRAY.0 != 0 {
+        return Err(DISP_E_TYPEMISMATCH.into());
+    }
+    let base = VARENUM(vt.0 & !VT_BYREF.0);
+    unsafe {
+        let refs = &target.Anonymous.Anonymous.Anonymous;
+        if base == VT_VARIANT {
+            *refs.pvarVal = value.clone();
+            return Ok(());
+        }
+        let mut converted = VARIANT::default();
+        VariantChangeType(&mut converted, value, VAR_CHANGE_FLAGS(0), base)?;
+        let value = &converted.Anonymous.Anonymous.Anonymous;
+        match base {
+            VT_BOOL => *refs.pboolVal = value.boolVal,
+            VT_UI1 => *refs.pbVal = value.bVal,
+            VT_I2 => *refs.piVal = value.iVal,
+            VT_UI2 => *refs.puiVal = value.uiVal,
+            VT_I4 => *refs.plVal = value.lVal,
+            VT_UI4 => *refs.pulVal = value.ulVal,
+            VT_I8 => *refs.pllVal = value.llVal,
+            VT_UI8 => *refs.pullVal = value.ullVal,
+            VT_R4 => *refs.pfltVal = value.fltVal,
+            VT_R8 => *refs.pdblVal = value.dblVal,
+            VT_DATE => *refs.pdate = value.date,
+            VT_CY => *refs.pcyVal = value.cyVal,
+            VT_ERROR => *refs.pscode = value.scode,
+            VT_BSTR => *refs.pbstrVal = (*value.bstrVal).clone(),
+            VT_DISPATCH => *refs.ppdispVal = (*value.pdispVal).clone(),
+            VT_UNKNOWN => *refs.ppunkVal = (*value.punkVal).clone(),
+            _ => return Err(DISP_E_TYPEMISMATCH.into()),
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn vartype_name(vt: VARENUM) -> String {
+    let base = match vt.0 & VT_TYPEMASK.0 {
+        0 => "EMPTY",
+        1 => "NULL",
+        2 => "I2",
+        3 => "I4",
+        4 => "R4",
+        5 => "R8",
+        6 => "CY",
+        7 => "DATE",
+        8 => "BSTR",
+        9 => "DISPATCH",
+        10 => "ERROR",
+        11 => "BOOL",
+        12 => "VARIANT",
+        13 => "UNKNOWN",
+        14 => "DECIMAL",
+        16 => "I1",
+        17 => "UI1",
+        18 => "UI2",
+        19 => "UI4",
+        20 => "I8",
+        21 => "UI8",
+        22 => "INT",
+        23 => "UINT",
+        24 => "VOID",
+        25 => "HRESULT",
+        26 => "PTR",
+        27 => "SAFEARRAY",
+        28 => "CARRAY",
+        29 => "USERDEFINED",
+        30 => "LPSTR",
+        31 => "LPWSTR",
+        36 => "RECORD",
+        other => return format!("Unknown Type {other}"),
+    };
+    let mut name = base.to_string();
+    if vt.0 & VT_ARRAY.0 != 0 {
+        name = format!("ARRAY|{name}");
+    }
+    if vt.0 & VT_BYREF.0 != 0 {
+        name = format!("BYREF|{name}");
+    }
+    name
+}
+
+/// The `IUnknown` held by a `VT_UNKNOWN` or `VT_DISPATCH` variant, if any.
+pub(crate) fn variant_unknown(variant: &VARIANT) -> Option<IUnknown> {
+    unsafe {
+        let inner = &variant.Anonymous.Anonymous;
+        match inner.vt {
+            VT_UNKNOWN => (*inner.Anonymous.punkVal).clone(),
+            VT_DISPATCH => (*inner.Anonymous.pdispVal).clone().map(IUnknown::from),
+            _ => None,
+        }
+    }
+}
+
+/// The `IDispatch` held by a `VT_DISPATCH` variant, or queried from a `VT_UNKNOWN` one.
+pub(crate) fn variant_dispatch(variant: &VARIANT) -> Option<IDispatch> {
+    unsafe {
+        let inner = &variant.Anonymous.Anonymous;
+        match inner.vt {
+            VT_DISPATCH => (*inner.Anonymous.pdispVal).clone(),
+            VT_UNKNOWN => (*inner.Anonymous.punkVal)
+                .as_ref()
+                .and_then(|unknown| unknown.cast().ok()),
+            _ => None,
+        }
+    }
+}
+
+// VARIANTs are cleared through oleaut32, so these only link on Windows.
+#[cfg(all(test, windows))]
+mod tests {
+    use super::*;
+    use crate::value::Variant;
+
+    #[test]
+    fn typed_byref_round_trip() {
+        let mut storage = VARIANT::from(1i32);
+        let byref = typed_byref_variant(&mut storage);
+        assert_eq!(byref.vt(), VARENUM(VT_BYREF.0 | VT_I4.0));
+        write_byref(&byref, &VARIANT::from("42")).unwrap();
+        drop(byref);
+        assert_eq!(i32::try_from(&storage).unwrap(), 42);
+
+        let mut storage = VARIANT::from("before");
+        let byref = typed_byref_variant(&mut storage);
+        write_byref(&byref, &VARIANT::from("after")).unwrap();
+        drop(byref);
+        assert_eq!(Variant::try_from(&storage).ok(), Some(Variant::from("after")));
+    }
+}
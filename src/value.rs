@@ -0,0 +1,436 @@
+use std::{mem::ManuallyDrop, ptr};
+
+use windows::{
+    core::{IUnknown, BSTR},
+    Win32::System::{
+        Com::{IDispatch, CY, SAFEARRAY},
+        Ole::{
+            SafeArrayCreateVector, SafeArrayGetDim, SafeArrayGetLBound, SafeArrayGetUBound,
+            SafeArrayLock, SafeArrayPtrOfIndex, SafeArrayPutElement, SafeArrayUnlock,
+        },
+        Variant::{
+            VariantChangeType, VariantCopyInd, VARENUM, VARIANT, VARIANT_0_0_0, VAR_CHANGE_FLAGS,
+            VT_ARRAY, VT_BOOL, VT_BSTR, VT_BYREF, VT_CY, VT_DATE, VT_DECIMAL, VT_DISPATCH,
+            VT_EMPTY, VT_ERROR, VT_I1, VT_I2, VT_I4, VT_I8, VT_INT, VT_NULL, VT_R4, VT_R8,
+            VT_TYPEMASK, VT_UI1, VT_UI2, VT_UI4, VT_UI8, VT_UINT, VT_UNKNOWN, VT_VARIANT,
+        },
+    },
+};
+
+use crate::{
+    error::{Error, Result},
+    util::variant::{missing_argument, variant_dispatch, variant_unknown, vartype_name},
+    OleData,
+};
+
+/// Conversion of Rust values into `VARIANT` arguments
+pub trait IntoVariant {
+    fn into_variant(self) -> VARIANT;
+}
+
+fn variant_with(vt: VARENUM, set: impl FnOnce(&mut VARIANT_0_0_0)) -> VARIANT {
+    let mut variant = VARIANT::default();
+    unsafe {
+        let inner = &mut *variant.Anonymous.Anonymous;
+        inner.vt = vt;
+        set(&mut inner.Anonymous);
+    }
+    variant
+}
+
+macro_rules! impl_into_variant {
+    ($($t:ty => $vt:ident, $field:ident;)*) => {
+        $(
+            impl IntoVariant for $t {
+                fn into_variant(self) -> VARIANT {
+                    variant_with($vt, |value| value.$field = self)
+                }
+            }
+        )*
+    };
+}
+
+impl_into_variant! {
+    u8 => VT_UI1, bVal;
+    i16 => VT_I2, iVal;
+    u16 => VT_UI2, uiVal;
+    i32 => VT_I4, lVal;
+    u32 => VT_UI4, ulVal;
+    i64 => VT_I8, llVal;
+    u64 => VT_UI8, ullVal;
+    f32 => VT_R4, fltVal;
+    f64 => VT_R8, dblVal;
+}
+
+impl IntoVariant for VARIANT {
+    fn into_variant(self) -> VARIANT {
+        self
+    }
+}
+
+impl IntoVariant for () {
+    fn into_variant(self) -> VARIANT {
+        VARIANT::default()
+    }
+}
+
+impl IntoVariant for bool {
+    fn into_variant(self) -> VARIANT {
+        VARIANT::from(self)
+    }
+}
+
+impl IntoVariant for BSTR {
+    fn into_variant(self) -> VARIANT {
+        variant_with(VT_BSTR, |value| value.bstrVal = ManuallyDrop::new(self))
+    }
+}
+
+impl IntoVariant for &str {
+    fn into_variant(self) -> VARIANT {
+        BSTR::from(self).into_variant()
+    }
+}
+
+impl IntoVariant for String {
+    fn into_variant(self) -> VARIANT {
+        self.as_str().into_variant()
+    }
+}
+
+impl IntoVariant for &String {
+    fn into_variant(self) -> VARIANT {
+        self.as_str().into_variant()
+    }
+}
+
+impl IntoVariant for IDispatch {
+    fn into_variant(self) -> VARIANT {
+        variant_with(VT_DISPATCH, |value| {
+            value.pdispVal = ManuallyDrop::new(Some(self))
+        })
+    }
+}
+
+impl IntoVariant for IUnknown {
+    fn into_variant(self) -> VARIANT {
+        variant_with(VT_UNKNOWN, |value| {
+            value.punkVal = ManuallyDrop::new(Some(self))
+        })
+    }
+}
+
+impl IntoVariant for OleData {
+    fn into_variant(self) -> VARIANT {
+        self.as_dispatch().into_variant()
+    }
+}
+
+impl IntoVariant for &OleData {
+    fn into_variant(self) -> VARIANT {
+        self.as_dispatch().into_variant()
+    }
+}
+
+/// `None` is `VT_EMPTY`; pass [`Missing`] to omit an optional argument.
+impl<T: IntoVariant> IntoVariant for Option<T> {
+    fn into_variant(self) -> VARIANT {
+        match self {
+            Some(value) => value.into_variant(),
+            None => VARIANT::default(),
+        }
+    }
+}
+
+/// An omitted optional argument, passed as `VT_ERROR` holding `DISP_E_PARAMNOTFOUND`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Missing;
+
+impl IntoVariant for Missing {
+    fn into_variant(self) -> VARIANT {
+        missing_argument()
+    }
+}
+
+/// An owned, Rust-side copy of a `VARIANT`
+///
+/// Objects are represented by `O`, which is [`OleData`] for values used on the thread that
+/// owns them. Arrays are nested by dimension, so a two dimensional `Range.Value` becomes a
+/// vector of rows.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Variant<O = OleData> {
+    Empty,
+    Null,
+    Nothing,
+    Bool(bool),
+    UI1(u8),
+    I2(i16),
+    UI2(u16),
+    I4(i32),
+    UI4(u32),
+    I8(i64),
+    UI8(u64),
+    R4(f32),
+    R8(f64),
+    /// Currency, scaled by 10,000
+    Currency(i64),
+    /// An OLE automation date
+    Date(f64),
+    String(String),
+    /// An `SCODE`, as in `VT_ERROR`
+    Error(i32),
+    Array(Vec<Variant<O>>),
+    Object(O),
+}
+
+impl<O> Variant<O> {
+    /// Convert the objects held by this value, leaving everything else untouched
+    ///
+    pub fn try_map_objects<P, E>(
+        self,
+        f: &mut impl FnMut(O) -> std::result::Result<P, E>,
+    ) -> std::result::Result<Variant<P>, E> {
+        Ok(match self {
+            Variant::Empty => Variant::Empty,
+            Variant::Null => Variant::Null,
+            Variant::Nothing => Variant::Nothing,
+            Variant::Bool(value) => Variant::Bool(value),
+            Variant::UI1(value) => Variant::UI1(value),
+            Variant::I2(value) => Variant::I2(value),
+            Variant::UI2(value) => Variant::UI2(value),
+            Variant::I4(value) => Variant::I4(value),
+            Variant::UI4(value) => Variant::UI4(value),
+            Variant::I8(value) => Variant::I8(value),
+            Variant::UI8(value) => Variant::UI8(value),
+            Variant::R4(value) => Variant::R4(value),
+            Variant::R8(value) => Variant::R8(value),
+            Variant::Currency(value) => Variant::Currency(value),
+            Variant::Date(value) => Variant::Date(value),
+            Variant::String(value) => Variant::String(value),
+            Variant::Error(value) => Variant::Error(value),
+            Variant::Array(items) => Variant::Array(
+                items
+                    .into_iter()
+                    .map(|item| item.try_map_objects(f))
+                    .collect::<std::result::Result<_, E>>()?,
+            ),
+            Variant::Object(object) => Variant::Object(f(object)?),
+        })
+    }
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Variant::Empty)
+    }
+    pub fn as_object(&self) -> Option<&O> {
+        match self {
+            Variant::Object(object) => Some(object),
+            _ => None,
+        }
+    }
+    pub fn into_object(self) -> Option<O> {
+        match self {
+            Variant::Object(object) => Some(object),
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<&VARIANT> for Variant {
+    type Error = Error;
+
+    fn try_from(variant: &VARIANT) -> Result<Variant> {
+        let vt = variant.vt();
+        if vt.0 & VT_BYREF.0 != 0 {
+            let mut copy = VARIANT::default();
+            unsafe { VariantCopyInd(&mut copy, variant)? };
+            return Variant::try_from(&copy);
+        }
+        if vt.0 & VT_ARRAY.0 != 0 {
+            let psa = unsafe { variant.Anonymous.Anonymous.Anonymous.parray };
+            return safearray_to_variant(psa, VARENUM(vt.0 & VT_TYPEMASK.0));
+        }
+        let value = unsafe { &variant.Anonymous.Anonymous.Anonymous };
+        Ok(unsafe {
+            match vt {
+                VT_EMPTY => Variant::Empty,
+                VT_NULL => Variant::Null,
+                VT_BOOL => Variant::Bool(value.boolVal.0 != 0),
+                VT_UI1 => Variant::UI1(value.bVal),
+                VT_I2 => Variant::I2(value.iVal),
+                VT_UI2 => Variant::UI2(value.uiVal),
+                VT_I4 => Variant::I4(value.lVal),
+                VT_UI4 => Variant::UI4(value.ulVal),
+                VT_I8 => Variant::I8(value.llVal),
+                VT_UI8 => Variant::UI8(value.ullVal),
+                VT_R4 => Variant::R4(value.fltVal),
+                VT_R8 => Variant::R8(value.dblVal),
+                VT_CY => Variant::Currency(value.cyVal.int64),
+                VT_DATE => Variant::Date(value.date),
+                VT_BSTR => Variant::String(value.bstrVal.to_string()),
+                VT_ERROR => Variant::Error(value.scode),
+                VT_DISPATCH | VT_UNKNOWN => match variant_dispatch(variant) {
+                    Some(dispatch) => Variant::Object(OleData::from_dispatch(dispatch)),
+                    None if variant_unknown(variant).is_none() => Variant::Nothing,
+                    None => {
+                        return Err(Error::Custom(
+                            "VT_UNKNOWN value does not implement IDispatch".into(),
+                        ))
+                    }
+                },
+                VT_I1 | VT_INT => return coerced(variant, VT_I4),
+                VT_UINT => return coerced(variant, VT_UI4),
+                VT_DECIMAL => return coerced(variant, VT_R8),
+                other => {
+                    return Err(Error::Custom(format!(
+                        "cannot convert a {} VARIANT",
+                        vartype_name(other)
+                    )))
+                }
+            }
+        })
+    }
+}
+
+impl TryFrom<VARIANT> for Variant {
+    type Error = Error;
+
+    fn try_from(variant: VARIANT) -> Result<Variant> {
+        Variant::try_from(&variant)
+    }
+}
+
+fn coerced(variant: &VARIANT, vt: VARENUM) -> Result<Variant> {
+    let mut converted = VARIANT::default();
+    unsafe { VariantChangeType(&mut converted, variant, VAR_CHANGE_FLAGS(0), vt)? };
+    Variant::try_from(&converted)
+}
+
+fn safearray_to_variant(psa: *mut SAFEARRAY, vt: VARENUM) -> Result<Variant> {
+    if psa.is_null() {
+        return Ok(Variant::Null);
+    }
+    let dims = unsafe { SafeArrayGetDim(psa) };
+    let mut bounds = Vec::with_capacity(dims as usize);
+    for dim in 1..=dims {
+        let lower = unsafe { SafeArrayGetLBound(psa, dim)? };
+        let upper = unsafe { SafeArrayGetUBound(psa, dim)? };
+        bounds.push((lower, upper));
+    }
+    let mut indices: Vec<i32> = bounds.iter().map(|(lower, _)| *lower).collect();
+    unsafe { SafeArrayLock(psa)? };
+    let result = safearray_dimension(psa, vt, &bounds, &mut indices, 0);
+    unsafe { SafeArrayUnlock(psa)? };
+    result
+}
+
+fn safearray_dimension(
+    psa: *mut SAFEARRAY,
+    vt: VARENUM,
+    bounds: &[(i32, i32)],
+    indices: &mut [i32],
+    dim: usize,
+) -> Result<Variant> {
+    let (lower, upper) = bounds[dim];
+    let mut items = vec![];
+    for index in lower..=upper {
+        indices[dim] = index;
+        if dim + 1 < bounds.len() {
+            items.push(safearray_dimension(psa, vt, bounds, indices, dim + 1)?);
+            continue;
+        }
+        let mut element = ptr::null_mut();
+        unsafe { SafeArrayPtrOfIndex(psa, indices.as_ptr(), &mut element)? };
+        let item = if vt == VT_VARIANT {
+            Variant::try_from(unsafe { &*(element as *const VARIANT) })?
+        } else {
+            let reference = variant_with(VARENUM(vt.0 | VT_BYREF.0), |value| value.byref = element);
+            Variant::try_from(&reference)?
+        };
+        items.push(item);
+    }
+    Ok(Variant::Array(items))
+}
+
+impl IntoVariant for Variant {
+    fn into_variant(self) -> VARIANT {
+        match self {
+            Variant::Empty => VARIANT::default(),
+            Variant::Null => variant_with(VT_NULL, |_| ()),
+            Variant::Nothing => variant_with(VT_DISPATCH, |value| {
+                value.pdispVal = ManuallyDrop::new(None)
+            }),
+            Variant::Bool(value) => value.into_variant(),
+            Variant::UI1(value) => value.into_variant(),
+            Variant::I2(value) => value.into_variant(),
+            Variant::UI2(value) => value.into_variant(),
+            Variant::I4(value) => value.into_variant(),
+            Variant::UI4(value) => value.into_variant(),
+            Variant::I8(value) => value.into_variant(),
+            Variant::UI8(value) => value.into_variant(),
+            Variant::R4(value) => value.into_variant(),
+            Variant::R8(value) => value.into_variant(),
+            Variant::Currency(amount) => {
+                variant_with(VT_CY, |value| value.cyVal = CY { int64: amount })
+            }
+            Variant::Date(date) => variant_with(VT_DATE, |value| value.date = date),
+            Variant::String(value) => value.into_variant(),
+            Variant::Error(scode) => variant_with(VT_ERROR, |value| value.scode = scode),
+            Variant::Array(items) => {
+                let psa = unsafe { SafeArrayCreateVector(VT_VARIANT, 0, items.len() as u32) };
+                for (index, item) in items.into_iter().enumerate() {
+                    let element = item.into_variant();
+                    let index = index as i32;
+                    // SafeArrayPutElement copies the VARIANT, so `element` is dropped normally.
+                    let _ = unsafe {
+                        SafeArrayPutElement(psa, &index, &element as *const VARIANT as *const _)
+                    };
+                }
+                variant_with(VARENUM(VT_ARRAY.0 | VT_VARIANT.0), |value| {
+                    value.parray = psa
+                })
+            }
+            Variant::Object(object) => object.into_variant(),
+        }
+    }
+}
+
+impl<O> From<bool> for Variant<O> {
+    fn from(value: bool) -> Self {
+        Variant::Bool(value)
+    }
+}
+
+impl<O> From<i32> for Variant<O> {
+    fn from(value: i32) -> Self {
+        Variant::I4(value)
+    }
+}
+
+impl<O> From<i64> for Variant<O> {
+    fn from(value: i64) -> Self {
+        Variant::I8(value)
+    }
+}
+
+impl<O> From<f64> for Variant<O> {
+    fn from(value: f64) -> Self {
+        Variant::R8(value)
+    }
+}
+
+impl<O> From<&str> for Variant<O> {
+    fn from(value: &str) -> Self {
+        Variant::String(value.to_string())
+    }
+}
+
+impl<O> From<String> for Variant<O> {
+    fn from(value: String) -> Self {
+        Variant::String(value)
+    }
+}
+
+impl From<OleData> for Variant {
+    fn from(value: OleData) -> Self {
+        Variant::Object(value)
+    }
+}
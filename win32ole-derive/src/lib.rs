@@ -0,0 +1,649 @@
+//! Derive macros for `win32ole::server`.
+//!
+//! `#[derive(Dispatch)]` exposes struct fields as properties and implements
+//! `DispatchObject`; `#[dispatch_impl]` exposes the public methods of an impl block.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, spanned::Spanned, Attribute, Data, DeriveInput, Fields, FnArg,
+    GenericArgument, ImplItem, ImplItemFn, ItemImpl, LitInt, LitStr, Pat, PathArguments,
+    ReturnType, Type, Visibility,
+};
+
+/// First DISPID given to methods, leaving the range below for fields
+const FIRST_METHOD_DISPID: i32 = 1001;
+
+#[derive(Default)]
+struct Options {
+    name: Option<String>,
+    dispid: Option<i32>,
+    get: bool,
+    put: bool,
+    skip: bool,
+    methods: bool,
+    present: bool,
+}
+
+fn options(attrs: &[Attribute]) -> syn::Result<Options> {
+    let mut options = Options::default();
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("dispatch")) {
+        options.present = true;
+        if matches!(attr.meta, syn::Meta::Path(_)) {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                options.name = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("dispid") {
+                options.dispid = Some(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+            } else if meta.path.is_ident("get") {
+                options.get = true;
+            } else if meta.path.is_ident("put") {
+                options.put = true;
+            } else if meta.path.is_ident("skip") {
+                options.skip = true;
+            } else if meta.path.is_ident("methods") {
+                options.methods = true;
+            } else {
+                return Err(meta.error("unknown dispatch option"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(options)
+}
+
+/// `snake_case` to `PascalCase`
+fn pascal_case(name: &str) -> String {
+    name.trim_start_matches("r#")
+        .split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+/// Expose the fields of a struct as automation properties
+///
+/// Public fields are readable and writable unless marked `#[dispatch(skip)]`; other fields
+/// are exposed when they carry a `#[dispatch]` attribute. `#[dispatch(get)]` or
+/// `#[dispatch(put)]` restrict the direction, `name = "..."` and `dispid = N` override the
+/// defaults. Mark the struct `#[dispatch(methods)]` when it also has a `#[dispatch_impl]` block;
+/// a DISPID shared by two members, fields or methods, is a compile error.
+#[proc_macro_derive(Dispatch, attributes(dispatch))]
+pub fn derive_dispatch(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    derive(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn derive(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let ident = &input.ident;
+    let struct_options = options(&input.attrs)?;
+    let type_name = struct_options
+        .name
+        .clone()
+        .unwrap_or_else(|| ident.to_string());
+    let Data::Struct(ref data) = input.data else {
+        return Err(syn::Error::new(
+            input.span(),
+            "Dispatch can only be derived for structs",
+        ));
+    };
+    let fields = match data.fields {
+        Fields::Named(ref fields) => fields.named.iter().collect(),
+        Fields::Unit => vec![],
+        Fields::Unnamed(_) => {
+            return Err(syn::Error::new(
+                data.fields.span(),
+                "Dispatch requires named fields",
+            ))
+        }
+    };
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    let mut infos = vec![];
+    let mut gets = vec![];
+    let mut puts = vec![];
+    let mut dispids = vec![];
+    let mut next_dispid = 1;
+    for field in fields {
+        let field_options = options(&field.attrs)?;
+        let public = matches!(field.vis, Visibility::Public(_));
+        if field_options.skip || !(public || field_options.present) {
+            continue;
+        }
+        let field_ident = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
+        let name = field_options
+            .name
+            .unwrap_or_else(|| pascal_case(&field_ident.to_string()));
+        let dispid = field_options.dispid.unwrap_or(next_dispid);
+        next_dispid = dispid + 1;
+        check_dispid(&mut dispids, dispid, &name, field.span())?;
+        let (get, put) = match (field_options.get, field_options.put) {
+            (false, false) => (true, true),
+            directions => directions,
+        };
+        infos.push(quote! {
+            ::win32ole::server::MemberInfo {
+                name: #name,
+                dispid: #dispid,
+                kind: ::win32ole::server::MemberKind::Property,
+                get: #get,
+                put: #put,
+                params: &[],
+                vt: <#ty as ::win32ole::server::VarType>::VT.0,
+            }
+        });
+        if get {
+            gets.push(quote! {
+                #dispid => Some(Ok(::win32ole::IntoVariant::into_variant(
+                    ::std::clone::Clone::clone(&self.#field_ident),
+                ))),
+            });
+        }
+        if put {
+            puts.push(quote! {
+                #dispid => Some(
+                    <#ty as ::win32ole::server::FromVariant>::from_variant(value)
+                        .map(|value| self.#field_ident = value),
+                ),
+            });
+        }
+    }
+
+    let (members, invoke, exposes_methods) = if struct_options.methods {
+        (
+            quote! {
+                const {
+                    ::win32ole::server::check_dispids(
+                        <Self as ::win32ole::server::DispatchFields>::FIELD_MEMBERS,
+                        <Self as ::win32ole::server::DispatchMethods>::METHOD_MEMBERS,
+                    )
+                };
+                <Self as ::win32ole::server::DispatchFields>::FIELD_MEMBERS
+                    .iter()
+                    .chain(<Self as ::win32ole::server::DispatchMethods>::METHOD_MEMBERS)
+                    .collect()
+            },
+            quote! {
+                <Self as ::win32ole::server::DispatchMethods>::invoke_method(this, dispid, access, args)
+                    .or_else(|| ::win32ole::server::invoke_fields(this, dispid, access, args))
+            },
+            quote! {
+                impl #impl_generics ::win32ole::server::ExposesMethods for #ident #type_generics #where_clause {}
+            },
+        )
+    } else {
+        (
+            quote! {
+                <Self as ::win32ole::server::DispatchFields>::FIELD_MEMBERS
+                    .iter()
+                    .collect()
+            },
+            quote! {
+                ::win32ole::server::invoke_fields(this, dispid, access, args)
+            },
+            quote!(),
+        )
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::win32ole::server::DispatchFields for #ident #type_generics #where_clause {
+            const FIELD_MEMBERS: &'static [::win32ole::server::MemberInfo] = &[#(#infos),*];
+
+            #[allow(unreachable_code)]
+            fn get_field(
+                &self,
+                dispid: i32,
+            ) -> Option<::win32ole::error::Result<::win32ole::server::VARIANT>> {
+                match dispid {
+                    #(#gets)*
+                    _ => None,
+                }
+            }
+            #[allow(unused_variables)]
+            fn put_field(
+                &mut self,
+                dispid: i32,
+                value: &::win32ole::server::VARIANT,
+            ) -> Option<::win32ole::error::Result<()>> {
+                match dispid {
+                    #(#puts)*
+                    _ => None,
+                }
+            }
+        }
+
+        impl #impl_generics ::win32ole::server::DispatchObject for #ident #type_generics #where_clause {
+            const TYPE_NAME: &'static str = #type_name;
+
+            fn members() -> Vec<&'static ::win32ole::server::MemberInfo> {
+                #members
+            }
+
+            fn invoke_member(
+                this: &::std::cell::RefCell<Self>,
+                dispid: i32,
+                access: ::win32ole::server::Access,
+                args: &::win32ole::server::Arguments,
+            ) -> Option<::win32ole::error::Result<::win32ole::server::VARIANT>> {
+                #invoke
+            }
+        }
+
+        #exposes_methods
+    })
+}
+
+/// Record `dispid` for the member `name`, failing if another member already has it
+fn check_dispid(
+    dispids: &mut Vec<(i32, String)>,
+    dispid: i32,
+    name: &str,
+    span: proc_macro2::Span,
+) -> syn::Result<()> {
+    if let Some((_, other)) = dispids.iter().find(|(used, _)| *used == dispid) {
+        return Err(syn::Error::new(
+            span,
+            format!("{name} has DISPID {dispid}, which is already used by {other}"),
+        ));
+    }
+    dispids.push((dispid, name.to_string()));
+    Ok(())
+}
+
+/// Expose the public methods of an impl block as automation members
+///
+/// Methods become callable members named in PascalCase. `#[dispatch(get)]` makes a method
+/// a property getter and `#[dispatch(put)]` a setter taking the assigned value as its last
+/// parameter; a `get_` or `set_` prefix is dropped from the name. Parameters must be owned
+/// types implementing `FromVariant`, and `Option` parameters may be omitted by the caller.
+/// Methods returning `Result` report errors to the caller as exceptions.
+#[proc_macro_attribute]
+pub fn dispatch_impl(_attr: TokenStream, item: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(item as ItemImpl);
+    dispatch_methods(item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+struct Param {
+    ident: syn::Ident,
+    ty: Type,
+}
+
+struct Method {
+    ident: syn::Ident,
+    mutable: bool,
+    params: Vec<Param>,
+    /// The success type, and whether the method returns a `Result`
+    output: Type,
+    fallible: bool,
+}
+
+struct Member {
+    name: String,
+    dispid: i32,
+    property: bool,
+    get: Option<Method>,
+    put: Option<Method>,
+}
+
+/// The `T` of `Result<T>` or `Result<T, E>`, or `None` if `ty` is not a `Result`
+fn result_type(ty: &Type) -> Option<Type> {
+    let Type::Path(ref path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Result" {
+        return None;
+    }
+    let PathArguments::AngleBracketed(ref args) = segment.arguments else {
+        return None;
+    };
+    match args.args.first()? {
+        GenericArgument::Type(ty) => Some(ty.clone()),
+        _ => None,
+    }
+}
+
+fn method(function: &ImplItemFn) -> syn::Result<Option<Method>> {
+    let mut mutable = None;
+    let mut params = vec![];
+    for input in &function.sig.inputs {
+        match input {
+            FnArg::Receiver(receiver) => {
+                if receiver.reference.is_none() {
+                    return Err(syn::Error::new(
+                        receiver.span(),
+                        "dispatch methods take &self or &mut self",
+                    ));
+                }
+                mutable = Some(receiver.mutability.is_some());
+            }
+            FnArg::Typed(typed) => {
+                let Pat::Ident(ref ident) = *typed.pat else {
+                    return Err(syn::Error::new(
+                        typed.pat.span(),
+                        "dispatch parameters must be plain identifiers",
+                    ));
+                };
+                params.push(Param {
+                    ident: ident.ident.clone(),
+                    ty: (*typed.ty).clone(),
+                });
+            }
+        }
+    }
+    // Associated functions without a receiver are not members.
+    let Some(mutable) = mutable else {
+        return Ok(None);
+    };
+    let (output, fallible) = match function.sig.output {
+        ReturnType::Default => (syn::parse_quote!(()), false),
+        ReturnType::Type(_, ref ty) => match result_type(ty) {
+            Some(ty) => (ty, true),
+            None => ((**ty).clone(), false),
+        },
+    };
+    Ok(Some(Method {
+        ident: function.sig.ident.clone(),
+        mutable,
+        params,
+        output,
+        fallible,
+    }))
+}
+
+fn dispatch_methods(mut item: ItemImpl) -> syn::Result<TokenStream2> {
+    let mut members: Vec<Member> = vec![];
+    let mut dispids = vec![];
+    let mut next_dispid = FIRST_METHOD_DISPID;
+    for impl_item in item.items.iter_mut() {
+        let ImplItem::Fn(function) = impl_item else {
+            continue;
+        };
+        let method_options = options(&function.attrs)?;
+        function
+            .attrs
+            .retain(|attr| !attr.path().is_ident("dispatch"));
+        let public = matches!(function.vis, Visibility::Public(_));
+        if method_options.skip || !(public || method_options.present) {
+            continue;
+        }
+        let Some(method) = method(function)? else {
+            continue;
+        };
+        let ident = method.ident.to_string();
+        let name = method_options.name.unwrap_or_else(|| {
+            let ident = match (method_options.get, method_options.put) {
+                (true, _) => ident.strip_prefix("get_").unwrap_or(&ident),
+                (_, true) => ident.strip_prefix("set_").unwrap_or(&ident),
+                _ => &ident,
+            };
+            pascal_case(ident)
+        });
+        if method_options.put && method.params.is_empty() {
+            return Err(syn::Error::new(
+                function.sig.span(),
+                "a dispatch setter takes the assigned value as its last parameter",
+            ));
+        }
+        let property = method_options.get || method_options.put;
+        // A getter and a setter with the same name form one property.
+        if let Some(member) = members
+            .iter_mut()
+            .find(|member| member.property && property && member.name.eq_ignore_ascii_case(&name))
+        {
+            let slot = if method_options.put {
+                &mut member.put
+            } else {
+                &mut member.get
+            };
+            if slot.is_some() {
+                return Err(syn::Error::new(
+                    function.sig.span(),
+                    format!("the property {name} is already defined"),
+                ));
+            }
+            *slot = Some(method);
+            continue;
+        }
+        if members
+            .iter()
+            .any(|member| member.name.eq_ignore_ascii_case(&name))
+        {
+            return Err(syn::Error::new(
+                function.sig.span(),
+                format!("the member {name} is already defined"),
+            ));
+        }
+        let dispid = method_options.dispid.unwrap_or(next_dispid);
+        next_dispid = dispid + 1;
+        check_dispid(&mut dispids, dispid, &name, function.sig.span())?;
+        let (get, put) = if method_options.put {
+            (None, Some(method))
+        } else {
+            (Some(method), None)
+        };
+        members.push(Member {
+            name,
+            dispid,
+            property,
+            get,
+            put,
+        });
+    }
+
+    let mut infos = vec![];
+    let mut arms = vec![];
+    for member in &members {
+        let name = &member.name;
+        let dispid = member.dispid;
+        let (params, vt) = match (&member.get, &member.put) {
+            (Some(get), _) => {
+                let output = &get.output;
+                (
+                    &get.params[..],
+                    quote!(<#output as ::win32ole::server::VarType>::VT.0),
+                )
+            }
+            (None, Some(put)) => {
+                let (value, index) = put.params.split_last().unwrap();
+                let ty = &value.ty;
+                (index, quote!(<#ty as ::win32ole::server::VarType>::VT.0))
+            }
+            (None, None) => unreachable!(),
+        };
+        let kind = if member.property {
+            quote!(::win32ole::server::MemberKind::Property)
+        } else {
+            quote!(::win32ole::server::MemberKind::Method)
+        };
+        let param_infos = params.iter().map(|param| {
+            let name = pascal_case(&param.ident.to_string());
+            let ty = &param.ty;
+            quote! {
+                ::win32ole::server::ParamInfo {
+                    name: #name,
+                    vt: <#ty as ::win32ole::server::VarType>::VT.0,
+                }
+            }
+        });
+        let get = member.get.is_some();
+        let put = member.put.is_some();
+        infos.push(quote! {
+            ::win32ole::server::MemberInfo {
+                name: #name,
+                dispid: #dispid,
+                kind: #kind,
+                get: #get,
+                put: #put,
+                params: &[#(#param_infos),*],
+                vt: #vt,
+            }
+        });
+        if let Some(ref get) = member.get {
+            let call = call(get, &get.params, None);
+            arms.push(quote! {
+                (#dispid, ::win32ole::server::Access::Get) => #call,
+            });
+        }
+        if let Some(ref put) = member.put {
+            let (value, index) = put.params.split_last().unwrap();
+            let call = call(put, index, Some(value));
+            arms.push(quote! {
+                (#dispid, ::win32ole::server::Access::Put) => #call,
+            });
+        }
+    }
+
+    let self_ty = &item.self_ty;
+    let (impl_generics, _, where_clause) = item.generics.split_for_impl();
+    Ok(quote! {
+        #item
+
+        impl #impl_generics ::win32ole::server::DispatchMethods for #self_ty #where_clause {
+            const METHOD_MEMBERS: &'static [::win32ole::server::MemberInfo] = &[#(#infos),*];
+
+            #[allow(unused_variables)]
+            fn invoke_method(
+                this: &::std::cell::RefCell<Self>,
+                dispid: i32,
+                access: ::win32ole::server::Access,
+                args: &::win32ole::server::Arguments,
+            ) -> Option<::win32ole::error::Result<::win32ole::server::VARIANT>> {
+                match (dispid, access) {
+                    #(#arms)*
+                    _ => None,
+                }
+            }
+        }
+    })
+}
+
+/// The expression invoking `method` with arguments unpacked from `args`
+fn call(method: &Method, params: &[Param], value: Option<&Param>) -> TokenStream2 {
+    let ident = &method.ident;
+    let count = params.len();
+    let unpack = params.iter().enumerate().map(|(index, param)| {
+        let name = &param.ident;
+        let ty = &param.ty;
+        quote!(let #name: #ty = args.arg(#index)?;)
+    });
+    let put_value = value.map(|value| {
+        let name = &value.ident;
+        let ty = &value.ty;
+        quote!(let #name: #ty = args.value()?;)
+    });
+    let names: Vec<_> = params
+        .iter()
+        .chain(value)
+        .map(|param| &param.ident)
+        .collect();
+    let borrow = if method.mutable {
+        quote!(::win32ole::server::borrow_mut(this)?)
+    } else {
+        quote!(::win32ole::server::borrow(this)?)
+    };
+    let result = if method.fallible {
+        quote!(.map_err(::win32ole::server::method_error)?)
+    } else {
+        quote!()
+    };
+    quote! {
+        Some((|| -> ::win32ole::error::Result<::win32ole::server::VARIANT> {
+            args.expect_at_most(#count)?;
+            #(#unpack)*
+            #put_value
+            let result = #borrow.#ident(#(#names),*)#result;
+            Ok(::win32ole::IntoVariant::into_variant(result))
+        })())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syn::parse_quote;
+
+    fn error(result: syn::Result<TokenStream2>) -> String {
+        match result {
+            Ok(_) => panic!("expected an error"),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(pascal_case("total"), "Total");
+        assert_eq!(pascal_case("get_item_count"), "GetItemCount");
+        assert_eq!(pascal_case("r#type"), "Type");
+        assert_eq!(pascal_case("_hidden__name"), "HiddenName");
+    }
+
+    #[test]
+    fn field_dispids() {
+        let input: DeriveInput = parse_quote! {
+            struct Account {
+                pub owner: String,
+                #[dispatch(dispid = 1)]
+                pub balance: i32,
+            }
+        };
+        assert_eq!(
+            error(derive(input)),
+            "Balance has DISPID 1, which is already used by Owner"
+        );
+
+        let input: DeriveInput = parse_quote! {
+            struct Account {
+                #[dispatch(dispid = 5)]
+                pub owner: String,
+                pub balance: i32,
+                #[dispatch(dispid = 6)]
+                pub limit: i32,
+            }
+        };
+        assert_eq!(
+            error(derive(input)),
+            "Limit has DISPID 6, which is already used by Balance"
+        );
+    }
+
+    #[test]
+    fn method_dispids() {
+        let item: ItemImpl = parse_quote! {
+            impl Account {
+                pub fn deposit(&mut self, amount: i32) {}
+                #[dispatch(dispid = 1001)]
+                pub fn withdraw(&mut self, amount: i32) {}
+            }
+        };
+        assert_eq!(
+            error(dispatch_methods(item)),
+            "Withdraw has DISPID 1001, which is already used by Deposit"
+        );
+
+        let item: ItemImpl = parse_quote! {
+            impl Account {
+                #[dispatch(get)]
+                pub fn get_total(&self) -> i32 { 0 }
+                #[dispatch(put)]
+                pub fn set_total(&mut self, total: i32) {}
+                pub fn reset(&mut self) {}
+            }
+        };
+        assert!(dispatch_methods(item).is_ok());
+    }
+}